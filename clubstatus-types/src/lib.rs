@@ -35,6 +35,20 @@ pub struct StatusAction {
     pub action: BaseAction,
     pub user: UserName,
     pub status: Status,
+    /// When the status is expected to end. The status is then changed to `revert_to`, unless a
+    /// newer status action has been stored in the meantime.
+    pub until: Option<i64>,
+    pub revert_to: Option<Status>,
+    /// The status action this one undoes. The undone action is then ignored when determining
+    /// when the status last changed.
+    pub reverts: Option<u64>,
+    /// Who is responsible while the space is open, ie. holds the key and has to lock up. Always
+    /// `None` while the space is closed.
+    pub keyholder: Option<UserName>,
+    /// The zone (eg. a room) this status applies to, `None` for the status of the whole space.
+    pub zone: Option<String>,
+    /// Shown to the public while this is the last status action, unlike `note`.
+    pub public_message: Option<String>,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
@@ -51,10 +65,59 @@ impl StatusAction {
             action: BaseAction::new_with_time(note, time),
             user,
             status,
+            until: None,
+            revert_to: None,
+            reverts: None,
+            keyholder: None,
+            zone: None,
+            public_message: None,
         }
     }
 }
 
+/// A status change that has been requested for a later time. It becomes a `StatusAction` once
+/// `at` has passed.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScheduledStatus {
+    pub id: Option<u64>,
+    /// When the change was requested.
+    pub time: i64,
+    /// When the change is to be applied.
+    pub at: i64,
+    pub note: String,
+    pub user: UserName,
+    pub status: Status,
+    pub until: Option<i64>,
+    pub revert_to: Option<Status>,
+    pub keyholder: Option<UserName>,
+    pub public_message: Option<String>,
+}
+
+impl ScheduledStatus {
+    /// The status action to store when applying the change at `time`.
+    pub fn to_action(&self, time: i64) -> StatusAction {
+        StatusAction {
+            until: self.until,
+            revert_to: self.revert_to,
+            keyholder: self.keyholder.clone(),
+            public_message: self.public_message.clone(),
+            ..StatusAction::new(self.note.clone(), time, self.user.clone(), self.status)
+        }
+    }
+}
+
+/// The key moving from one member to another while the space is open.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HandoverAction {
+    #[serde(flatten)]
+    pub action: BaseAction,
+    /// Who recorded the handover.
+    pub user: UserName,
+    /// The keyholder before the handover, `None` if nobody was named.
+    pub from: Option<UserName>,
+    pub to: UserName,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct AnnouncementAction {
     #[serde(flatten)]
@@ -66,6 +129,50 @@ pub struct AnnouncementAction {
     pub to: i64,
     pub public: bool,
     pub url: Option<Url>,
+    /// The series this announcement is an occurrence of.
+    pub series: Option<u64>,
+    /// Start of the occurrence according to the rule of the series. Stays the same when the
+    /// occurrence is moved (iCalendar `RECURRENCE-ID`).
+    pub recurrence_id: Option<i64>,
+    /// One of the configured categories, eg. `workshop`.
+    pub category: Option<String>,
+    /// Free-form keywords, eg. `soldering`.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Longer text (plain text or Markdown), `note` being the summary.
+    pub description: Option<String>,
+    /// The configured resources (eg. a room) booked for the announcement.
+    #[serde(default)]
+    pub resources: Vec<String>,
+}
+
+/// Announcements repeating according to an iCalendar RRULE. The occurrences are stored as
+/// ordinary announcements some time before they start, see `AnnouncementAction::series`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AnnouncementSeries {
+    pub id: Option<u64>,
+    /// When the series was created or last modified.
+    pub time: i64,
+    pub user: UserName,
+    pub note: String,
+    /// eg. `FREQ=WEEKLY;INTERVAL=2;BYDAY=TH`
+    pub rrule: String,
+    /// IANA name of the time zone the rule is evaluated in, eg. `Europe/Berlin`.
+    pub timezone: String,
+    /// Start of the first occurrence.
+    pub from: i64,
+    /// Length of every occurrence, in seconds.
+    pub duration: i64,
+    pub public: bool,
+    pub url: Option<Url>,
+    /// Taken over by the occurrences, see `AnnouncementAction::category`.
+    pub category: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub description: Option<String>,
+    /// Occurrences starting until this time have been stored already.
+    pub materialized_until: i64,
+    pub deleted: bool,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
@@ -76,6 +183,48 @@ pub enum AnnouncementMethod {
     Del,
 }
 
+/// Someone answering whether they attend an announcement. Only the last answer of every user
+/// counts.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RsvpAction {
+    #[serde(flatten)]
+    pub action: BaseAction,
+    pub aid: u64,
+    pub user: UserName,
+    pub answer: RsvpAnswer,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RsvpAnswer {
+    Yes,
+    No,
+    Maybe,
+}
+
+/// Number of users per answer.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RsvpCounts {
+    pub yes: u64,
+    pub no: u64,
+    pub maybe: u64,
+}
+
+impl RsvpCounts {
+    /// Expects the last answer of every user only.
+    pub fn count(rsvps: &[RsvpAction]) -> Self {
+        let mut counts = RsvpCounts::default();
+        for rsvp in rsvps {
+            match rsvp.answer {
+                RsvpAnswer::Yes => counts.yes += 1,
+                RsvpAnswer::No => counts.no += 1,
+                RsvpAnswer::Maybe => counts.maybe += 1,
+            }
+        }
+        counts
+    }
+}
+
 #[derive(Clone, Debug, Serialize)]
 pub struct PresenceAction {
     #[serde(flatten)]
@@ -125,6 +274,8 @@ pub enum TypedAction {
     Status(StatusAction),
     Announcement(AnnouncementAction),
     Presence(PresenceAction),
+    Handover(HandoverAction),
+    Rsvp(RsvpAction),
 }
 
 #[derive(Hash, PartialEq, Eq, Clone, Serialize, PartialOrd, Ord)]
@@ -21,6 +21,7 @@ pub struct PublicStatusAction {
     #[serde(flatten)]
     pub action: PublicBaseAction,
     pub status: PublicStatus,
+    pub public_message: Option<String>,
 }
 #[derive(Debug, Serialize)]
 pub struct PublicAnnouncementAction {
@@ -33,6 +34,9 @@ pub struct PublicAnnouncementAction {
 
     pub note: String,
     pub url: Option<Url>,
+    pub category: Option<String>,
+    pub tags: Vec<String>,
+    pub description: Option<String>,
 }
 
 pub trait ToPublic {
@@ -64,6 +68,7 @@ impl ToPublic for StatusAction {
         PublicStatusAction {
             action: self.action.to_public(),
             status: self.status.to_public(),
+            public_message: self.public_message.clone(),
         }
     }
 }
@@ -79,6 +84,9 @@ impl ToPublic for AnnouncementAction {
             to: self.to,
             note: self.action.note.clone(),
             url: self.url.clone(),
+            category: self.category.clone(),
+            tags: self.tags.clone(),
+            description: self.description.clone(),
         }
     }
 }
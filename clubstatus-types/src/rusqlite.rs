@@ -3,7 +3,7 @@ use rusqlite::{
     Error, ToSql,
 };
 
-use crate::{AnnouncementMethod, Status, UserName};
+use crate::{AnnouncementMethod, RsvpAnswer, Status, UserName};
 
 impl FromSql for UserName {
     fn column_result(value: ValueRef) -> FromSqlResult<UserName> {
@@ -64,3 +64,27 @@ impl ToSql for AnnouncementMethod {
         Ok(ToSqlOutput::Owned(Value::Integer(i)))
     }
 }
+
+impl FromSql for RsvpAnswer {
+    fn column_result(value: ValueRef) -> FromSqlResult<RsvpAnswer> {
+        match FromSql::column_result(value) {
+            Ok(i) => match i {
+                0 => Ok(RsvpAnswer::No),
+                1 => Ok(RsvpAnswer::Maybe),
+                2 => Ok(RsvpAnswer::Yes),
+                _ => Err(FromSqlError::Other("unknown RsvpAnswer".into())),
+            },
+            Err(e) => Err(e),
+        }
+    }
+}
+impl ToSql for RsvpAnswer {
+    fn to_sql(&self) -> Result<ToSqlOutput<'_>, Error> {
+        let i = match self {
+            RsvpAnswer::Yes => 2,
+            RsvpAnswer::Maybe => 1,
+            RsvpAnswer::No => 0,
+        };
+        Ok(ToSqlOutput::Owned(Value::Integer(i)))
+    }
+}
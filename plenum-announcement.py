@@ -29,7 +29,7 @@ def next_friday(today):
 def send_reminder_email(today):
     # friday = next_friday(today)
     thursday = next_thursday(today)
-    # TODO
+    # TODO, or let clubstatusd send them, see [reminders] in example-config.toml
     pass
 
 
@@ -1,66 +1,475 @@
+use std::collections::{BTreeMap, HashSet};
 use std::io::Cursor;
 use std::sync::{Arc, Mutex};
 
-use chrono::{TimeZone, Utc};
-use icalendar::{Calendar, Component, Event, EventLike};
+use chrono::{Datelike, Duration, NaiveDateTime, Offset, TimeZone, Utc};
+use chrono_tz::{OffsetComponents, OffsetName};
+use icalendar::{Calendar, CalendarDateTime, Component, Event, EventLike, Property};
 use rocket::State;
 use rocket::http::{self, ContentType};
 use rocket::response::{Responder, Response};
 use uuid::Uuid;
 
-use super::{Authenticated, DbCon};
-use clubstatus_types::public::ToPublic;
+use super::{AnnouncementListParams, Authenticated, DbCon, QueryTime, Settings};
+use crate::clock::SharedClock;
+use crate::db::{self, Storage};
+use clubstatus_types::{
+    AnnouncementAction, AnnouncementMethod, AnnouncementSeries, RsvpAction, RsvpAnswer, Status,
+    StatusAction,
+};
 
-#[get("/api/v0/announcement/current.ics")]
+/// At most this many status changes end up in the status history calendars.
+const STATUS_HISTORY_MAX_CHANGES: u64 = 10_000;
+
+/// Takes the same `since`/`until` window and pagination as `/api/v0/announcement/current`.
+#[get("/api/v0/announcement/current.ics?<params..>")]
 pub(super) fn announcement_current(
     _authenticated: Authenticated,
     shared_con: &State<Arc<Mutex<DbCon>>>,
+    clock: &State<SharedClock>,
+    settings: &State<Settings>,
+    params: AnnouncementListParams,
 ) -> IcsResponder {
-    let con = shared_con.lock().unwrap();
-    let actions = crate::db::announcements::get_current(&con).unwrap();
-    let ics: Calendar = actions
-        .iter()
-        .map(|a| {
-            let mut ev = Event::new();
-            event_set_uuid_from_aid(&mut ev, a.aid.unwrap());
-            ev.summary(&a.action.note);
-            ev.starts(Utc.timestamp_opt(a.from, 0).unwrap());
-            ev.ends(Utc.timestamp_opt(a.to, 0).unwrap());
-            if let Some(url) = &a.url {
-                ev.url(url.as_ref());
-            }
-            ev
-        })
+    let now = clock.now();
+    let mut con = shared_con.lock().unwrap();
+    let window = params.window(now, settings, false);
+    let actions = con.get_announcements(&window, &params.filter()).unwrap();
+    let (ics, timezones) = announcement_calendar(&mut **con, &actions, false, now);
+    IcsResponder::new(http::Status::Ok, ics).with_timezones(timezones)
+}
+
+#[get("/api/v0/announcement/current.ics?public&<params..>")]
+pub(super) fn announcement_current_public(
+    shared_con: &State<Arc<Mutex<DbCon>>>,
+    clock: &State<SharedClock>,
+    settings: &State<Settings>,
+    params: AnnouncementListParams,
+) -> IcsResponder {
+    let now = clock.now();
+    let mut con = shared_con.lock().unwrap();
+    let window = params.window(now, settings, true);
+    let actions = con.get_announcements(&window, &params.filter()).unwrap();
+    let (ics, timezones) = announcement_calendar(&mut **con, &actions, true, now);
+    IcsResponder::new(http::Status::Ok, ics).with_timezones(timezones)
+}
+
+/// The times the space has been open since `since`, which defaults to `status_history_range`
+/// before now. A period going on at `since` starts at `since`. Only periods starting before
+/// `until` are listed.
+#[get("/api/v0/status/history.ics?<since>&<until>")]
+pub(super) fn status_history(
+    _authenticated: Authenticated,
+    shared_con: &State<Arc<Mutex<DbCon>>>,
+    clock: &State<SharedClock>,
+    settings: &State<Settings>,
+    since: QueryTime,
+    until: QueryTime,
+) -> IcsResponder {
+    let now = clock.now();
+    let since = history_since(&since, settings, now);
+    let mut con = shared_con.lock().unwrap();
+    let mut changes = con
+        .get_status_changes(since, STATUS_HISTORY_MAX_CHANGES)
+        .unwrap();
+    add_running_change(&mut changes, con.get_status_change_before(since));
+    let ics: Calendar = status_events(&changes, since, until.absolute(now), false, now)
+        .into_iter()
         .collect();
     IcsResponder::new(http::Status::Ok, ics)
 }
 
-#[get("/api/v0/announcement/current.ics?public")]
-pub(super) fn announcement_current_public(shared_con: &State<Arc<Mutex<DbCon>>>) -> IcsResponder {
-    let con = shared_con.lock().unwrap();
-    let actions = crate::db::announcements::get_current_public(&con).unwrap();
-    let public = actions.iter().map(|a| a.to_public());
-    let ics: Calendar = public
-        .map(|a| {
+/// Like `status_history`, but only from changes between public and {private, closed}.
+#[get("/api/v0/status/history.ics?public&<since>&<until>")]
+pub(super) fn status_history_public(
+    shared_con: &State<Arc<Mutex<DbCon>>>,
+    clock: &State<SharedClock>,
+    settings: &State<Settings>,
+    since: QueryTime,
+    until: QueryTime,
+) -> IcsResponder {
+    let now = clock.now();
+    let since = history_since(&since, settings, now);
+    let mut con = shared_con.lock().unwrap();
+    let mut changes = con
+        .get_public_status_changes(since, STATUS_HISTORY_MAX_CHANGES)
+        .unwrap();
+    add_running_change(&mut changes, con.get_public_status_change_before(since));
+    let ics: Calendar = status_events(&changes, since, until.absolute(now), true, now)
+        .into_iter()
+        .collect();
+    IcsResponder::new(http::Status::Ok, ics)
+}
+
+fn history_since(since: &QueryTime, settings: &Settings, now: i64) -> i64 {
+    since
+        .absolute(now)
+        .or(settings.status_history_range.map(|range| now - range))
+        .unwrap_or(i64::MIN)
+}
+
+/// Appends the status change before `since` to `changes`, which the period going on at `since`
+/// started with, unless `changes` have been cut off at `STATUS_HISTORY_MAX_CHANGES` anyway.
+fn add_running_change(changes: &mut Vec<StatusAction>, running: Result<StatusAction, db::Error>) {
+    if changes.len() as u64 == STATUS_HISTORY_MAX_CHANGES {
+        return;
+    }
+    match running {
+        Ok(change) => changes.push(change),
+        Err(db::Error::NotFound) => {}
+        Err(e) => panic!("{e}"),
+    }
+}
+
+/// One event per period the space has been open, from the status change (or `since`, if later)
+/// to the next one. The period going on right now ends now. `changes` are ordered newest first.
+/// In the `public` calendar, private counts as closed and only the `public_message` is shown.
+fn status_events(
+    changes: &[StatusAction],
+    since: i64,
+    until: Option<i64>,
+    public: bool,
+    now: i64,
+) -> Vec<Event> {
+    let mut events = Vec::new();
+    let mut end = now;
+    for change in changes {
+        let start = change.action.time.max(since);
+        // nothing is left of a period that ended right at `since`
+        let clipped_away = change.action.time < since && start == end;
+        let summary = match (change.status, public) {
+            (Status::Public, true) => Some("open"),
+            (Status::Public, false) => Some("open (public)"),
+            (Status::Private, false) => Some("open (private)"),
+            _ => None,
+        };
+        if let Some(summary) =
+            summary.filter(|_| !clipped_away && until.is_none_or(|until| start < until))
+        {
             let mut ev = Event::new();
-            event_set_uuid_from_aid(&mut ev, a.aid);
-            ev.summary(&a.note);
-            ev.starts(Utc.timestamp_opt(a.from, 0).unwrap());
-            ev.ends(Utc.timestamp_opt(a.to, 0).unwrap());
-            if let Some(url) = a.url {
-                ev.url(url.as_ref());
+            ev.timestamp(Utc.timestamp_opt(now, 0).unwrap());
+            event_set_uuid(
+                &mut ev,
+                format!("status/{}", change.action.id.unwrap()).as_bytes(),
+            );
+            ev.summary(summary);
+            let description = if public {
+                change.public_message.as_deref()
+            } else {
+                Some(change.action.note.as_str())
+            };
+            if let Some(description) = description.filter(|d| !d.is_empty()) {
+                ev.description(description);
             }
+            ev.starts(Utc.timestamp_opt(start, 0).unwrap());
+            ev.ends(Utc.timestamp_opt(end, 0).unwrap());
+            events.push(ev);
+        }
+        end = start;
+    }
+    events.reverse();
+    events
+}
+
+/// The announcements booking the resource, 404 for unknown resources.
+#[get("/api/v0/resource/<name>/bookings.ics")]
+pub(super) fn resource_bookings(
+    _authenticated: Authenticated,
+    shared_con: &State<Arc<Mutex<DbCon>>>,
+    clock: &State<SharedClock>,
+    settings: &State<Settings>,
+    name: &str,
+) -> IcsResponder {
+    if !settings.resources.iter().any(|r| r.name == name) {
+        return IcsResponder::new(http::Status::NotFound, Calendar::new());
+    }
+    let now = clock.now();
+    let mut con = shared_con.lock().unwrap();
+    let ics: Calendar = db::resource_bookings(&mut **con, name, now)
+        .unwrap()
+        .iter()
+        .map(|a| {
+            let mut ev = announcement_event(a, now);
+            add_resources(&mut ev, &a.resources);
             ev
         })
         .collect();
     IcsResponder::new(http::Status::Ok, ics)
 }
 
+/// One event per announcement, except for occurrences of a series: these are combined into one
+/// event with the RRULE of the series. Deleted occurrences are listed in its EXDATE, and changed
+/// occurrences are added as events with a RECURRENCE-ID. For the `public` calendar, private
+/// occurrences count as deleted. Outside the `public` calendar, the RSVPs are added as ATTENDEEs
+/// and the booked resources as RESOURCES. Returns the VTIMEZONEs of the series separately, see
+/// `IcsResponder::with_timezones`.
+fn announcement_calendar(
+    storage: &mut dyn Storage,
+    actions: &[AnnouncementAction],
+    public: bool,
+    now: i64,
+) -> (Calendar, Vec<String>) {
+    let mut events = Vec::new();
+    let mut added_series = HashSet::new();
+    let mut timezones = BTreeMap::new();
+    for a in actions {
+        let series = a
+            .series
+            .map(|id| storage.get_announcement_series(id).unwrap())
+            .filter(|series| !series.deleted && (series.public || !public))
+            // occurrences from before the series has been modified don't follow its rule
+            .filter(|series| a.recurrence_id >= Some(series.from));
+        match series {
+            None => {
+                let mut ev = announcement_event(a, now);
+                if !public {
+                    add_attendees(&mut ev, &storage.get_rsvps(a.aid.unwrap()).unwrap());
+                    add_resources(&mut ev, &a.resources);
+                }
+                events.push(ev);
+            }
+            Some(series) => {
+                if added_series.insert(series.id) {
+                    events.extend(series_events(storage, &series, public, now));
+                    timezones
+                        .entry(series.timezone.clone())
+                        .or_insert(series.from);
+                }
+            }
+        }
+    }
+    let timezones = timezones
+        .into_iter()
+        .filter_map(|(timezone, from)| vtimezone(&timezone, from))
+        .collect();
+    (events.into_iter().collect(), timezones)
+}
+
+fn announcement_event(a: &AnnouncementAction, now: i64) -> Event {
+    let mut ev = Event::new();
+    ev.timestamp(Utc.timestamp_opt(now, 0).unwrap());
+    event_set_uuid_from_aid(&mut ev, a.aid.unwrap());
+    ev.summary(&a.action.note);
+    if let Some(description) = &a.description {
+        ev.description(description);
+    }
+    ev.starts(Utc.timestamp_opt(a.from, 0).unwrap());
+    ev.ends(Utc.timestamp_opt(a.to, 0).unwrap());
+    if let Some(url) = &a.url {
+        ev.url(url.as_ref());
+    }
+    add_categories(&mut ev, a.category.as_deref(), &a.tags);
+    ev
+}
+
+/// The category comes first, followed by the tags.
+fn add_categories(event: &mut Event, category: Option<&str>, tags: &[String]) {
+    for name in category.into_iter().chain(tags.iter().map(String::as_str)) {
+        event.append_multi_property(Property::new("CATEGORIES", name));
+    }
+}
+
+/// Which resources are booked is only shown to members.
+fn add_resources(event: &mut Event, resources: &[String]) {
+    for resource in resources {
+        event.append_multi_property(Property::new("RESOURCES", resource));
+    }
+}
+
+/// Users are identified by their name only, so the calendar address is made up from it.
+fn add_attendees(event: &mut Event, rsvps: &[RsvpAction]) {
+    for rsvp in rsvps {
+        let name: String =
+            url::form_urlencoded::byte_serialize(rsvp.user.as_str().as_bytes()).collect();
+        let partstat = match rsvp.answer {
+            RsvpAnswer::Yes => "ACCEPTED",
+            RsvpAnswer::No => "DECLINED",
+            RsvpAnswer::Maybe => "TENTATIVE",
+        };
+        event.append_multi_property(
+            Property::new("ATTENDEE", format!("urn:x-clubstatusd:user:{name}"))
+                .add_parameter("CN", rsvp.user.as_str())
+                .add_parameter("PARTSTAT", partstat)
+                .done(),
+        );
+    }
+}
+
+fn series_events(
+    storage: &mut dyn Storage,
+    series: &AnnouncementSeries,
+    public: bool,
+    now: i64,
+) -> Vec<Event> {
+    let id = series.id.unwrap();
+    let local = |time: i64| local_time(time, &series.timezone);
+    let mut master = Event::new();
+    master.timestamp(Utc.timestamp_opt(now, 0).unwrap());
+    event_set_uuid_from_series(&mut master, id);
+    master.summary(&series.note);
+    if let Some(description) = &series.description {
+        master.description(description);
+    }
+    master.starts(local(series.from));
+    master.ends(local(series.from + series.duration));
+    if let Some(url) = &series.url {
+        master.url(url.as_ref());
+    }
+    add_categories(&mut master, series.category.as_deref(), &series.tags);
+    master.add_property("RRULE", &series.rrule);
+
+    // modifying the series deletes the upcoming occurrences and stores them again, so only the
+    // newest occurrence with a recurrence_id counts
+    let mut occurrences = BTreeMap::new();
+    for occurrence in storage.get_series_announcements(id).unwrap() {
+        if let Some(recurrence_id) = occurrence.recurrence_id
+            && recurrence_id >= series.from
+        {
+            occurrences.insert(recurrence_id, occurrence);
+        }
+    }
+    let mut overrides = Vec::new();
+    for (recurrence_id, occurrence) in occurrences {
+        if occurrence.method == AnnouncementMethod::Del || (public && !occurrence.public) {
+            master.exdate(local(recurrence_id));
+            continue;
+        }
+        // occurrences with RSVPs need their own event for the ATTENDEEs
+        let rsvps = match public {
+            true => Vec::new(),
+            false => storage.get_rsvps(occurrence.aid.unwrap()).unwrap(),
+        };
+        if occurrence.method == AnnouncementMethod::Mod || !rsvps.is_empty() {
+            let mut ev = announcement_event(&occurrence, now);
+            event_set_uuid_from_series(&mut ev, id);
+            ev.recurrence_id(local(recurrence_id));
+            add_attendees(&mut ev, &rsvps);
+            if !public {
+                add_resources(&mut ev, &occurrence.resources);
+            }
+            overrides.push(ev);
+        }
+    }
+    let mut events = vec![master];
+    events.extend(overrides);
+    events
+}
+
+/// `time` in the time zone of a series, which calendar apps need to expand the RRULE correctly
+/// across daylight saving time changes.
+fn local_time(time: i64, timezone: &str) -> CalendarDateTime {
+    let utc = Utc.timestamp_opt(time, 0).unwrap();
+    match timezone.parse::<chrono_tz::Tz>() {
+        Ok(tz) if tz != chrono_tz::UTC => CalendarDateTime::WithTimezone {
+            date_time: utc.with_timezone(&tz).naive_local(),
+            tzid: String::from(timezone),
+        },
+        _ => CalendarDateTime::Utc(utc),
+    }
+}
+
+/// A VTIMEZONE for the `DTSTART;TZID=` of a series, with the observances of the year before
+/// `from`, so they start before the series. They repeat yearly, which holds as long as the rules
+/// of the time zone don't change. Returns `None` for UTC, which `local_time` doesn't use a TZID
+/// for.
+fn vtimezone(timezone: &str, from: i64) -> Option<String> {
+    let tz = match timezone.parse::<chrono_tz::Tz>() {
+        Ok(tz) if tz != chrono_tz::UTC => tz,
+        _ => return None,
+    };
+    let offset = |time: i64| tz.offset_from_utc_datetime(&utc_time(time).naive_utc());
+    let seconds = |time: i64| offset(time).fix().local_minus_utc();
+
+    let year = utc_time(from).year() - 1;
+    let start = Utc
+        .with_ymd_and_hms(year, 1, 1, 0, 0, 0)
+        .unwrap()
+        .timestamp();
+    let end = Utc
+        .with_ymd_and_hms(year + 1, 1, 1, 0, 0, 0)
+        .unwrap()
+        .timestamp();
+    let mut transitions = Vec::new();
+    let mut day = start;
+    while day < end {
+        if seconds(day) != seconds(day + 86400) {
+            // the first second with the new offset
+            let (mut before, mut after) = (day, day + 86400);
+            while after - before > 1 {
+                let middle = (before + after) / 2;
+                match seconds(middle) == seconds(day) {
+                    true => before = middle,
+                    false => after = middle,
+                }
+            }
+            transitions.push(after);
+        }
+        day += 86400;
+    }
+
+    let mut ics = format!("BEGIN:VTIMEZONE\nTZID:{timezone}\n");
+    if transitions.is_empty() {
+        transitions.push(start);
+    }
+    for &time in &transitions {
+        let (from_offset, to) = (seconds(time - 1), offset(time));
+        let kind = match to.dst_offset().is_zero() {
+            true => "STANDARD",
+            false => "DAYLIGHT",
+        };
+        // observances start at the local time before the change
+        let local = utc_time(time).naive_utc() + Duration::seconds(from_offset.into());
+        ics += &format!("BEGIN:{kind}\nDTSTART:{}\n", local.format("%Y%m%dT%H%M%S"));
+        if transitions.len() == 2 {
+            ics += &format!("RRULE:FREQ=YEARLY;{}\n", yearly_by_day(local));
+        }
+        if let Some(name) = to.abbreviation() {
+            ics += &format!("TZNAME:{name}\n");
+        }
+        ics += &format!(
+            "TZOFFSETFROM:{}\nTZOFFSETTO:{}\nEND:{kind}\n",
+            utc_offset(from_offset),
+            utc_offset(to.fix().local_minus_utc()),
+        );
+    }
+    ics += "END:VTIMEZONE\n";
+    Some(ics.replace('\n', "\r\n"))
+}
+
+/// `BYMONTH` and `BYDAY` matching the weekday of `local` in its month, eg. the last Sunday in
+/// March is `BYMONTH=3;BYDAY=-1SU`.
+fn yearly_by_day(local: NaiveDateTime) -> String {
+    let weekday =
+        ["MO", "TU", "WE", "TH", "FR", "SA", "SU"][local.weekday().num_days_from_monday() as usize];
+    let week = match (local + Duration::days(7)).month() == local.month() {
+        true => ((local.day() - 1) / 7 + 1) as i32,
+        false => -1,
+    };
+    format!("BYMONTH={};BYDAY={week}{weekday}", local.month())
+}
+
+/// `+HHMM` or `-HHMM`.
+fn utc_offset(seconds: i32) -> String {
+    let sign = if seconds < 0 { '-' } else { '+' };
+    let minutes = seconds.abs() / 60;
+    format!("{sign}{:02}{:02}", minutes / 60, minutes % 60)
+}
+
+fn utc_time(time: i64) -> chrono::DateTime<Utc> {
+    Utc.timestamp_opt(time, 0).unwrap()
+}
+
 fn event_set_uuid_from_aid(event: &mut Event, aid: u64) {
+    event_set_uuid(event, &aid.to_le_bytes());
+}
+
+fn event_set_uuid_from_series(event: &mut Event, series: u64) {
+    event_set_uuid(event, format!("series/{series}").as_bytes());
+}
+
+fn event_set_uuid(event: &mut Event, name: &[u8]) {
     let namespace_uuid = Uuid::parse_str("6fda1deb-16f7-4901-a3cb-eb65069c0db9").unwrap();
-    let aid_bytes = aid.to_le_bytes();
     event.uid(
-        Uuid::new_v5(&namespace_uuid, &aid_bytes)
+        Uuid::new_v5(&namespace_uuid, name)
             .hyphenated()
             .encode_lower(&mut Uuid::encode_buffer()),
     );
@@ -69,10 +478,22 @@ fn event_set_uuid_from_aid(event: &mut Event, aid: u64) {
 pub(super) struct IcsResponder {
     status: http::Status,
     calendar: Calendar,
+    timezones: Vec<String>,
 }
 impl IcsResponder {
     fn new(status: http::Status, calendar: Calendar) -> Self {
-        IcsResponder { status, calendar }
+        IcsResponder {
+            status,
+            calendar,
+            timezones: Vec::new(),
+        }
+    }
+
+    /// Adds VTIMEZONEs before the events. icalendar has no type for them, and would add the
+    /// DTSTAMP and UID of an event to every component.
+    fn with_timezones(mut self, timezones: Vec<String>) -> Self {
+        self.timezones = timezones;
+        self
     }
 }
 impl<'r, 'o: 'r> Responder<'r, 'o> for IcsResponder {
@@ -81,6 +502,9 @@ impl<'r, 'o: 'r> Responder<'r, 'o> for IcsResponder {
         _req: &'r rocket::Request<'_>,
     ) -> Result<rocket::Response<'o>, rocket::http::Status> {
         let mut s = self.calendar.to_string();
+        if let Some(first) = s.find("\r\nBEGIN:").or_else(|| s.find("\r\nEND:VCALENDAR")) {
+            s.insert_str(first + 2, &self.timezones.concat());
+        }
         s.push('\n'); // add trailing newline
         let mut res = Response::build();
         res.header(ContentType::new("text", "calendar; charset=utf-8"));
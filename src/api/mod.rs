@@ -1,4 +1,4 @@
-use std::cmp::{Ordering, min};
+use std::cmp::{Ordering, Reverse, min};
 use std::convert::TryInto;
 use std::fmt;
 use std::io::Cursor;
@@ -7,10 +7,10 @@ use std::num::ParseIntError;
 use std::str;
 use std::str::FromStr;
 use std::sync::mpsc::SyncSender;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, RwLock};
 
-use chrono::{Datelike, TimeZone, Utc};
-use clubstatus_types::public::{PublicAnnouncementAction, PublicStatusAction};
+use chrono::{Datelike, Local, TimeZone, Utc};
+use clubstatus_types::public::{PublicAnnouncementAction, PublicStatus, PublicStatusAction};
 use cookie::Expiration;
 use regex::Regex;
 use rocket::data::{self, Data, FromData, ToByteUnit};
@@ -30,18 +30,99 @@ use time::OffsetDateTime;
 use url::Url;
 
 use crate::api::mqtt::MqttSendQueue;
+use crate::api::spaceapi_file::{SharedSpaceapi, SpaceapiFile};
+use crate::clock::SharedClock;
 use crate::db;
 use crate::db::DbCon;
 use crate::db::DbStored;
+use crate::db::alerts::{Alert, SharedAlerts};
 use crate::model::QueryActionType;
 use crate::util::bytes_to_hex;
 use clubstatus_types::{
-    AnnouncementAction, AnnouncementMethod, BaseAction, Status, StatusAction, TypedAction,
-    UserName, public::ToPublic,
+    AnnouncementAction, AnnouncementMethod, AnnouncementSeries, BaseAction, HandoverAction,
+    PresenceAction, PresentUserStatus, RsvpAction, RsvpAnswer, RsvpCounts, ScheduledStatus, Status,
+    StatusAction, TypedAction, UserName, public::ToPublic,
 };
 
 mod ics;
 pub mod mqtt;
+pub mod spaceapi_file;
+pub mod spaceapi_schema;
+#[cfg(test)]
+mod tests;
+
+/// Optional behaviour, set in the config file.
+#[derive(Default)]
+pub struct Settings {
+    /// Static part of the SpaceAPI response, `/spaceapi` is only provided if this is set.
+    pub spaceapi_static: Option<SpaceapiStatus>,
+    /// The file `spaceapi_static` has been loaded from, it is reloaded when the file changes.
+    pub spaceapi_file: Option<SpaceapiFile>,
+    pub keyholder_check: KeyholderCheck,
+    /// Parts of the space (eg. rooms) with a status of their own.
+    pub zones: Vec<String>,
+    /// The categories announcements can be put in.
+    pub announcement_categories: Vec<String>,
+    /// At most this many announcements are listed at once, also the default page size. No
+    /// limit if `None`.
+    pub announcement_limit: Option<u64>,
+    /// How many seconds back `/api/v0/status/history.ics` goes by default. All status changes if
+    /// `None`.
+    pub status_history_range: Option<i64>,
+    /// Things (eg. a room or a machine) announcements can book.
+    pub resources: Vec<Resource>,
+    pub spaceapi_presence: SpaceapiPresence,
+    /// Fill the SpaceAPI `events`, not done if `None`.
+    pub spaceapi_events: Option<SpaceapiEvents>,
+}
+
+/// Which presence data is shown in the SpaceAPI `sensors.people_now_present`. Nothing by
+/// default, and only while the status is public, so a private status is not revealed.
+#[derive(Default)]
+pub struct SpaceapiPresence {
+    /// Show how many people are present, anonymous users are rounded.
+    pub count: bool,
+    /// Users who agreed to be listed by name while present. Only shown with `count`, as the
+    /// sensor needs the number.
+    pub public_names: Vec<UserName>,
+}
+
+/// Which public status changes and announcements are shown as SpaceAPI `events`.
+pub struct SpaceapiEvents {
+    /// Only events within this many seconds before or after now are shown.
+    pub window: i64,
+    /// At most this many events are shown, the newest ones.
+    pub count: u64,
+}
+
+/// Something announcements can book, like the lecture room or the laser cutter.
+#[derive(Deserialize)]
+pub struct Resource {
+    pub name: String,
+    #[serde(default)]
+    pub overlap: OverlapCheck,
+}
+
+/// What to do when a booking overlaps with another booking of the same resource.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OverlapCheck {
+    /// Reject the announcement with 409 Conflict.
+    #[default]
+    Refuse,
+    /// Store it anyway, but log a warning.
+    Warn,
+}
+
+/// What to do when someone other than the keyholder closes the space.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum KeyholderCheck {
+    /// Close anyway, but log a warning.
+    #[default]
+    Warn,
+    /// Reject the status change with 409 Conflict.
+    Refuse,
+}
 
 pub fn run(
     shared_con: Arc<Mutex<DbCon>>,
@@ -49,9 +130,21 @@ pub fn run(
     password: Option<String>,
     cookie_salt: Salt,
     mqtt: Option<MqttSendQueue>,
-    spaceapi_static: Option<SpaceapiStatus>,
+    mut settings: Settings,
+    clock: SharedClock,
 ) -> Rocket<Build> {
-    let presence_tracker = db::presence::start_tracker(shared_con.clone(), mqtt.as_ref());
+    let spaceapi_static: Option<SharedSpaceapi> = settings
+        .spaceapi_static
+        .take()
+        .map(|s| Arc::new(RwLock::new(s)));
+    if let (Some(file), Some(spaceapi)) = (settings.spaceapi_file.take(), &spaceapi_static) {
+        spaceapi_file::start(file, spaceapi.clone());
+    }
+
+    let presence_tracker =
+        db::presence::start_tracker(shared_con.clone(), mqtt.as_ref(), clock.clone());
+    db::schedule::start_scheduler(shared_con.clone(), mqtt.as_ref(), clock.clone());
+    db::series::start_materializer(shared_con.clone(), mqtt.as_ref(), clock.clone());
 
     let auth_secrets = password.map(|p| AuthSecrets {
         cookie: generate_cookie(&cookie_salt, p.as_str()),
@@ -68,6 +161,8 @@ pub fn run(
         .manage(auth_secrets)
         .manage(presence_tracker)
         .manage(mqtt)
+        .manage(clock)
+        .manage(settings)
         .register("/", catchers![unauthorized_catcher,])
         .mount(
             "/",
@@ -77,10 +172,20 @@ pub fn run(
                 query,
                 status_current,
                 status_current_public,
+                status_scheduled,
+                status_scheduled_cancel,
                 announcement_current,
                 announcement_current_public,
+                announcement_archive,
+                announcement_archive_public,
+                announcement_series,
+                announcement_rsvp,
+                resource_busy,
                 ics::announcement_current,
                 ics::announcement_current_public,
+                ics::resource_bookings,
+                ics::status_history,
+                ics::status_history_public,
                 all_options,
             ],
         )
@@ -98,6 +203,11 @@ pub fn run(
     rocket
 }
 
+/// Provides the alerts at `/api/v0/alerts`.
+pub fn mount_alerts(rocket: Rocket<Build>, alerts: SharedAlerts) -> Rocket<Build> {
+    rocket.manage(alerts).mount("/", routes![alerts_])
+}
+
 #[derive(Debug)]
 struct AuthSecrets {
     password: String,
@@ -117,6 +227,7 @@ impl<'r> FromRequest<'r> for Authenticated {
     type Error = &'static str;
     async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
         let cookie_jar = req.guard::<&CookieJar>().await.unwrap(); // CookieJar has Error=Infallible
+        let now = req.guard::<&State<SharedClock>>().await.unwrap().now();
         let auth_secrets = match &**req.guard::<&State<Option<AuthSecrets>>>().await.unwrap() {
             None => {
                 // authentication is disabled
@@ -128,7 +239,7 @@ impl<'r> FromRequest<'r> for Authenticated {
             && cookie.value() == auth_secrets.cookie
         {
             // set cookie again to extend lifetime
-            set_auth_cookie(cookie_jar, auth_secrets.cookie.as_str());
+            set_auth_cookie(cookie_jar, auth_secrets.cookie.as_str(), now);
             return request::Outcome::Success(Authenticated {});
         }
         let auth = req.guard::<BasicAuth>().await;
@@ -137,10 +248,10 @@ impl<'r> FromRequest<'r> for Authenticated {
             _ => "",
         };
         if basic_auth_password == auth_secrets.password {
-            set_auth_cookie(cookie_jar, auth_secrets.cookie.as_str());
+            set_auth_cookie(cookie_jar, auth_secrets.cookie.as_str(), now);
             return request::Outcome::Success(Authenticated {});
         } else {
-            clear_auth_cookie(cookie_jar);
+            clear_auth_cookie(cookie_jar, now);
             return request::Outcome::Error((
                 http::Status::Unauthorized,
                 "Auth check failed. Please perform HTTP basic auth with the correct password.",
@@ -162,9 +273,9 @@ fn generate_cookie(cookie_salt: &Salt, password: &str) -> String {
     bytes_to_hex(&key[..])
 }
 
-fn set_auth_cookie(cookie_jar: &CookieJar, cookie: &str) {
+fn set_auth_cookie(cookie_jar: &CookieJar, cookie: &str, now: i64) {
     // cookie expires in 1 to 2 years
-    let expiration_year = Utc::now().year() + 2;
+    let expiration_year = Utc.timestamp_opt(now, 0).unwrap().year() + 2;
     let expire_time_chrono = Utc
         .with_ymd_and_hms(expiration_year, 1, 1, 0, 0, 0)
         .unwrap();
@@ -175,8 +286,8 @@ fn set_auth_cookie(cookie_jar: &CookieJar, cookie: &str) {
     cookie_jar.add(cookie);
 }
 
-fn clear_auth_cookie(cookie_jar: &CookieJar) {
-    set_auth_cookie(cookie_jar, "");
+fn clear_auth_cookie(cookie_jar: &CookieJar, now: i64) {
+    set_auth_cookie(cookie_jar, "", now);
 }
 
 #[catch(401)]
@@ -210,7 +321,10 @@ fn api_versions() -> RestResponder<ApiVersions> {
 #[serde(tag = "type", rename_all = "snake_case")]
 enum ActionRequest {
     Status(StatusRequest),
+    StatusRevert(StatusRevertRequest),
+    Handover(HandoverRequest),
     Announcement(AnnouncementRequest),
+    Rsvp(RsvpRequest),
     Presence(PresenceRequest),
 }
 #[derive(Debug)]
@@ -228,8 +342,8 @@ impl<'r> FromData<'r> for ActionRequest {
         use ActionRequestError::*;
         use rocket::outcome::Outcome::*;
 
-        // Read the data into a string.
-        let string = match data.open(1024.bytes()).into_string().await {
+        // Read the data into a string. Announcement descriptions take the most space.
+        let string = match data.open(16.kibibytes()).into_string().await {
             Ok(string) if string.is_complete() => string.into_inner(),
             Ok(_) => return Error((http::Status::PayloadTooLarge, TooLarge)),
             Err(e) => return Error((http::Status::InternalServerError, Io(e))),
@@ -249,7 +363,49 @@ struct StatusRequest {
     user: UserName,
     status: Status,
     note: Note,
+    /// Apply the status change later instead of right away.
+    #[serde(default)]
+    at: Option<Time>,
+    /// Revert the status to `revert_to` (default: closed) at this time.
+    #[serde(default)]
+    until: Option<Time>,
+    #[serde(default)]
+    revert_to: Option<Status>,
+    /// Who is responsible while the space is open, defaults to `user` when opening.
+    #[serde(default)]
+    keyholder: Option<UserName>,
+    /// Set the status of this zone instead of the whole space.
+    #[serde(default)]
+    zone: Option<String>,
+    /// Shown in the public status and the SpaceAPI, unlike `note`.
+    #[serde(default)]
+    public_message: Option<Note>,
 }
+/// Undoes the last status action, eg. after a mis-tap.
+#[derive(Deserialize)]
+struct StatusRevertRequest {
+    /// Has to be the id of the last status action, so a newer change is not reverted by accident.
+    reverts: u64,
+    user: UserName,
+    note: Note,
+}
+/// Records the key moving to another member.
+#[derive(Deserialize)]
+struct HandoverRequest {
+    user: UserName,
+    to: UserName,
+    note: Note,
+}
+/// Answers whether `user` attends the announcement `aid`, replacing their previous answer.
+#[derive(Deserialize)]
+struct RsvpRequest {
+    aid: u64,
+    user: UserName,
+    answer: RsvpAnswer,
+    #[serde(default)]
+    note: Option<Note>,
+}
+/// `mod` and `del` concern either a single announcement (`aid`) or a whole series (`series`).
 #[derive(Deserialize)]
 #[serde(tag = "method", rename_all = "snake_case")]
 enum AnnouncementRequest {
@@ -260,18 +416,52 @@ enum AnnouncementRequest {
         to: Time,
         public: bool,
         url: Option<Url>,
+        /// Create a series repeating by this iCalendar RRULE, starting with `from` and `to`.
+        #[serde(default)]
+        rrule: Option<String>,
+        /// IANA time zone the rule is evaluated in, defaults to UTC.
+        #[serde(default)]
+        timezone: Option<String>,
+        #[serde(default)]
+        category: Option<String>,
+        #[serde(default)]
+        tags: Vec<String>,
+        /// Longer text, `note` being the summary.
+        #[serde(default)]
+        description: Option<Description>,
+        /// Names of the booked resources, single announcements only.
+        #[serde(default)]
+        resources: Vec<String>,
     },
     Mod {
-        aid: u64,
+        #[serde(default)]
+        aid: Option<u64>,
+        #[serde(default)]
+        series: Option<u64>,
         user: UserName,
         note: Note,
         from: Time,
         to: Time,
         public: bool,
         url: Option<Url>,
+        #[serde(default)]
+        rrule: Option<String>,
+        #[serde(default)]
+        timezone: Option<String>,
+        #[serde(default)]
+        category: Option<String>,
+        #[serde(default)]
+        tags: Vec<String>,
+        #[serde(default)]
+        description: Option<Description>,
+        #[serde(default)]
+        resources: Vec<String>,
     },
     Del {
-        aid: u64,
+        #[serde(default)]
+        aid: Option<u64>,
+        #[serde(default)]
+        series: Option<u64>,
         user: UserName,
     },
     //FIXME: validate from <= to
@@ -342,6 +532,41 @@ impl Visitor<'_> for NoteVisitor {
         Ok(Note(note))
     }
 }
+/// Longer text of an announcement, up to `DESCRIPTION_MAX` bytes.
+#[derive(Debug, PartialEq)]
+struct Description(String);
+const DESCRIPTION_MAX: usize = 8192;
+impl<'de> Deserialize<'de> for Description {
+    fn deserialize<D>(deserializer: D) -> Result<Description, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(DescriptionVisitor)
+    }
+}
+struct DescriptionVisitor;
+impl Visitor<'_> for DescriptionVisitor {
+    type Value = Description;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            formatter,
+            "Description must be UTF-8 encoded, and no longer than {DESCRIPTION_MAX} bytes."
+        )
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        if value.len() > DESCRIPTION_MAX {
+            return Err(E::custom(format!(
+                "Description cannot be longer than {DESCRIPTION_MAX} bytes."
+            )));
+        }
+        Ok(Description(String::from(value)))
+    }
+}
 #[derive(Debug)]
 pub enum Time {
     Timestamp(i64),
@@ -425,34 +650,310 @@ impl Visitor<'_> for TimeVisitor {
         }
     }
 }
-impl DbStored for StatusRequest {
-    fn store(
-        &mut self,
-        transaction: &rusqlite::Transaction<'_>,
-        mqtt: Option<&MqttSendQueue>,
-    ) -> Option<u64> {
+impl StatusRequest {
+    /// Checks that `until` comes after the status change at `at`, that a keyholder is only
+    /// named when opening, and that the zone exists. Zones only support immediate status changes.
+    fn validate(&self, now: i64, at: i64, zones: &[String]) -> Result<(), serde_json::Error> {
+        if let Some(zone) = &self.zone {
+            if !zones.contains(zone) {
+                return Err(de::Error::custom(format!("unknown zone '{zone}'")));
+            }
+            if self.at.is_some() || self.until.is_some() || self.keyholder.is_some() {
+                return Err(de::Error::custom(
+                    "at, until and keyholder can't be used together with zone",
+                ));
+            }
+        }
+        if self.status == Status::Closed && self.keyholder.is_some() {
+            return Err(de::Error::custom(
+                "keyholder can only be set together with status public or private",
+            ));
+        }
+        match &self.until {
+            None if self.revert_to.is_some() => Err(de::Error::custom(
+                "revert_to can only be used together with until",
+            )),
+            Some(until) if until.absolute(now) <= at => Err(de::Error::custom(
+                "until has to be after the time the status is changed",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn until(&self, now: i64) -> (Option<i64>, Option<Status>) {
+        let until = self.until.as_ref().map(|until| until.absolute(now));
+        (
+            until,
+            until.map(|_| self.revert_to.unwrap_or(Status::Closed)),
+        )
+    }
+
+    fn into_action(self, now: i64) -> StatusAction {
+        let (until, revert_to) = self.until(now);
         StatusAction {
             action: BaseAction {
                 id: None,
-                note: self.note.0.clone(),
-                time: Utc::now().timestamp(),
+                note: self.note.0,
+                time: now,
             },
             status: self.status,
-            user: self.user.clone(),
+            user: self.user,
+            until,
+            revert_to,
+            reverts: None,
+            keyholder: self.keyholder,
+            zone: self.zone,
+            public_message: self.public_message.map(|m| m.0),
+        }
+    }
+
+    fn into_scheduled(self, now: i64, at: i64) -> ScheduledStatus {
+        let (until, revert_to) = self.until(now);
+        ScheduledStatus {
+            id: None,
+            time: now,
+            at,
+            note: self.note.0,
+            user: self.user,
+            status: self.status,
+            until,
+            revert_to,
+            keyholder: self.keyholder,
+            public_message: self.public_message.map(|m| m.0),
+        }
+    }
+}
+impl StatusRevertRequest {
+    fn into_action(self, now: i64) -> StatusAction {
+        StatusAction {
+            reverts: Some(self.reverts),
+            // the status is restored from before the reverted action when stored
+            ..StatusAction::new(self.note.0, now, self.user, Status::Closed)
+        }
+    }
+}
+impl HandoverRequest {
+    fn into_action(self, now: i64) -> HandoverAction {
+        HandoverAction {
+            action: BaseAction::new_with_time(self.note.0, now),
+            user: self.user,
+            // set to the current keyholder when stored
+            from: None,
+            to: self.to,
         }
-        .store(transaction, mqtt)
     }
 }
-impl DbStored for AnnouncementRequest {
-    fn store(
-        &mut self,
-        transaction: &rusqlite::Transaction<'_>,
-        mqtt: Option<&MqttSendQueue>,
-    ) -> Option<u64> {
+impl RsvpRequest {
+    fn into_action(self, now: i64) -> RsvpAction {
+        RsvpAction {
+            action: BaseAction::new_with_time(self.note.map_or_else(String::new, |n| n.0), now),
+            aid: self.aid,
+            user: self.user,
+            answer: self.answer,
+        }
+    }
+}
+/// A change to a series of announcements.
+enum SeriesRequest {
+    New(AnnouncementSeries),
+    /// Replaces the series with the same id.
+    Mod(AnnouncementSeries),
+    Del(u64, UserName),
+}
+impl AnnouncementRequest {
+    /// Checks that either a single announcement or a series is concerned, that the rule of a
+    /// series is valid, and that the category and the resources exist. Only single
+    /// announcements can book resources. Returns whether a series is concerned.
+    fn validate(
+        &self,
+        now: i64,
+        categories: &[String],
+        resources: &[Resource],
+    ) -> Result<bool, serde_json::Error> {
         use AnnouncementRequest::*;
 
-        let now = Utc::now().timestamp();
-        let mut action = match self {
+        if let New {
+            category,
+            tags,
+            resources: booked,
+            ..
+        }
+        | Mod {
+            category,
+            tags,
+            resources: booked,
+            ..
+        } = self
+        {
+            if let Some(category) = category
+                && !categories.contains(category)
+            {
+                return Err(de::Error::custom(format!("unknown category '{category}'")));
+            }
+            if tags.iter().any(|tag| tag.is_empty() || tag.contains(',')) {
+                return Err(de::Error::custom("tags can't be empty or contain ','"));
+            }
+            if let Some(name) = booked
+                .iter()
+                .find(|&name| !resources.iter().any(|r| &r.name == name))
+            {
+                return Err(de::Error::custom(format!("unknown resource '{name}'")));
+            }
+        }
+
+        let (aid, series, rrule, timezone, times) = match self {
+            New {
+                rrule,
+                timezone,
+                from,
+                to,
+                ..
+            } => (None, None, rrule, timezone, Some((from, to))),
+            Mod {
+                aid,
+                series,
+                rrule,
+                timezone,
+                from,
+                to,
+                ..
+            } => (*aid, *series, rrule, timezone, Some((from, to))),
+            Del { aid, series, .. } => (*aid, *series, &None, &None, None),
+        };
+        let is_series = match (self, aid, series) {
+            (New { .. }, _, _) => rrule.is_some(),
+            (_, Some(_), None) => false,
+            (_, None, Some(_)) => true,
+            _ => {
+                return Err(de::Error::custom("either aid or series has to be set"));
+            }
+        };
+        if !is_series {
+            return match (rrule, timezone) {
+                (None, None) => Ok(false),
+                _ => Err(de::Error::custom(
+                    "rrule and timezone can only be set for a series",
+                )),
+            };
+        }
+        if let New { resources, .. } | Mod { resources, .. } = self
+            && !resources.is_empty()
+        {
+            return Err(de::Error::custom("resources can't be booked by a series"));
+        }
+        if let Some((from, to)) = times {
+            let Some(rrule) = rrule else {
+                return Err(de::Error::custom("rrule has to be set for a series"));
+            };
+            let (from, to) = (from.absolute(now), to.absolute(now));
+            if to < from {
+                return Err(de::Error::custom("to can't be before from"));
+            }
+            let timezone = timezone.as_deref().unwrap_or("UTC");
+            db::series::parse(rrule, timezone, from).map_err(de::Error::custom)?;
+        }
+        Ok(true)
+    }
+
+    /// Only for requests concerning a series, see `validate`.
+    fn into_series(self, now: i64) -> SeriesRequest {
+        use AnnouncementRequest::*;
+
+        let series = |id,
+                      user,
+                      note: Note,
+                      from: Time,
+                      to: Time,
+                      public,
+                      url,
+                      rrule: Option<String>,
+                      timezone: Option<String>,
+                      category,
+                      tags,
+                      description: Option<Description>| {
+            let from = from.absolute(now);
+            AnnouncementSeries {
+                id,
+                time: now,
+                user,
+                note: note.0,
+                rrule: rrule.unwrap(),
+                timezone: timezone.unwrap_or_else(|| String::from("UTC")),
+                from,
+                duration: to.absolute(now) - from,
+                public,
+                url,
+                category,
+                tags,
+                description: description.map(|d| d.0),
+                materialized_until: 0,
+                deleted: false,
+            }
+        };
+        match self {
+            New {
+                user,
+                note,
+                from,
+                to,
+                public,
+                url,
+                rrule,
+                timezone,
+                category,
+                tags,
+                description,
+                ..
+            } => SeriesRequest::New(series(
+                None,
+                user,
+                note,
+                from,
+                to,
+                public,
+                url,
+                rrule,
+                timezone,
+                category,
+                tags,
+                description,
+            )),
+            Mod {
+                series: id,
+                user,
+                note,
+                from,
+                to,
+                public,
+                url,
+                rrule,
+                timezone,
+                category,
+                tags,
+                description,
+                ..
+            } => SeriesRequest::Mod(series(
+                id,
+                user,
+                note,
+                from,
+                to,
+                public,
+                url,
+                rrule,
+                timezone,
+                category,
+                tags,
+                description,
+            )),
+            Del { series, user, .. } => SeriesRequest::Del(series.unwrap(), user),
+        }
+    }
+
+    fn into_action(self, now: i64) -> AnnouncementAction {
+        use AnnouncementRequest::*;
+
+        match self {
             New {
                 note,
                 from,
@@ -460,19 +961,30 @@ impl DbStored for AnnouncementRequest {
                 user,
                 public,
                 url,
+                category,
+                tags,
+                description,
+                resources,
+                ..
             } => AnnouncementAction {
                 action: BaseAction {
                     id: None,
-                    note: note.0.clone(),
+                    note: note.0,
                     time: now,
                 },
                 aid: None,
                 method: AnnouncementMethod::New,
                 from: from.absolute(now),
                 to: to.absolute(now),
-                user: user.clone(),
-                public: *public,
-                url: url.clone(),
+                user,
+                public,
+                url,
+                series: None,
+                recurrence_id: None,
+                category,
+                tags,
+                description: description.map(|d| d.0),
+                resources,
             },
             Mod {
                 aid,
@@ -482,37 +994,54 @@ impl DbStored for AnnouncementRequest {
                 user,
                 public,
                 url,
+                category,
+                tags,
+                description,
+                resources,
+                ..
             } => AnnouncementAction {
                 action: BaseAction {
                     id: None,
-                    note: note.0.clone(),
+                    note: note.0,
                     time: now,
                 },
-                aid: Some(*aid),
+                aid,
                 method: AnnouncementMethod::Mod,
                 from: from.absolute(now),
                 to: to.absolute(now),
-                user: user.clone(),
-                public: *public,
-                url: url.clone(),
+                user,
+                public,
+                url,
+                // taken from the modified announcement when stored
+                series: None,
+                recurrence_id: None,
+                category,
+                tags,
+                description: description.map(|d| d.0),
+                resources,
             },
-            Del { aid, user } => AnnouncementAction {
+            Del { aid, user, .. } => AnnouncementAction {
                 // Most of the fields will just be ignored when stored.
                 action: BaseAction {
                     id: None,
                     note: String::from(""),
                     time: now,
                 },
-                aid: Some(*aid),
+                aid,
                 method: AnnouncementMethod::Del,
                 from: 0,
                 to: 0,
-                user: user.clone(),
+                user,
                 public: false,
                 url: None,
+                series: None,
+                recurrence_id: None,
+                category: None,
+                tags: Vec::new(),
+                description: None,
+                resources: Vec::new(),
             },
-        };
-        action.store(transaction, mqtt)
+        }
     }
 }
 
@@ -520,7 +1049,10 @@ impl DbStored for AnnouncementRequest {
 #[serde(untagged)]
 enum CreateActionResponse {
     ActionCreated(u64),
+    StatusScheduled { scheduled: u64 },
+    AnnouncementSeries { series: u64 },
     PresenceRecorded,
+    Refused { error: String },
     Error,
 }
 
@@ -531,6 +1063,8 @@ fn create_action(
     shared_con: &State<Arc<Mutex<DbCon>>>,
     presence_tracker: &State<SyncSender<PresenceRequest>>,
     mqtt: &State<Option<MqttSendQueue>>,
+    clock: &State<SharedClock>,
+    settings: &State<Settings>,
     action_request: Result<ActionRequest, ActionRequestError>,
 ) -> Result<RestResponder<CreateActionResponse>, JsonErrorResponder> {
     let action_request = match action_request {
@@ -546,34 +1080,188 @@ fn create_action(
         }
     };
     match action_request {
-        ActionRequest::Status(mut action) => {
+        ActionRequest::Status(request) => {
+            let now = clock.now();
+            let at = request.at.as_ref().map_or(now, |at| at.absolute(now));
+            if let Err(err) = request.validate(now, at.max(now), &settings.zones) {
+                return Err(JsonErrorResponder::new(err));
+            }
             let mut con = shared_con.lock().unwrap();
-            let transaction = con.transaction().unwrap();
-            match action.store(&transaction, mqtt.as_ref()) {
-                Some(action_id) => {
-                    transaction.commit().unwrap();
-                    Ok(RestResponder::new(
+            if at > now {
+                let mut scheduled = request.into_scheduled(now, at);
+                return match db::schedule::schedule(&mut **con, &mut scheduled) {
+                    Ok(id) => Ok(RestResponder::new(
                         http::Status::Ok,
-                        CreateActionResponse::ActionCreated(action_id),
-                    ))
+                        CreateActionResponse::StatusScheduled { scheduled: id },
+                    )),
+                    Err(_) => Ok(RestResponder::new(
+                        http::Status::InternalServerError,
+                        CreateActionResponse::Error,
+                    )),
+                };
+            }
+            if request.status == Status::Closed
+                && request.zone.is_none()
+                && let Some(keyholder) = db::current_keyholder(&mut **con).unwrap()
+                && keyholder != request.user
+            {
+                let message = format!("{keyholder} holds the key, but {} is closing", request.user);
+                if settings.keyholder_check == KeyholderCheck::Refuse {
+                    return Ok(RestResponder::new(
+                        http::Status::Conflict,
+                        CreateActionResponse::Refused { error: message },
+                    ));
                 }
+                eprintln!("Warning: {message}");
+            }
+            let mut action = request.into_action(now);
+            match action.store(&mut **con, mqtt.as_ref()) {
+                Some(action_id) => Ok(RestResponder::new(
+                    http::Status::Ok,
+                    CreateActionResponse::ActionCreated(action_id),
+                )),
+                None => Ok(RestResponder::new(
+                    http::Status::InternalServerError,
+                    CreateActionResponse::Error,
+                )),
+            }
+        }
+        ActionRequest::StatusRevert(request) => {
+            let mut con = shared_con.lock().unwrap();
+            if let Some(error) = db::revert_refusal(&mut **con, request.reverts).unwrap() {
+                return Ok(RestResponder::new(
+                    http::Status::Conflict,
+                    CreateActionResponse::Refused { error },
+                ));
+            }
+            let mut action = request.into_action(clock.now());
+            match action.store(&mut **con, mqtt.as_ref()) {
+                Some(action_id) => Ok(RestResponder::new(
+                    http::Status::Ok,
+                    CreateActionResponse::ActionCreated(action_id),
+                )),
                 None => Ok(RestResponder::new(
                     http::Status::InternalServerError,
                     CreateActionResponse::Error,
                 )),
             }
         }
-        ActionRequest::Announcement(mut action) => {
+        ActionRequest::Handover(request) => {
+            let mut action = request.into_action(clock.now());
             let mut con = shared_con.lock().unwrap();
-            let transaction = con.transaction().unwrap();
-            match action.store(&transaction, mqtt.as_ref()) {
-                Some(action_id) => {
-                    transaction.commit().unwrap();
-                    Ok(RestResponder::new(
+            if let Some(error) = db::handover_refusal(&mut **con).unwrap() {
+                return Ok(RestResponder::new(
+                    http::Status::Conflict,
+                    CreateActionResponse::Refused { error },
+                ));
+            }
+            match action.store(&mut **con, mqtt.as_ref()) {
+                Some(action_id) => Ok(RestResponder::new(
+                    http::Status::Ok,
+                    CreateActionResponse::ActionCreated(action_id),
+                )),
+                None => Ok(RestResponder::new(
+                    http::Status::InternalServerError,
+                    CreateActionResponse::Error,
+                )),
+            }
+        }
+        ActionRequest::Announcement(request) => {
+            let now = clock.now();
+            let is_series =
+                match request.validate(now, &settings.announcement_categories, &settings.resources)
+                {
+                    Ok(is_series) => is_series,
+                    Err(err) => return Err(JsonErrorResponder::new(err)),
+                };
+            let mut con = shared_con.lock().unwrap();
+            if is_series {
+                let mqtt = mqtt.as_ref();
+                let stored = match request.into_series(now) {
+                    SeriesRequest::New(mut series) => {
+                        db::series::create(&mut **con, &mut series, mqtt, now)
+                    }
+                    SeriesRequest::Mod(mut series) => {
+                        db::series::modify(&mut **con, &mut series, mqtt, now)
+                    }
+                    SeriesRequest::Del(id, user) => {
+                        db::series::delete(&mut **con, id, user, mqtt, now)
+                    }
+                };
+                return match stored {
+                    Ok(series) => Ok(RestResponder::new(
                         http::Status::Ok,
-                        CreateActionResponse::ActionCreated(action_id),
-                    ))
+                        CreateActionResponse::AnnouncementSeries { series },
+                    )),
+                    Err(db::Error::InvalidSeries(error)) => Ok(RestResponder::new(
+                        http::Status::UnprocessableEntity,
+                        CreateActionResponse::Refused { error },
+                    )),
+                    Err(db::Error::NotFound) => Ok(RestResponder::new(
+                        http::Status::NotFound,
+                        CreateActionResponse::Refused {
+                            error: String::from("unknown or deleted series"),
+                        },
+                    )),
+                    Err(_) => Ok(RestResponder::new(
+                        http::Status::InternalServerError,
+                        CreateActionResponse::Error,
+                    )),
+                };
+            }
+            let mut action = request.into_action(now);
+            for (name, other) in db::overlapping_bookings(&mut **con, &action).unwrap() {
+                let message = format!(
+                    "{name} is already booked for '{}' (announcement {})",
+                    other.action.note,
+                    other.aid.unwrap()
+                );
+                let resource = settings.resources.iter().find(|r| r.name == name);
+                if resource.is_some_and(|r| r.overlap == OverlapCheck::Refuse) {
+                    return Ok(RestResponder::new(
+                        http::Status::Conflict,
+                        CreateActionResponse::Refused { error: message },
+                    ));
+                }
+                eprintln!("Warning: {message}");
+            }
+            match action.store(&mut **con, mqtt.as_ref()) {
+                Some(action_id) => Ok(RestResponder::new(
+                    http::Status::Ok,
+                    CreateActionResponse::ActionCreated(action_id),
+                )),
+                None => Ok(RestResponder::new(
+                    http::Status::InternalServerError,
+                    CreateActionResponse::Error,
+                )),
+            }
+        }
+        ActionRequest::Rsvp(request) => {
+            let mut action = request.into_action(clock.now());
+            let mut con = shared_con.lock().unwrap();
+            match db::rsvp_refusal(&mut **con, action.aid, action.action.time) {
+                Ok(None) => {}
+                Ok(Some(error)) => {
+                    return Ok(RestResponder::new(
+                        http::Status::Conflict,
+                        CreateActionResponse::Refused { error },
+                    ));
                 }
+                Err(db::Error::NotFound) => {
+                    return Ok(RestResponder::new(
+                        http::Status::NotFound,
+                        CreateActionResponse::Refused {
+                            error: format!("unknown or deleted announcement {}", action.aid),
+                        },
+                    ));
+                }
+                Err(e) => panic!("{e}"),
+            }
+            match action.store(&mut **con, mqtt.as_ref()) {
+                Some(action_id) => Ok(RestResponder::new(
+                    http::Status::Ok,
+                    CreateActionResponse::ActionCreated(action_id),
+                )),
                 None => Ok(RestResponder::new(
                     http::Status::InternalServerError,
                     CreateActionResponse::Error,
@@ -590,37 +1278,108 @@ fn create_action(
     }
 }
 
-#[get("/api/v0/status/current")]
+/// `None` for zones without any status action yet.
+fn zone_status(result: Result<StatusAction, db::Error>) -> Option<StatusAction> {
+    match result {
+        Ok(status) => Some(status),
+        Err(db::Error::NotFound) => None,
+        Err(e) => panic!("{e}"),
+    }
+}
+/// Returns `null` with 404 for unknown zones.
+#[get("/api/v0/status/current?<zone>")]
 fn status_current(
     _authenticated: Authenticated,
     shared_con: &State<Arc<Mutex<DbCon>>>,
-) -> RestResponder<StatusCurrent> {
-    let con = shared_con.lock().unwrap();
-    let last = db::status::get_last(&con).unwrap();
-    let changed = db::status::get_last_changed(&con).unwrap();
-    let status_current = StatusCurrent { last, changed };
-    RestResponder::new(http::Status::Ok, status_current)
-}
-#[get("/api/v0/status/current?public")]
+    settings: &State<Settings>,
+    zone: Option<&str>,
+) -> RestResponder<Option<StatusCurrent>> {
+    if zone.is_some_and(|zone| !settings.zones.iter().any(|z| z == zone)) {
+        return RestResponder::new(http::Status::NotFound, None);
+    }
+    let mut con = shared_con.lock().unwrap();
+    let last = zone_status(con.get_last_zone_status(zone));
+    let changed = zone_status(con.get_last_changed_zone_status(zone));
+    let keyholder = match zone {
+        None => db::current_keyholder(&mut **con).unwrap(),
+        Some(_) => None,
+    };
+    let status_current = StatusCurrent {
+        last,
+        changed,
+        keyholder,
+    };
+    RestResponder::new(http::Status::Ok, Some(status_current))
+}
+#[get("/api/v0/status/current?public&<zone>")]
 fn status_current_public(
     shared_con: &State<Arc<Mutex<DbCon>>>,
-) -> RestResponder<StatusCurrentPublic> {
-    let con = shared_con.lock().unwrap();
+    settings: &State<Settings>,
+    zone: Option<&str>,
+) -> RestResponder<Option<StatusCurrentPublic>> {
+    if zone.is_some_and(|zone| !settings.zones.iter().any(|z| z == zone)) {
+        return RestResponder::new(http::Status::NotFound, None);
+    }
+    let mut con = shared_con.lock().unwrap();
 
-    let changed = db::status::get_last_changed_public(&con)
-        .unwrap()
-        .to_public();
-    let status_current = StatusCurrentPublic { changed };
-    RestResponder::new(http::Status::Ok, status_current)
+    let changed = zone_status(con.get_last_changed_public_zone_status(zone)).map(|s| s.to_public());
+    let last = zone_status(con.get_last_zone_status(zone));
+    let status_current = StatusCurrentPublic {
+        changed,
+        until: last.as_ref().and_then(public_until),
+        public_message: last.and_then(|last| last.public_message),
+    };
+    RestResponder::new(http::Status::Ok, Some(status_current))
+}
+#[derive(Serialize)]
+struct StatusScheduled {
+    scheduled: Vec<ScheduledStatus>,
+}
+#[get("/api/v0/status/scheduled")]
+fn status_scheduled(
+    _authenticated: Authenticated,
+    shared_con: &State<Arc<Mutex<DbCon>>>,
+) -> RestResponder<StatusScheduled> {
+    let mut con = shared_con.lock().unwrap();
+    let scheduled = con.get_scheduled_statuses().unwrap();
+    RestResponder::new(http::Status::Ok, StatusScheduled { scheduled })
+}
+/// Returns the id of the cancelled status change, or `null` if there is none with this id.
+#[delete("/api/v0/status/scheduled/<id>")]
+fn status_scheduled_cancel(
+    _authenticated: Authenticated,
+    shared_con: &State<Arc<Mutex<DbCon>>>,
+    id: u64,
+) -> RestResponder<Option<u64>> {
+    let mut con = shared_con.lock().unwrap();
+    if con.delete_scheduled_status(id).unwrap() {
+        println!("Cancelled scheduled status change {id}");
+        RestResponder::new(http::Status::Ok, Some(id))
+    } else {
+        RestResponder::new(http::Status::NotFound, None)
+    }
 }
 #[derive(Serialize)]
 struct StatusCurrent {
-    last: StatusAction,
-    changed: StatusAction,
+    /// `last` and `changed` are only `None` for zones without any status action yet.
+    last: Option<StatusAction>,
+    changed: Option<StatusAction>,
+    /// Differs from `last.keyholder` if the key has been handed over since.
+    keyholder: Option<UserName>,
 }
 #[derive(Serialize)]
 struct StatusCurrentPublic {
-    changed: PublicStatusAction,
+    changed: Option<PublicStatusAction>,
+    until: Option<i64>,
+    /// `public_message` of the last status, which may be newer than `changed`.
+    public_message: Option<String>,
+}
+/// `until` of the last status, unless that would reveal a private status.
+fn public_until(last: &StatusAction) -> Option<i64> {
+    match last.status {
+        Status::Public => last.until,
+        _ => None,
+    }
 }
 
 /**
@@ -670,37 +1429,173 @@ impl<'r, 'o: 'r> Responder<'r, 'o> for JsonErrorResponder {
     }
 }
 
+/// An announcement together with the number of users per RSVP answer.
+#[derive(Serialize)]
+struct WithRsvp<A> {
+    #[serde(flatten)]
+    announcement: A,
+    rsvp: RsvpCounts,
+}
+fn with_rsvp<A>(storage: &mut dyn db::Storage, aid: u64, announcement: A) -> WithRsvp<A> {
+    let rsvps = storage.get_rsvps(aid).unwrap();
+    WithRsvp {
+        announcement,
+        rsvp: RsvpCounts::count(&rsvps),
+    }
+}
 #[derive(Serialize)]
 #[serde(rename_all = "snake_case")]
-struct CurrentAnnouncements {
-    actions: Vec<AnnouncementAction>,
+struct AnnouncementList {
+    actions: Vec<WithRsvp<AnnouncementAction>>,
 }
-#[get("/api/v0/announcement/current")]
+fn announcement_list(
+    storage: &mut dyn db::Storage,
+    window: &db::AnnouncementWindow,
+    filter: &AnnouncementFilter,
+) -> AnnouncementList {
+    let actions = storage
+        .get_announcements(window, filter)
+        .unwrap()
+        .into_iter()
+        .map(|a| with_rsvp(storage, a.aid.unwrap(), a))
+        .collect();
+    AnnouncementList { actions }
+}
+#[get("/api/v0/announcement/current?<params..>")]
 fn announcement_current(
     _authenticated: Authenticated,
     shared_con: &State<Arc<Mutex<DbCon>>>,
-) -> RestResponder<CurrentAnnouncements> {
-    let con = shared_con.lock().unwrap();
-    let actions = db::announcements::get_current(&con).unwrap();
-    let r = CurrentAnnouncements { actions };
+    clock: &State<SharedClock>,
+    settings: &State<Settings>,
+    params: AnnouncementListParams,
+) -> RestResponder<AnnouncementList> {
+    let mut con = shared_con.lock().unwrap();
+    let window = params.window(clock.now(), settings, false);
+    let r = announcement_list(&mut **con, &window, &params.filter());
+    RestResponder::new(http::Status::Ok, r)
+}
+/// Announcements that have ended, newest first.
+#[get("/api/v0/announcement/archive?<params..>")]
+fn announcement_archive(
+    _authenticated: Authenticated,
+    shared_con: &State<Arc<Mutex<DbCon>>>,
+    clock: &State<SharedClock>,
+    settings: &State<Settings>,
+    params: AnnouncementListParams,
+) -> RestResponder<AnnouncementList> {
+    let mut con = shared_con.lock().unwrap();
+    let window = params.archive_window(clock.now(), settings, false);
+    let r = announcement_list(&mut **con, &window, &params.filter());
     RestResponder::new(http::Status::Ok, r)
 }
 #[derive(Serialize)]
-#[serde(rename_all = "snake_case")]
-struct CurrentPublicAnnouncements {
-    actions: Vec<PublicAnnouncementAction>,
+struct AnnouncementRsvps {
+    rsvps: Vec<RsvpAction>,
+    counts: RsvpCounts,
 }
-#[get("/api/v0/announcement/current?public")]
-fn announcement_current_public(
+/// The last answer of every user. Returns `null` with 404 for unknown announcements.
+#[get("/api/v0/announcement/<aid>/rsvp")]
+fn announcement_rsvp(
+    _authenticated: Authenticated,
+    shared_con: &State<Arc<Mutex<DbCon>>>,
+    aid: u64,
+) -> RestResponder<Option<AnnouncementRsvps>> {
+    let mut con = shared_con.lock().unwrap();
+    if con.get_last_announcement(aid).unwrap().is_none() {
+        return RestResponder::new(http::Status::NotFound, None);
+    }
+    let rsvps = con.get_rsvps(aid).unwrap();
+    let counts = RsvpCounts::count(&rsvps);
+    RestResponder::new(http::Status::Ok, Some(AnnouncementRsvps { rsvps, counts }))
+}
+#[derive(Serialize)]
+struct Busy {
+    aid: u64,
+    from: i64,
+    to: i64,
+}
+#[derive(Serialize)]
+struct ResourceBusy {
+    busy: Vec<Busy>,
+}
+/// When the resource is booked, from now on. Returns `null` with 404 for unknown resources.
+#[get("/api/v0/resource/<name>/busy")]
+fn resource_busy(
+    _authenticated: Authenticated,
+    shared_con: &State<Arc<Mutex<DbCon>>>,
+    clock: &State<SharedClock>,
+    settings: &State<Settings>,
+    name: &str,
+) -> RestResponder<Option<ResourceBusy>> {
+    if !settings.resources.iter().any(|r| r.name == name) {
+        return RestResponder::new(http::Status::NotFound, None);
+    }
+    let mut con = shared_con.lock().unwrap();
+    let busy = db::resource_bookings(&mut **con, name, clock.now())
+        .unwrap()
+        .into_iter()
+        .map(|a| Busy {
+            aid: a.aid.unwrap(),
+            from: a.from,
+            to: a.to,
+        })
+        .collect();
+    RestResponder::new(http::Status::Ok, Some(ResourceBusy { busy }))
+}
+#[derive(Serialize)]
+struct AnnouncementSeriesList {
+    series: Vec<AnnouncementSeries>,
+}
+/// Series of announcements that have not been deleted.
+#[get("/api/v0/announcement/series")]
+fn announcement_series(
+    _authenticated: Authenticated,
     shared_con: &State<Arc<Mutex<DbCon>>>,
-) -> RestResponder<CurrentPublicAnnouncements> {
-    let con = shared_con.lock().unwrap();
-    let actions = db::announcements::get_current_public(&con)
+) -> RestResponder<AnnouncementSeriesList> {
+    let mut con = shared_con.lock().unwrap();
+    let series = con.get_active_announcement_series().unwrap();
+    RestResponder::new(http::Status::Ok, AnnouncementSeriesList { series })
+}
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+struct PublicAnnouncementList {
+    actions: Vec<WithRsvp<PublicAnnouncementAction>>,
+}
+fn public_announcement_list(
+    storage: &mut dyn db::Storage,
+    window: &db::AnnouncementWindow,
+    filter: &AnnouncementFilter,
+) -> PublicAnnouncementList {
+    let actions = storage
+        .get_announcements(window, filter)
         .unwrap()
         .iter()
-        .map(|a| a.to_public())
+        .map(|a| with_rsvp(storage, a.aid.unwrap(), a.to_public()))
         .collect();
-    let r = CurrentPublicAnnouncements { actions };
+    PublicAnnouncementList { actions }
+}
+#[get("/api/v0/announcement/current?public&<params..>")]
+fn announcement_current_public(
+    shared_con: &State<Arc<Mutex<DbCon>>>,
+    clock: &State<SharedClock>,
+    settings: &State<Settings>,
+    params: AnnouncementListParams,
+) -> RestResponder<PublicAnnouncementList> {
+    let mut con = shared_con.lock().unwrap();
+    let window = params.window(clock.now(), settings, true);
+    let r = public_announcement_list(&mut **con, &window, &params.filter());
+    RestResponder::new(http::Status::Ok, r)
+}
+#[get("/api/v0/announcement/archive?public&<params..>")]
+fn announcement_archive_public(
+    shared_con: &State<Arc<Mutex<DbCon>>>,
+    clock: &State<SharedClock>,
+    settings: &State<Settings>,
+    params: AnnouncementListParams,
+) -> RestResponder<PublicAnnouncementList> {
+    let mut con = shared_con.lock().unwrap();
+    let window = params.archive_window(clock.now(), settings, true);
+    let r = public_announcement_list(&mut **con, &window, &params.filter());
     RestResponder::new(http::Status::Ok, r)
 }
 
@@ -740,6 +1635,27 @@ impl<T: FromStr + PartialOrd> FromStr for RangeExpr<T> {
         })
     }
 }
+/// An optional `Time` in a query string. Unlike with `Option<Time>`, invalid values are rejected
+/// instead of being ignored.
+struct QueryTime(Option<Time>);
+impl QueryTime {
+    fn absolute(&self, now: i64) -> Option<i64> {
+        self.0.as_ref().map(|t| t.absolute(now))
+    }
+}
+#[rocket::async_trait]
+impl<'r> FromFormField<'r> for QueryTime {
+    fn from_value(field: ValueField<'r>) -> form::Result<'r, Self> {
+        let time = TimeVisitor
+            .visit_str::<de::value::Error>(field.value)
+            .map_err(|e| form::Error::validation(e.to_string()))?;
+        Ok(QueryTime(Some(time)))
+    }
+
+    fn default() -> Option<Self> {
+        Some(QueryTime(None))
+    }
+}
 #[rocket::async_trait]
 impl<'r> FromFormField<'r> for RangeExpr<IdExpr> {
     fn from_value(field: ValueField<'r>) -> form::Result<'r, Self> {
@@ -802,6 +1718,63 @@ impl<'r> FromFormField<'r> for Take {
     }
 }
 
+/// Restricts a list of announcements to a category and a tag, eg. `?category=workshop`.
+#[derive(Debug, Default, FromForm)]
+pub struct AnnouncementFilter {
+    pub category: Option<String>,
+    pub tag: Option<String>,
+}
+
+/// A page of announcements within a time window, eg.
+/// `?since=now-604800&until=now+604800&offset=30`. Also takes the `AnnouncementFilter`.
+#[derive(FromForm)]
+struct AnnouncementListParams {
+    since: QueryTime,
+    until: QueryTime,
+    #[field(default = 0)]
+    offset: u64,
+    count: Option<u64>,
+    category: Option<String>,
+    tag: Option<String>,
+}
+impl AnnouncementListParams {
+    /// Announcements that have not ended before `since`, which defaults to now. `count` defaults
+    /// to, and is capped at, the configured `announcement_limit`.
+    fn window(&self, now: i64, settings: &Settings, public: bool) -> db::AnnouncementWindow {
+        let limit = match (self.count, settings.announcement_limit) {
+            (Some(count), Some(limit)) => Some(min(count, limit)),
+            (count, limit) => count.or(limit),
+        };
+        db::AnnouncementWindow {
+            since: Some(self.since.absolute(now).unwrap_or(now)),
+            until: self.until.absolute(now),
+            ended_before: None,
+            public,
+            offset: self.offset,
+            limit,
+        }
+    }
+    /// Like `window`, but announcements that ended before now. `since` has no default here.
+    fn archive_window(
+        &self,
+        now: i64,
+        settings: &Settings,
+        public: bool,
+    ) -> db::AnnouncementWindow {
+        db::AnnouncementWindow {
+            since: self.since.absolute(now),
+            ended_before: Some(now),
+            ..self.window(now, settings, public)
+        }
+    }
+    fn filter(&self) -> AnnouncementFilter {
+        AnnouncementFilter {
+            category: self.category.clone(),
+            tag: self.tag.clone(),
+        }
+    }
+}
+
 #[derive(FromForm)]
 struct QueryParams {
     #[field(default = RangeExpr::range(IdExpr::Int(0), IdExpr::Last))]
@@ -812,6 +1785,8 @@ struct QueryParams {
     count: u64,
     #[field(default = Take::Last)]
     take: Take,
+    category: Option<String>,
+    tag: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -832,34 +1807,190 @@ fn query(
         time,
         count,
         take,
+        category,
+        tag,
     } = params;
 
     let count: u64 = min(count, 100);
     let count = if id.is_single() { 1 } else { count };
 
     let mut con = shared_con.lock().unwrap();
-    let actions = db::query(r#type, id, time, count, take, &mut con).unwrap();
+    let filter = AnnouncementFilter { category, tag };
+    let actions = con.query(r#type, id, time, count, take, &filter).unwrap();
 
     RestResponder::new(http::Status::Ok, QueryResponse { actions })
 }
 
-#[get("/spaceapi")]
-fn spaceapi_(
-    shared_con: &State<Arc<Mutex<DbCon>>>,
-    spaceapi_static: &State<SpaceapiStatus>,
-) -> RestResponder<SpaceapiStatus> {
-    let changed_action = {
-        let con = shared_con.lock().unwrap();
-        db::status::get_last_changed_public(&con).unwrap()
+/// "open until 22:00" in local time, including the date if `until` is not on the same day as
+/// `now`.
+fn open_until_message(until: i64, now: i64) -> String {
+    let until = Local.timestamp_opt(until, 0).unwrap();
+    let format = if until.date_naive() == Local.timestamp_opt(now, 0).unwrap().date_naive() {
+        "%H:%M"
+    } else {
+        "%Y-%m-%d %H:%M"
     };
+    format!("open until {}", until.format(format))
+}
+
+fn people_now_present(
+    presence: &PresenceAction,
+    settings: &SpaceapiPresence,
+) -> spaceapi::sensors::PeopleNowPresentSensor {
+    let present: Vec<&UserName> = presence
+        .users
+        .iter()
+        .filter(|u| u.status != PresentUserStatus::Left)
+        .map(|u| &u.name)
+        .collect();
+    let mut names: Vec<String> = present
+        .iter()
+        .filter(|name| settings.public_names.contains(name))
+        .map(|name| name.to_string())
+        .collect();
+    names.sort_unstable();
+    spaceapi::sensors::PeopleNowPresentSensor {
+        value: present.len() as u64 + presence.anonymous_users.round() as u64,
+        names: (!names.is_empty()).then_some(names),
+        ..Default::default()
+    }
+}
+
+/// Public status changes and public announcements (by their start) within the window, newest
+/// first. Only what the public API shows is used.
+fn spaceapi_events(
+    storage: &mut dyn db::Storage,
+    settings: &SpaceapiEvents,
+    now: i64,
+) -> Vec<spaceapi::Event> {
+    let window = (now - settings.window)..=(now + settings.window);
+    let mut events: Vec<spaceapi::Event> = storage
+        .get_public_status_changes(*window.start(), settings.count)
+        .unwrap()
+        .iter()
+        .map(|s| s.to_public())
+        .map(|s| spaceapi::Event {
+            name: String::from("status"),
+            type_: String::from(match s.status {
+                PublicStatus::Public => "open",
+                PublicStatus::Closed => "closed",
+            }),
+            timestamp: s.action.time as u64,
+            extra: s.public_message,
+        })
+        .collect();
+    let announcements = storage.get_current_public_announcements(now).unwrap();
+    let announcements = announcements
+        .iter()
+        .filter(|a| window.contains(&a.from))
+        .map(|a| a.to_public())
+        .map(|a| spaceapi::Event {
+            name: a.note,
+            type_: String::from("announcement"),
+            timestamp: a.from as u64,
+            extra: a.url.map(String::from),
+        });
+    events.extend(announcements);
+    events.sort_by_key(|e| Reverse(e.timestamp));
+    events.truncate(settings.count as usize);
+    events
+}
+
+/// The public message of the last status, followed by "open until 22:00" if `until` is set.
+fn spaceapi_message(last: &StatusAction, now: i64) -> Option<String> {
+    let until = public_until(last).map(|until| open_until_message(until, now));
+    match (&last.public_message, until) {
+        (Some(message), Some(until)) => Some(format!("{message} ({until})")),
+        (Some(message), None) => Some(message.clone()),
+        (None, until) => until,
+    }
+}
+
+#[derive(Serialize)]
+struct ActiveAlerts {
+    alerts: Vec<Alert>,
+}
+#[get("/api/v0/alerts")]
+fn alerts_(
+    _authenticated: Authenticated,
+    alerts: &State<SharedAlerts>,
+) -> RestResponder<ActiveAlerts> {
+    let alerts = alerts.lock().unwrap().active().to_vec();
+    RestResponder::new(http::Status::Ok, ActiveAlerts { alerts })
+}
+
+/// The SpaceAPI response: the static part from the config, filled in with the current status.
+fn spaceapi_status(
+    storage: &mut dyn db::Storage,
+    spaceapi_static: &SpaceapiStatus,
+    settings: &Settings,
+    now: i64,
+) -> SpaceapiStatus {
+    let zones: serde_json::Map<_, _> = settings
+        .zones
+        .iter()
+        .map(|zone| {
+            let changed = zone_status(storage.get_last_changed_public_zone_status(Some(zone)));
+            let state = serde_json::json!({
+                "open": changed.as_ref().is_some_and(|c| c.status == Status::Public),
+                "lastchange": changed.map(|c| c.action.time),
+            });
+            (zone.clone(), state)
+        })
+        .collect();
+    let changed_action = storage.get_last_changed_public_status().unwrap();
+    let last_action = storage.get_last_status().unwrap();
 
-    let mut status = spaceapi_static.inner().clone();
+    let mut status = spaceapi_static.clone();
     status.state = Some(spaceapi::State {
         open: Some(changed_action.status == Status::Public),
         lastchange: Some(changed_action.action.time.try_into().unwrap()),
+        message: spaceapi_message(&last_action, now),
         ..Default::default()
     });
+    if let Some(events) = &settings.spaceapi_events {
+        status.events = Some(spaceapi_events(storage, events, now));
+    }
+    if settings.spaceapi_presence.count && changed_action.status == Status::Public {
+        let presence = storage.get_last_presence().unwrap();
+        // replaces a sensor from the static data, which can't know who is present
+        status
+            .sensors
+            .get_or_insert_with(Default::default)
+            .people_now_present = vec![people_now_present(&presence, &settings.spaceapi_presence)];
+    }
+    if !zones.is_empty() {
+        status
+            .extensions
+            .insert(String::from("ext_zones"), zones.into());
+    }
+    status
+}
+
+/// Checks the SpaceAPI response with the current data against the schemas of the versions in
+/// `api_compatibility`, so mistakes in the config are found at startup.
+pub fn check_spaceapi(
+    storage: &mut dyn db::Storage,
+    settings: &Settings,
+    now: i64,
+) -> Result<(), Vec<String>> {
+    let Some(spaceapi_static) = &settings.spaceapi_static else {
+        return Ok(());
+    };
+    let status = spaceapi_status(storage, spaceapi_static, settings, now);
+    spaceapi_schema::validate(&serde_json::to_value(status).unwrap())
+}
 
+#[get("/spaceapi")]
+fn spaceapi_(
+    shared_con: &State<Arc<Mutex<DbCon>>>,
+    spaceapi_static: &State<SharedSpaceapi>,
+    settings: &State<Settings>,
+    clock: &State<SharedClock>,
+) -> RestResponder<SpaceapiStatus> {
+    let spaceapi_static = spaceapi_static.read().unwrap().clone();
+    let mut con = shared_con.lock().unwrap();
+    let status = spaceapi_status(&mut **con, &spaceapi_static, settings, clock.now());
     RestResponder::new(http::Status::Ok, status)
 }
 
@@ -873,6 +2004,7 @@ fn all_options() {
 mod test {
     use super::*;
     use crate::hex_str_to_salt;
+    use clubstatus_types::PresentNamedUser;
 
     #[test]
     /// Make sure we do not inadvertantly log people out by changing the cookie generation
@@ -904,4 +2036,50 @@ mod test {
             None
         );
     }
+
+    #[test]
+    fn test_description_deserialize() {
+        // two bytes per character
+        let description = "ö".repeat(DESCRIPTION_MAX / 2);
+        assert_eq!(
+            serde_json::from_value::<Description>(description.clone().into()).ok(),
+            Some(Description(description.clone()))
+        );
+        assert!(serde_json::from_value::<Description>((description + "!").into()).is_err());
+    }
+
+    #[test]
+    fn test_people_now_present() {
+        let user = |name: &str, status| PresentNamedUser {
+            name: UserName::new(name.into()),
+            since: 0,
+            status,
+        };
+        let presence = PresenceAction::new_with_time(
+            String::new(),
+            0,
+            vec![
+                user("Hans Acker", PresentUserStatus::Present),
+                user("Frank Nord", PresentUserStatus::Joined),
+                user("Gabi Süd", PresentUserStatus::Present),
+                user("Lotte West", PresentUserStatus::Left),
+            ],
+            1.6,
+        );
+        let settings = SpaceapiPresence {
+            count: true,
+            public_names: ["Lotte West", "Hans Acker", "Frank Nord"]
+                .map(|n| UserName::new(n.into()))
+                .to_vec(),
+        };
+        let sensor = people_now_present(&presence, &settings);
+        assert_eq!(sensor.value, 5);
+        assert_eq!(
+            sensor.names,
+            Some(vec![String::from("Frank Nord"), String::from("Hans Acker")])
+        );
+
+        let sensor = people_now_present(&presence, &SpaceapiPresence::default());
+        assert_eq!((sensor.value, sensor.names), (5, None));
+    }
 }
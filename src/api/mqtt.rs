@@ -7,9 +7,21 @@ use std::time::Duration;
 use rumqttc::{Client, Event, Incoming, MqttOptions, Outgoing, QoS};
 use uuid::Uuid;
 
-use crate::db::{DbCon, status};
+use crate::clock::SharedClock;
+use crate::db::alerts::AlertChange;
+use crate::db::announcement_events::AnnouncementEvent;
+use crate::db::door::SharedDoor;
+use crate::db::{self, DbCon};
 use clubstatus_types::*;
 
+fn status_payload(status: Status) -> &'static str {
+    match status {
+        Status::Public => "public",
+        Status::Private => "private",
+        Status::Closed => "closed",
+    }
+}
+
 fn publish_status(
     action: &StatusAction,
     typed_action: &TypedAction,
@@ -17,11 +29,7 @@ fn publish_status(
     topic_prefix: &str,
 ) {
     {
-        let payload = match action.status {
-            Status::Public => "public",
-            Status::Private => "private",
-            Status::Closed => "closed",
-        };
+        let payload = status_payload(action.status);
         mqtt_client
             .publish(
                 format!("{}status", topic_prefix).as_str(),
@@ -42,6 +50,35 @@ fn publish_status(
             )
             .unwrap();
     }
+    publish_keyholder(action.keyholder.as_ref(), mqtt_client, topic_prefix);
+}
+
+fn publish_zone_status(
+    zone: &str,
+    action: &StatusAction,
+    mqtt_client: &mut Client,
+    topic_prefix: &str,
+) {
+    mqtt_client
+        .publish(
+            format!("{}zone/{}/status", topic_prefix, zone).as_str(),
+            QoS::AtLeastOnce,
+            true,
+            status_payload(action.status),
+        )
+        .unwrap();
+}
+
+/// The name of the keyholder, or an empty payload while the space is closed.
+fn publish_keyholder(keyholder: Option<&UserName>, mqtt_client: &mut Client, topic_prefix: &str) {
+    mqtt_client
+        .publish(
+            format!("{}keyholder", topic_prefix).as_str(),
+            QoS::AtLeastOnce,
+            true,
+            keyholder.map_or("", |k| k.as_str()),
+        )
+        .unwrap();
 }
 
 fn publish_announcement(
@@ -61,6 +98,54 @@ fn publish_announcement(
         .unwrap();
 }
 
+fn publish_rsvp(
+    action: &RsvpAction,
+    typed_action: &TypedAction,
+    mqtt_client: &mut Client,
+    topic_prefix: &str,
+) {
+    mqtt_client
+        .publish(
+            format!("{}announcement/{}/rsvp", topic_prefix, action.aid).as_str(),
+            QoS::AtLeastOnce,
+            false,
+            serde_json::to_string(typed_action).unwrap().as_bytes(),
+        )
+        .unwrap();
+}
+
+/// `announcement/current` is retained, the other topics are not.
+fn publish_announcement_event(event: &AnnouncementEvent, client: &mut Client, topic_prefix: &str) {
+    let single = |topic: String, action: &AnnouncementAction| {
+        let typed_action = TypedAction::Announcement(action.clone());
+        (
+            format!(
+                "{}announcement/{}/{}",
+                topic_prefix,
+                action.aid.unwrap(),
+                topic
+            ),
+            false,
+            serde_json::to_string(&typed_action).unwrap(),
+        )
+    };
+    let (topic, retain, payload) = match event {
+        AnnouncementEvent::Started(action) => single(String::from("started"), action),
+        AnnouncementEvent::Ended(action) => single(String::from("ended"), action),
+        AnnouncementEvent::StartsIn(seconds, action) => {
+            single(format!("starts_in/{seconds}"), action)
+        }
+        AnnouncementEvent::Running(actions) => (
+            format!("{}announcement/current", topic_prefix),
+            true,
+            serde_json::json!({ "actions": actions }).to_string(),
+        ),
+    };
+    client
+        .publish(topic.as_str(), QoS::AtLeastOnce, retain, payload)
+        .unwrap();
+}
+
 fn publish_presence(action: &PresenceAction, client: &mut Client, topic_prefix: &str) {
     let mut users: Vec<Cow<str>> = action
         .users
@@ -120,13 +205,51 @@ fn publish_presence(action: &PresenceAction, client: &mut Client, topic_prefix:
         .unwrap();
 }
 
+/// Raised alerts are retained until they are cleared.
+fn publish_alert(change: &AlertChange, client: &mut Client, topic_prefix: &str) {
+    let (name, payload) = match change {
+        AlertChange::Raised(alert) => (alert.name, serde_json::to_string(alert).unwrap()),
+        AlertChange::Cleared(name) => (*name, String::new()),
+    };
+    client
+        .publish(
+            format!("{}alert/{}", topic_prefix, name).as_str(),
+            QoS::AtLeastOnce,
+            true,
+            payload,
+        )
+        .unwrap();
+}
+
+/// Subscribes to the door sensor's topics on every (re)connect, and passes its messages on.
+fn handle_door_event(event: &Event, door: &SharedDoor, clock: &SharedClock, client: &Client) {
+    match event {
+        Event::Incoming(Incoming::ConnAck(_)) => {
+            for topic in door.lock().unwrap().topics() {
+                if let Err(err) = client.try_subscribe(topic.as_str(), QoS::AtLeastOnce) {
+                    eprintln!("Error subscribing to {topic}: {err}");
+                }
+            }
+        }
+        Event::Incoming(Incoming::Publish(publish)) => {
+            door.lock()
+                .unwrap()
+                .handle_message(&publish.topic, &publish.payload, clock.now());
+        }
+        _ => {}
+    }
+}
+
 pub fn start_handler(
     server: Option<String>,
     port: u16,
     topic_prefix: String,
     shared_con: Arc<Mutex<DbCon>>,
+    zones: Vec<String>,
+    door: Option<SharedDoor>,
+    clock: SharedClock,
 ) -> Option<MqttSendQueue> {
-    let (tx, rx) = sync_channel::<TypedAction>(10);
+    let (tx, rx) = sync_channel::<Message>(10);
     match server {
         Some(server_str) => {
             thread::Builder::new()
@@ -138,8 +261,12 @@ pub fn start_handler(
                     opts.set_keep_alive(Duration::from_secs(30));
                     //.set_reconnect_opts(ReconnectOptions::AfterFirstSuccess(30));
                     let (mut mqtt_client, mut connection) = Client::new(opts, 10);
+                    let door_client = mqtt_client.clone();
                     thread::spawn(move || {
                         for notification in connection.iter() {
+                            if let (Some(door), Ok(event)) = (&door, &notification) {
+                                handle_door_event(event, door, &clock, &door_client);
+                            }
                             match notification {
                                 Ok(
                                     Event::Outgoing(Outgoing::PingReq)
@@ -155,9 +282,20 @@ pub fn start_handler(
                         panic!("MQTT client broke!");
                     });
 
-                    let last_status = {
-                        let con = shared_con.lock().unwrap();
-                        status::get_last(&con).unwrap()
+                    let (last_status, keyholder, zone_statuses) = {
+                        let mut con = shared_con.lock().unwrap();
+                        // zones without any status action yet have nothing to publish
+                        let zone_statuses: Vec<(&String, StatusAction)> = zones
+                            .iter()
+                            .filter_map(|zone| {
+                                Some((zone, con.get_last_zone_status(Some(zone)).ok()?))
+                            })
+                            .collect();
+                        (
+                            con.get_last_status().unwrap(),
+                            db::current_keyholder(&mut **con).unwrap(),
+                            zone_statuses,
+                        )
                     };
                     let typed_last_status = &TypedAction::Status(last_status.clone());
                     publish_status(
@@ -166,20 +304,41 @@ pub fn start_handler(
                         &mut mqtt_client,
                         &topic_prefix,
                     );
+                    // the key may have been handed over since the last status action
+                    publish_keyholder(keyholder.as_ref(), &mut mqtt_client, &topic_prefix);
+                    for (zone, status) in &zone_statuses {
+                        publish_zone_status(zone, status, &mut mqtt_client, &topic_prefix);
+                    }
                     println!("published current status on mqtt");
 
                     loop {
                         loop {
                             match rx.try_recv() {
-                                Ok(msg) => match msg {
-                                    TypedAction::Status(ref action) => {
-                                        publish_status(
+                                Ok(Message::Alert(change)) => {
+                                    publish_alert(&change, &mut mqtt_client, &topic_prefix);
+                                }
+                                Ok(Message::AnnouncementEvent(event)) => {
+                                    publish_announcement_event(
+                                        &event,
+                                        &mut mqtt_client,
+                                        &topic_prefix,
+                                    );
+                                }
+                                Ok(Message::Action(msg)) => match msg {
+                                    TypedAction::Status(ref action) => match &action.zone {
+                                        Some(zone) => publish_zone_status(
+                                            zone,
+                                            action,
+                                            &mut mqtt_client,
+                                            &topic_prefix,
+                                        ),
+                                        None => publish_status(
                                             action,
                                             &msg,
                                             &mut mqtt_client,
                                             &topic_prefix,
-                                        );
-                                    }
+                                        ),
+                                    },
                                     TypedAction::Announcement(ref action) => {
                                         publish_announcement(
                                             action,
@@ -188,9 +347,19 @@ pub fn start_handler(
                                             &topic_prefix,
                                         );
                                     }
+                                    TypedAction::Rsvp(ref action) => {
+                                        publish_rsvp(action, &msg, &mut mqtt_client, &topic_prefix);
+                                    }
                                     TypedAction::Presence(ref action) => {
                                         publish_presence(action, &mut mqtt_client, &topic_prefix);
                                     }
+                                    TypedAction::Handover(ref action) => {
+                                        publish_keyholder(
+                                            Some(&action.to),
+                                            &mut mqtt_client,
+                                            &topic_prefix,
+                                        );
+                                    }
                                 },
                                 Err(TryRecvError::Empty) => {
                                     break;
@@ -210,11 +379,30 @@ pub fn start_handler(
     }
 }
 
+#[derive(Debug)]
+enum Message {
+    Action(TypedAction),
+    Alert(AlertChange),
+    AnnouncementEvent(AnnouncementEvent),
+}
+
 #[derive(Debug, Clone)]
-pub(crate) struct MqttSendQueue(SyncSender<TypedAction>);
+pub(crate) struct MqttSendQueue(SyncSender<Message>);
 impl MqttSendQueue {
     pub(crate) fn try_send(&self, action: TypedAction) {
-        if let Err(err) = self.0.try_send(action) {
+        self.send(Message::Action(action));
+    }
+
+    pub(crate) fn try_send_alert(&self, change: AlertChange) {
+        self.send(Message::Alert(change));
+    }
+
+    pub(crate) fn try_send_announcement_event(&self, event: AnnouncementEvent) {
+        self.send(Message::AnnouncementEvent(event));
+    }
+
+    fn send(&self, message: Message) {
+        if let Err(err) = self.0.try_send(message) {
             eprintln!("Error adding to mqtt send queue: {err}");
         }
     }
@@ -0,0 +1,136 @@
+//! Reads the static part of the SpaceAPI response from a separate JSON or TOML file, and reloads it
+//! when the file changes.
+
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use config::Config;
+use serde_json::Value;
+use spaceapi::Status as SpaceapiStatus;
+
+use crate::api::spaceapi_schema;
+
+/// How often the file is checked for changes, in seconds.
+const INTERVAL: u64 = 5;
+
+pub type SharedSpaceapi = Arc<RwLock<SpaceapiStatus>>;
+
+/// Loads the file, the format is taken from the extension (`.json` or `.toml`).
+pub fn load(path: &Utf8Path) -> Result<SpaceapiStatus, String> {
+    let value: Value = Config::builder()
+        .add_source(config::File::from(path.as_std_path()))
+        .build()
+        .and_then(|c| c.try_deserialize())
+        .map_err(|e| e.to_string())?;
+    let status = spaceapi_schema::from_value(value)?;
+    // the dynamic parts are filled in by clubstatusd, so checking the static part is enough here
+    spaceapi_schema::validate(&serde_json::to_value(&status).unwrap())
+        .map_err(|errors| errors.join(", "))?;
+    Ok(status)
+}
+
+fn modified(path: &Utf8Path) -> Option<SystemTime> {
+    path.metadata().and_then(|m| m.modified()).ok()
+}
+
+pub struct SpaceapiFile {
+    path: Utf8PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl SpaceapiFile {
+    /// Expects the file to have been loaded just now.
+    pub fn new(path: Utf8PathBuf) -> Self {
+        let modified = modified(&path);
+        SpaceapiFile { path, modified }
+    }
+
+    /// Reloads the file if it has been modified since the last check, returning whether it has
+    /// been reloaded. If the new version can't be read, the previous one is kept.
+    pub fn check(&mut self, spaceapi: &SharedSpaceapi) -> bool {
+        let modified = modified(&self.path);
+        if modified == self.modified {
+            return false;
+        }
+        self.modified = modified;
+        match load(&self.path) {
+            Ok(status) => {
+                *spaceapi.write().unwrap() = status;
+                println!("Reloaded {}", self.path);
+                true
+            }
+            Err(err) => {
+                eprintln!(
+                    "Error reloading {}, keeping the previous version: {err}",
+                    self.path
+                );
+                false
+            }
+        }
+    }
+}
+
+pub fn start(mut file: SpaceapiFile, spaceapi: SharedSpaceapi) {
+    thread::Builder::new()
+        .name(String::from("spaceapi_file"))
+        .spawn(move || {
+            loop {
+                file.check(&spaceapi);
+                thread::sleep(Duration::new(INTERVAL, 0));
+            }
+        })
+        .unwrap();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::{self, File};
+
+    fn write(path: &Utf8Path, content: &str, modified: u64) {
+        fs::write(path, content).unwrap();
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(modified))
+            .unwrap();
+    }
+
+    #[test]
+    fn reloads_on_change() {
+        let dir = Utf8PathBuf::try_from(std::env::temp_dir()).unwrap();
+        let path = dir.join(format!("clubstatusd-spaceapi-{}.toml", std::process::id()));
+        let toml = |space: &str| {
+            format!(
+                "api_compatibility = [\"14\", \"15\"]\n\
+                 space = \"{space}\"\nlogo = \"logo.png\"\nurl = \"https://example.org\"\n\
+                 contact = {{}}\n[location]\nlat = 48.777\nlon = 9\n"
+            )
+        };
+        write(&path, &toml("Slopspace"), 1000);
+        let spaceapi: SharedSpaceapi = Arc::new(RwLock::new(load(&path).unwrap()));
+        assert_eq!(spaceapi.read().unwrap().location.lon, 9.0);
+        let mut file = SpaceapiFile::new(path.clone());
+        assert!(!file.check(&spaceapi));
+
+        write(&path, &toml("Hackspace"), 2000);
+        assert!(file.check(&spaceapi));
+        assert_eq!(spaceapi.read().unwrap().space, "Hackspace");
+        assert!(!file.check(&spaceapi));
+
+        // broken versions are ignored
+        write(&path, "space = ", 3000);
+        assert!(!file.check(&spaceapi));
+        write(
+            &path,
+            &toml("Hackspace").replace("[\"14\", \"15\"]", "[\"16\"]"),
+            4000,
+        );
+        assert!(!file.check(&spaceapi));
+        assert_eq!(spaceapi.read().unwrap().space, "Hackspace");
+        fs::remove_file(&path).unwrap();
+    }
+}
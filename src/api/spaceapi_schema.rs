@@ -0,0 +1,78 @@
+//! Reads the static part of the SpaceAPI response from the config, and checks responses against
+//! the bundled SpaceAPI JSON schemas of the versions in `api_compatibility`.
+
+use serde_json::Value;
+use spaceapi::Status as SpaceapiStatus;
+
+/// The SpaceAPI versions responses can be checked against.
+const SCHEMAS: &[(&str, &str)] = &[
+    ("14", include_str!("spaceapi-schema/14.json")),
+    ("15", include_str!("spaceapi-schema/15.json")),
+];
+
+/// Parses the `spaceapi` config, given as a JSON string.
+pub fn parse(json: &str) -> Result<SpaceapiStatus, String> {
+    from_value(serde_json::from_str(json).map_err(|e| format!("invalid JSON: {e}"))?)
+}
+
+/// Reads the `spaceapi` config. spaceapi-rs only knows about v14, so `api_compatibility` is
+/// kept as an extension, which is serialized at the top level like any other key.
+pub fn from_value(mut value: Value) -> Result<SpaceapiStatus, String> {
+    let api_compatibility = value
+        .as_object_mut()
+        .and_then(|o| o.remove("api_compatibility"));
+    let mut status: SpaceapiStatus =
+        serde_path_to_error::deserialize(value).map_err(|e| {
+            match e.path().to_string().as_str() {
+                "." => e.inner().to_string(),
+                path => format!("{path}: {}", e.inner()),
+            }
+        })?;
+    if let Some(versions) = api_compatibility {
+        status
+            .extensions
+            .insert(String::from("api_compatibility"), versions);
+    }
+    Ok(status)
+}
+
+/// Checks a SpaceAPI response against the schemas of the versions it claims compatibility with.
+/// Responses without `api_compatibility` (ie. v0.13) are not checked. Returns one message per
+/// problem, naming the offending key (eg. `location.lat`).
+pub fn validate(status: &Value) -> Result<(), Vec<String>> {
+    let Some(versions) = status.get("api_compatibility") else {
+        return Ok(());
+    };
+    let Some(versions) = versions.as_array() else {
+        return Err(vec![String::from(
+            "api_compatibility: has to be a list of versions, eg. [\"14\", \"15\"]",
+        )]);
+    };
+    let mut errors = Vec::new();
+    for version in versions {
+        let schema = SCHEMAS.iter().find(|(v, _)| Some(*v) == version.as_str());
+        let Some((version, schema)) = schema else {
+            let supported: Vec<&str> = SCHEMAS.iter().map(|(v, _)| *v).collect();
+            errors.push(format!(
+                "api_compatibility: unsupported version {version}, only {} are supported",
+                supported.join(", ")
+            ));
+            continue;
+        };
+        let schema: Value = serde_json::from_str(schema).unwrap();
+        let validator = jsonschema::validator_for(&schema).unwrap();
+        for error in validator.iter_errors(status) {
+            let path = error.instance_path.to_string();
+            let key = match path.trim_start_matches('/') {
+                "" => String::from("(top level)"),
+                key => key.replace('/', "."),
+            };
+            errors.push(format!("{key}: {error} (SpaceAPI v{version})"));
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
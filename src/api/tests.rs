@@ -0,0 +1,1773 @@
+//! Tests for the HTTP API, running against an in-memory database.
+//!
+//! Snapshots are stored in `src/api/snapshots/`, review changes with `cargo insta review`.
+
+use std::sync::{Arc, Mutex};
+
+use base64::prelude::{BASE64_STANDARD, Engine};
+use chrono::{Local, TimeZone};
+
+use insta::{assert_json_snapshot, assert_snapshot};
+use rocket::http::{ContentType, Cookie, Header, Status};
+use rocket::local::blocking::{Client, LocalResponse};
+use rocket::{Build, Rocket};
+use serde_json::{Value, json};
+
+use crate::clock::{Clock, TestClock};
+use crate::db::alerts::{AlertRules, Alerts};
+use crate::db::sqlite::SqliteStorage;
+use crate::db::{DbCon, DbStored, ensure_initialized};
+use crate::hex_str_to_salt;
+use clubstatus_types::{PresenceAction, PresentNamedUser, PresentUserStatus, UserName};
+
+const PASSWORD: &str = "correct horse";
+
+fn spaceapi_static() -> spaceapi::Status {
+    serde_json::from_value(json!({
+        "api": "0.13",
+        "space": "Slopspace",
+        "logo": "http://your-space.org/img/logo.png",
+        "url": "http://your-space.org",
+        "location": {
+            "lon": 9.236,
+            "lat": 48.777
+        },
+        "contact": {},
+        "issue_report_channels": ["twitter"],
+        "state": {}
+    }))
+    .unwrap()
+}
+
+/// 2023-11-14T22:13:20Z
+const NOW: i64 = 1700000000;
+
+fn rocket_with_settings(
+    password: Option<&str>,
+    clock: Arc<TestClock>,
+    settings: super::Settings,
+) -> Rocket<Build> {
+    let mut storage = SqliteStorage::open_in_memory().unwrap();
+    ensure_initialized(&mut storage).unwrap();
+    rocket_with_storage(storage, password, clock, settings)
+}
+
+fn rocket_with_storage(
+    storage: SqliteStorage,
+    password: Option<&str>,
+    clock: Arc<TestClock>,
+    settings: super::Settings,
+) -> Rocket<Build> {
+    let shared_con: Arc<Mutex<DbCon>> = Arc::new(Mutex::new(Box::new(storage)));
+    let salt = hex_str_to_salt("00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff");
+    super::run(
+        shared_con,
+        "127.0.0.1:8000",
+        password.map(String::from),
+        salt,
+        None,
+        settings,
+        clock,
+    )
+}
+
+fn rocket_with_clock(password: Option<&str>, clock: Arc<TestClock>) -> Rocket<Build> {
+    let settings = super::Settings {
+        spaceapi_static: Some(spaceapi_static()),
+        ..Default::default()
+    };
+    rocket_with_settings(password, clock, settings)
+}
+
+fn client_with_clock(password: Option<&str>, clock: Arc<TestClock>) -> Client {
+    Client::tracked(rocket_with_clock(password, clock)).unwrap()
+}
+
+fn client_with_password(password: Option<&str>) -> Client {
+    client_with_clock(password, TestClock::new(NOW))
+}
+
+/// A client without a configured password, so only the API itself is tested.
+fn client() -> Client {
+    client_with_password(None)
+}
+
+fn basic_auth(password: &str) -> Header<'static> {
+    let credentials = BASE64_STANDARD.encode(format!("user:{password}"));
+    Header::new("Authorization", format!("Basic {credentials}"))
+}
+
+fn json(response: LocalResponse) -> Value {
+    assert_eq!(response.content_type(), Some(ContentType::JSON));
+    serde_json::from_str(&response.into_string().unwrap()).unwrap()
+}
+
+fn put(client: &Client, body: Value) -> Value {
+    let response = client.put("/api/v0").body(body.to_string()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    json(response)
+}
+
+fn get(client: &Client, uri: &str) -> Value {
+    let response = client.get(uri.to_string()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    json(response)
+}
+
+fn put_status(client: &Client, status: &str) -> u64 {
+    put(
+        client,
+        json!({"type": "status", "user": "Hans Acker", "status": status, "note": "test"}),
+    )
+    .as_u64()
+    .unwrap()
+}
+
+/// 2100-01-01T00:00:00Z, so the announcements are always current.
+const FUTURE: i64 = 4102444800;
+
+fn put_announcement(client: &Client, note: &str, public: bool) -> u64 {
+    put(
+        client,
+        json!({
+            "type": "announcement",
+            "method": "new",
+            "user": "Frank Nord",
+            "note": note,
+            "from": FUTURE,
+            "to": FUTURE + 3600,
+            "public": public,
+            "url": "https://example.org/talk",
+        }),
+    )
+    .as_u64()
+    .unwrap()
+}
+
+fn action_ids(value: &Value) -> Vec<u64> {
+    value["actions"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|a| a["id"].as_u64().unwrap())
+        .collect()
+}
+
+#[test]
+fn api_versions() {
+    let client = client();
+    assert_json_snapshot!(get(&client, "/api/versions"));
+}
+
+#[test]
+fn auth_rejects_missing_and_wrong_credentials() {
+    let client = client_with_password(Some(PASSWORD));
+
+    let response = client.get("/api/v0/status/current").dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+    assert_eq!(
+        response.headers().get_one("WWW-Authenticate"),
+        Some("Basic")
+    );
+
+    let response = client
+        .get("/api/v0/status/current")
+        .header(basic_auth("wrong"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+
+    let response = client
+        .get("/api/v0/status/current")
+        .cookie(Cookie::new("clubstatusd-password", "wrong"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+
+    let response = client
+        .put("/api/v0")
+        .body(r#"{"type": "status", "user": "Hans Acker", "status": "public", "note": ""}"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+}
+
+#[test]
+fn auth_basic_sets_cookie() {
+    // the client's cookie jar drops expired cookies, so this one needs the real time
+    let clock = TestClock::new(chrono::Utc::now().timestamp());
+    let client = client_with_clock(Some(PASSWORD), clock);
+
+    let response = client
+        .get("/api/v0/status/current")
+        .header(basic_auth(PASSWORD))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let cookie = response
+        .cookies()
+        .get("clubstatusd-password")
+        .unwrap()
+        .clone();
+    // the cookie is derived from the password, it's not the password itself
+    assert_eq!(cookie.value().len(), 64);
+    assert_ne!(cookie.value(), PASSWORD);
+    assert_eq!(cookie.path(), Some("/"));
+    assert!(cookie.expires_datetime().is_some());
+    drop(response);
+
+    // the client tracks the cookie, so no more basic auth needed
+    let response = client.get("/api/v0/status/current").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    // a fresh client with the cookie only
+    let other = client_with_password(Some(PASSWORD));
+    let response = other
+        .get("/api/v0/status/current")
+        .cookie(Cookie::new(
+            "clubstatusd-password",
+            cookie.value().to_string(),
+        ))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+}
+
+#[test]
+fn auth_not_needed_for_public_endpoints() {
+    let client = client_with_password(Some(PASSWORD));
+    for uri in [
+        "/api/versions",
+        "/api/v0/status/current?public",
+        "/api/v0/announcement/current?public",
+        "/api/v0/announcement/current.ics?public",
+        "/spaceapi",
+    ] {
+        let response = client.get(uri).dispatch();
+        assert_eq!(response.status(), Status::Ok, "{}", uri);
+    }
+}
+
+#[test]
+fn put_status_and_current() {
+    let client = client();
+    assert_eq!(put_status(&client, "private"), 3);
+    assert_eq!(put_status(&client, "private"), 4);
+    assert_json_snapshot!(get(&client, "/api/v0/status/current"));
+}
+
+/// Applies due status changes right away, instead of waiting for the scheduler thread.
+fn apply_scheduled(client: &Client, now: i64) {
+    let shared_con = client.rocket().state::<Arc<Mutex<DbCon>>>().unwrap();
+    let mut con = shared_con.lock().unwrap();
+    crate::db::schedule::tick(&mut **con, None, now);
+}
+
+#[test]
+fn put_status_scheduled() {
+    let clock = TestClock::new(NOW);
+    let client = client_with_clock(None, clock.clone());
+    let scheduled = |at: Value, status: &str| {
+        put(
+            &client,
+            json!({"type": "status", "user": "Hans Acker", "status": status, "note": "workshop", "at": at}),
+        )
+    };
+    assert_eq!(
+        scheduled(json!("now+3600"), "public"),
+        json!({"scheduled": 1})
+    );
+    assert_eq!(
+        scheduled(json!(NOW + 7200), "closed"),
+        json!({"scheduled": 2})
+    );
+    // `at` in the past (or now) changes the status right away
+    assert_eq!(scheduled(json!("now"), "private"), json!(3));
+    assert_json_snapshot!(get(&client, "/api/v0/status/scheduled"));
+
+    let response = client.delete("/api/v0/status/scheduled/2").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(json(response), json!(2));
+    let response = client.delete("/api/v0/status/scheduled/2").dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+    assert_eq!(json(response), json!(null));
+
+    clock.advance(3599);
+    apply_scheduled(&client, clock.now());
+    assert_eq!(
+        get(&client, "/api/v0/status/current")["last"]["status"],
+        json!("private")
+    );
+    clock.advance(1);
+    apply_scheduled(&client, clock.now());
+    let current = get(&client, "/api/v0/status/current");
+    assert_eq!(current["last"]["id"], json!(4));
+    assert_eq!(current["last"]["status"], json!("public"));
+    assert_eq!(current["last"]["time"], json!(NOW + 3600));
+    assert_eq!(
+        get(&client, "/api/v0/status/scheduled"),
+        json!({"scheduled": []})
+    );
+}
+
+#[test]
+fn put_status_until() {
+    let clock = TestClock::new(NOW);
+    let client = client_with_clock(None, clock.clone());
+    let status = |body: Value| client.put("/api/v0").body(body.to_string()).dispatch();
+
+    for invalid in [
+        json!({"until": "now"}),
+        json!({"until": NOW - 1}),
+        json!({"revert_to": "private"}),
+        json!({"at": "now+60", "until": "now+60"}),
+    ] {
+        let mut body =
+            json!({"type": "status", "user": "Hans Acker", "status": "public", "note": ""});
+        body.as_object_mut()
+            .unwrap()
+            .extend(invalid.as_object().unwrap().clone());
+        assert_eq!(
+            status(body).status(),
+            Status::UnprocessableEntity,
+            "{invalid}"
+        );
+    }
+
+    let response = status(json!({
+        "type": "status", "user": "Hans Acker", "status": "public", "note": "",
+        "until": "now+60", "revert_to": "private",
+    }));
+    assert_eq!(json(response), json!(3));
+    let last = &get(&client, "/api/v0/status/current")["last"];
+    assert_eq!(last["until"], json!(NOW + 60));
+    assert_eq!(last["revert_to"], json!("private"));
+    assert_eq!(
+        get(&client, "/api/v0/status/current?public")["until"],
+        json!(NOW + 60)
+    );
+    let until_local = Local.timestamp_opt(NOW + 60, 0).unwrap();
+    assert_eq!(
+        get(&client, "/spaceapi")["state"]["message"],
+        json!(format!("open until {}", until_local.format("%H:%M")))
+    );
+
+    clock.advance(59);
+    apply_scheduled(&client, clock.now());
+    assert_eq!(
+        get(&client, "/api/v0/status/current")["last"]["id"],
+        json!(3)
+    );
+    clock.advance(1);
+    apply_scheduled(&client, clock.now());
+    let last = &get(&client, "/api/v0/status/current")["last"];
+    assert_eq!(last["status"], json!("private"));
+    assert_eq!(last["user"], json!("clubstatusd"));
+    assert_eq!(last["until"], json!(null));
+    let public = get(&client, "/api/v0/status/current?public");
+    assert_eq!(public["changed"]["status"], json!("closed"));
+    assert_eq!(public["until"], json!(null));
+    assert_eq!(get(&client, "/spaceapi")["state"]["message"], json!(null));
+}
+
+#[test]
+fn alerts() {
+    // not mounted unless configured, so "alerts" is rejected as action type
+    let response = client().get("/api/v0/alerts").dispatch().status();
+    assert_eq!(response, Status::UnprocessableEntity);
+
+    let clock = TestClock::new(NOW);
+    let alerts = Arc::new(Mutex::new(Alerts::new(AlertRules::default())));
+    let rocket = super::mount_alerts(rocket_with_clock(None, clock.clone()), alerts.clone());
+    let client = Client::tracked(rocket).unwrap();
+    let check = || {
+        let shared_con = client.rocket().state::<Arc<Mutex<DbCon>>>().unwrap();
+        let mut con = shared_con.lock().unwrap();
+        alerts
+            .lock()
+            .unwrap()
+            .check(&mut **con, clock.now())
+            .unwrap();
+    };
+
+    put_status(&client, "public");
+    check();
+    assert_eq!(get(&client, "/api/v0/alerts"), json!({"alerts": []}));
+    clock.advance(3600);
+    check();
+    assert_json_snapshot!(get(&client, "/api/v0/alerts"));
+    put_status(&client, "closed");
+    check();
+    assert_eq!(get(&client, "/api/v0/alerts"), json!({"alerts": []}));
+}
+
+#[test]
+fn put_status_revert() {
+    let clock = TestClock::new(NOW);
+    let client = client_with_clock(None, clock.clone());
+    assert_eq!(put_status(&client, "public"), 3);
+    clock.advance(60);
+    assert_eq!(put_status(&client, "closed"), 4);
+    clock.advance(5);
+
+    let revert = |reverts: u64| {
+        client
+            .put("/api/v0")
+            .body(
+                json!({"type": "status_revert", "reverts": reverts, "user": "Hans Acker", "note": "mis-tap"})
+                    .to_string(),
+            )
+            .dispatch()
+    };
+    // not the last status action
+    let response = revert(3);
+    assert_eq!(response.status(), Status::Conflict);
+    assert_eq!(
+        json(response),
+        json!({"error": "3 is not the last status action, which is 4"})
+    );
+    assert_eq!(json(revert(4)), json!(5));
+    let response = revert(5);
+    assert_eq!(response.status(), Status::Conflict);
+    assert_eq!(json(response), json!({"error": "5 is a revert itself"}));
+    assert_json_snapshot!(get(&client, "/api/v0/status/current"));
+    assert_eq!(get(&client, "/spaceapi")["state"]["lastchange"], json!(NOW));
+    assert_eq!(
+        get(&client, "/api/v0/status/current?public")["changed"]["id"],
+        json!(3)
+    );
+}
+
+#[test]
+fn keyholder() {
+    let settings = super::Settings {
+        keyholder_check: super::KeyholderCheck::Refuse,
+        ..Default::default()
+    };
+    let client =
+        Client::tracked(rocket_with_settings(None, TestClock::new(NOW), settings)).unwrap();
+    let status = |user: &str, status: &str| {
+        client
+            .put("/api/v0")
+            .body(json!({"type": "status", "user": user, "status": status, "note": ""}).to_string())
+            .dispatch()
+    };
+    let handover = |to: &str| {
+        client
+            .put("/api/v0")
+            .body(
+                json!({"type": "handover", "user": "Frank Nord", "to": to, "note": "leaving early"})
+                    .to_string(),
+            )
+            .dispatch()
+    };
+
+    // the key can't be handed over while closed
+    let response = handover("Hans Acker");
+    assert_eq!(response.status(), Status::Conflict);
+    assert_eq!(json(response), json!({"error": "the space is closed"}));
+    // nor named when closing
+    let response = client
+        .put("/api/v0")
+        .body(
+            json!({"type": "status", "user": "Hans Acker", "status": "closed", "note": "", "keyholder": "Frank Nord"})
+                .to_string(),
+        )
+        .dispatch();
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+
+    assert_eq!(json(status("Frank Nord", "public")), json!(3));
+    assert_eq!(
+        get(&client, "/api/v0/status/current")["keyholder"],
+        json!("Frank Nord")
+    );
+    let response = status("Hans Acker", "closed");
+    assert_eq!(response.status(), Status::Conflict);
+    assert_eq!(
+        json(response),
+        json!({"error": "Frank Nord holds the key, but Hans Acker is closing"})
+    );
+
+    assert_eq!(json(handover("Hans Acker")), json!(4));
+    assert_json_snapshot!(get(&client, "/api/v0/status/current"));
+    assert_eq!(
+        action_ids(&get(&client, "/api/v0/handover?id=0:last")),
+        vec![4]
+    );
+    assert_eq!(json(status("Hans Acker", "closed")), json!(5));
+    assert_eq!(
+        get(&client, "/api/v0/status/current")["keyholder"],
+        json!(null)
+    );
+
+    // by default closing is only warned about
+    let client = self::client();
+    put(
+        &client,
+        json!({"type": "status", "user": "Hans Acker", "status": "private", "note": "", "keyholder": "Frank Nord"}),
+    );
+    assert_eq!(put_status(&client, "closed"), 4);
+}
+
+#[test]
+fn zones() {
+    let settings = super::Settings {
+        spaceapi_static: Some(spaceapi_static()),
+        zones: vec![String::from("workshop")],
+        ..Default::default()
+    };
+    let client =
+        Client::tracked(rocket_with_settings(None, TestClock::new(NOW), settings)).unwrap();
+    let zone_status = |body: Value| client.put("/api/v0").body(body.to_string()).dispatch();
+
+    let response = zone_status(
+        json!({"type": "status", "user": "Hans Acker", "status": "public", "note": "", "zone": "lounge"}),
+    );
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+    let response = zone_status(
+        json!({"type": "status", "user": "Hans Acker", "status": "public", "note": "", "zone": "workshop", "until": NOW + 3600}),
+    );
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+
+    // no status yet
+    let response = client
+        .get("/api/v0/status/current?zone=workshop")
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(json(response)["last"], json!(null));
+    let response = client.get("/api/v0/status/current?zone=lounge").dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+
+    let response = zone_status(
+        json!({"type": "status", "user": "Hans Acker", "status": "public", "note": "", "zone": "workshop"}),
+    );
+    assert_eq!(json(response), json!(3));
+    assert_json_snapshot!(get(&client, "/api/v0/status/current?zone=workshop"));
+    assert_eq!(
+        get(&client, "/api/v0/status/current?public&zone=workshop")["changed"]["status"],
+        json!("public")
+    );
+    // the status of the whole space is unchanged
+    assert_eq!(
+        get(&client, "/api/v0/status/current")["last"]["status"],
+        json!("closed")
+    );
+    let spaceapi = get(&client, "/spaceapi");
+    assert_eq!(spaceapi["state"]["open"], json!(false));
+    assert_eq!(
+        spaceapi["ext_zones"],
+        json!({"workshop": {"open": true, "lastchange": NOW}})
+    );
+}
+
+#[test]
+fn put_announcement_new_mod_del() {
+    let client = client();
+    let aid = put_announcement(&client, "Workshop", false);
+    assert_eq!(aid, 3);
+    let mod_id = put(
+        &client,
+        json!({
+            "type": "announcement",
+            "method": "mod",
+            "aid": aid,
+            "user": "Frank Nord",
+            "note": "Workshop (longer)",
+            "from": FUTURE,
+            "to": FUTURE + 7200,
+            "public": true,
+        }),
+    );
+    assert_eq!(mod_id, json!(4));
+    assert_json_snapshot!(get(&client, "/api/v0/announcement/current"));
+
+    let del_id = put(
+        &client,
+        json!({"type": "announcement", "method": "del", "aid": aid, "user": "Frank Nord"}),
+    );
+    assert_eq!(del_id, json!(5));
+    assert_json_snapshot!(get(&client, "/api/v0/announcement/current"));
+
+    // deleted announcements cannot be modified anymore
+    let response = client
+        .put("/api/v0")
+        .body(
+            json!({"type": "announcement", "method": "del", "aid": aid, "user": "Frank Nord"})
+                .to_string(),
+        )
+        .dispatch();
+    assert_eq!(response.status(), Status::InternalServerError);
+}
+
+#[test]
+fn put_announcement_relative_time() {
+    let client = client();
+    let id = put(
+        &client,
+        json!({
+            "type": "announcement",
+            "method": "new",
+            "user": "Frank Nord",
+            "note": "soon",
+            "from": "now+60",
+            "to": "now+3660",
+            "public": false,
+        }),
+    );
+    let action = &get(&client, &format!("/api/v0/announcement?id={id}"))["actions"][0];
+    assert_eq!(action["time"], json!(NOW));
+    assert_eq!(action["from"], json!(NOW + 60));
+    assert_eq!(action["to"], json!(NOW + 3660));
+}
+
+#[test]
+fn announcement_expiry() {
+    let clock = TestClock::new(NOW);
+    let client = client_with_clock(None, clock.clone());
+    put(
+        &client,
+        json!({
+            "type": "announcement",
+            "method": "new",
+            "user": "Frank Nord",
+            "note": "short visit",
+            "from": "now",
+            "to": "now+60",
+            "public": true,
+        }),
+    );
+    let current = || {
+        (
+            action_ids(&get(&client, "/api/v0/announcement/current")),
+            action_ids(&get(&client, "/api/v0/announcement/current?public")),
+        )
+    };
+    assert_eq!(current(), (vec![3], vec![3]));
+    clock.advance(60);
+    // `to` is inclusive
+    assert_eq!(current(), (vec![3], vec![3]));
+    clock.advance(1);
+    assert_eq!(current(), (vec![], vec![]));
+    let ics = client
+        .get("/api/v0/announcement/current.ics")
+        .dispatch()
+        .into_string()
+        .unwrap();
+    assert!(!ics.contains("VEVENT"));
+}
+
+/// 2023-11-16T20:00:00+01:00, the Thursday after NOW.
+const PLENUM: i64 = 1700161200;
+
+#[test]
+fn announcement_series() {
+    let client = client();
+    let series = |body: Value| {
+        let response = client.put("/api/v0").body(body.to_string()).dispatch();
+        (response.status(), response.into_string().unwrap())
+    };
+    let plenum = json!({
+        "type": "announcement",
+        "method": "new",
+        "user": "plenumsbot",
+        "note": "Plenum",
+        "from": PLENUM,
+        "to": PLENUM + 3600,
+        "public": true,
+        "url": "https://example.org/plenum",
+        "rrule": "FREQ=WEEKLY;INTERVAL=2;BYDAY=TH",
+        "timezone": "Europe/Berlin",
+    });
+    assert_eq!(put(&client, plenum.clone()), json!({"series": 1}));
+    assert_json_snapshot!(get(&client, "/api/v0/announcement/series"));
+    // the occurrences within the next four weeks are stored right away
+    let current = get(&client, "/api/v0/announcement/current");
+    assert_eq!(action_ids(&current), vec![3, 4]);
+    assert_eq!(current["actions"][1]["series"], json!(1));
+    assert_eq!(
+        current["actions"][1]["recurrence_id"],
+        json!(PLENUM + 14 * 86400)
+    );
+
+    // single occurrences can be moved and deleted like other announcements
+    put(
+        &client,
+        json!({
+            "type": "announcement",
+            "method": "mod",
+            "aid": 4,
+            "user": "Hans Acker",
+            "note": "Plenum (late)",
+            "from": PLENUM + 14 * 86400 + 3600,
+            "to": PLENUM + 14 * 86400 + 7200,
+            "public": true,
+        }),
+    );
+    put(
+        &client,
+        json!({"type": "announcement", "method": "del", "aid": 3, "user": "Hans Acker"}),
+    );
+    let response = client.get("/api/v0/announcement/current.ics").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_snapshot!(response.into_string().unwrap());
+
+    // the whole series is modified with its id, which resets the upcoming occurrences
+    let mut early = plenum.clone();
+    early["method"] = json!("mod");
+    early["series"] = json!(1);
+    early["note"] = json!("Plenum (early)");
+    assert_eq!(put(&client, early), json!({"series": 1}));
+    let current = get(&client, "/api/v0/announcement/current");
+    assert_eq!(action_ids(&current), vec![8, 9]);
+    assert_eq!(current["actions"][0]["note"], json!("Plenum (early)"));
+    let ics = client
+        .get("/api/v0/announcement/current.ics?public")
+        .dispatch()
+        .into_string()
+        .unwrap();
+    assert!(ics.contains("RRULE:FREQ=WEEKLY;INTERVAL=2;BYDAY=TH"));
+    assert!(!ics.contains("EXDATE"));
+    assert!(ics.contains("BEGIN:VTIMEZONE\r\nTZID:Europe/Berlin\r\n"));
+
+    assert_eq!(
+        put(
+            &client,
+            json!({"type": "announcement", "method": "del", "series": 1, "user": "Hans Acker"}),
+        ),
+        json!({"series": 1})
+    );
+    assert_eq!(
+        action_ids(&get(&client, "/api/v0/announcement/current")),
+        Vec::<u64>::new()
+    );
+    assert_eq!(
+        get(&client, "/api/v0/announcement/series"),
+        json!({"series": []})
+    );
+    let (status, error) =
+        series(json!({"type": "announcement", "method": "del", "series": 1, "user": "Hans Acker"}));
+    assert_eq!(status, Status::NotFound);
+    assert_eq!(
+        serde_json::from_str::<Value>(&error).unwrap(),
+        json!({"error": "unknown or deleted series"})
+    );
+    let mut unknown = plenum.clone();
+    unknown["method"] = json!("mod");
+    unknown["series"] = json!(99);
+    assert_eq!(series(unknown).0, Status::NotFound);
+
+    let mut invalid = plenum.clone();
+    invalid["rrule"] = json!("FREQ=HOURLY");
+    assert_eq!(
+        series(invalid),
+        (
+            Status::UnprocessableEntity,
+            String::from("invalid rrule: FREQ has to be DAILY or longer\n")
+        )
+    );
+    let mut invalid = plenum.clone();
+    invalid["timezone"] = json!("Europe/Aachen");
+    assert_eq!(series(invalid).0, Status::UnprocessableEntity);
+    let (status, error) = series(
+        json!({"type": "announcement", "method": "del", "aid": 3, "series": 1, "user": "Hans Acker"}),
+    );
+    assert_eq!(
+        (status, error.as_str()),
+        (
+            Status::UnprocessableEntity,
+            "either aid or series has to be set\n"
+        )
+    );
+}
+
+#[test]
+fn auth_cookie_expiry() {
+    // cookies expire at the start of the year after next
+    let clock = TestClock::new(NOW);
+    let client = client_with_clock(Some(PASSWORD), clock.clone());
+    for (now, expires) in [
+        (1577836800, 1640995200), // 2020-01-01 -> 2022-01-01
+        (1609459199, 1640995200), // 2020-12-31T23:59:59 -> 2022-01-01
+        (1609459200, 1672531200), // 2021-01-01 -> 2023-01-01
+    ] {
+        clock.set(now);
+        let response = client
+            .get("/api/v0/status/current")
+            .header(basic_auth(PASSWORD))
+            .dispatch();
+        let cookie = response.cookies().get("clubstatusd-password").unwrap();
+        assert_eq!(
+            cookie.expires_datetime().unwrap().unix_timestamp(),
+            expires,
+            "now={now}"
+        );
+    }
+}
+
+#[test]
+fn put_presence() {
+    let client = client();
+    assert_eq!(
+        put(&client, json!({"type": "presence", "user": "Hans Acker"})),
+        Value::Null
+    );
+    assert_eq!(
+        put(
+            &client,
+            json!({"type": "presence", "anonymous_client_id": 42, "anonymous_users": 2.5})
+        ),
+        Value::Null
+    );
+}
+
+#[test]
+fn put_invalid() {
+    let client = client();
+    let response = client.put("/api/v0").body("{").dispatch();
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+
+    let response = client
+        .put("/api/v0")
+        .body(json!({"type": "status", "user": "", "status": "public", "note": ""}).to_string())
+        .dispatch();
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+    assert_snapshot!(response.into_string().unwrap());
+
+    let response = client
+        .put("/api/v0")
+        .body(
+            json!({"type": "status", "user": "Hans", "status": "public", "note": "x".repeat(81)})
+                .to_string(),
+        )
+        .dispatch();
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+
+    // bodies over 16KiB are rejected, but reported as internal error
+    let response = client.put("/api/v0").body("x".repeat(17000)).dispatch();
+    assert_eq!(response.status(), Status::InternalServerError);
+}
+
+#[test]
+fn query_filters() {
+    let client = client();
+    // 1: initial status, 2: initial presence
+    put_status(&client, "public"); // 3
+    put_announcement(&client, "Talk", true); // 4
+    put_status(&client, "closed"); // 5
+
+    assert_eq!(
+        action_ids(&get(&client, "/api/v0/all")),
+        vec![1, 2, 3, 4, 5]
+    );
+    assert_eq!(action_ids(&get(&client, "/api/v0/status")), vec![1, 3, 5]);
+    assert_eq!(action_ids(&get(&client, "/api/v0/announcement")), vec![4]);
+    assert_eq!(action_ids(&get(&client, "/api/v0/presence")), vec![2]);
+
+    // id
+    assert_eq!(action_ids(&get(&client, "/api/v0/all?id=3")), vec![3]);
+    assert_eq!(
+        action_ids(&get(&client, "/api/v0/all?id=2:4")),
+        vec![2, 3, 4]
+    );
+    assert_eq!(
+        action_ids(&get(&client, "/api/v0/all?id=4:2")),
+        vec![2, 3, 4]
+    );
+    assert_eq!(
+        action_ids(&get(&client, "/api/v0/all?id=4:last")),
+        vec![4, 5]
+    );
+    assert_eq!(action_ids(&get(&client, "/api/v0/all?id=last")), vec![5]);
+    assert_eq!(
+        action_ids(&get(&client, "/api/v0/status?id=4")),
+        Vec::<u64>::new()
+    );
+
+    // time, the initial actions have time=0
+    assert_eq!(action_ids(&get(&client, "/api/v0/all?time=0")), vec![1, 2]);
+    assert_eq!(
+        action_ids(&get(&client, "/api/v0/all?time=1:0")),
+        vec![1, 2]
+    );
+    assert_eq!(
+        action_ids(&get(&client, "/api/v0/all?time=1:9223372036854775807")),
+        vec![3, 4, 5]
+    );
+
+    // count and take
+    assert_eq!(action_ids(&get(&client, "/api/v0/all?count=2")), vec![4, 5]);
+    assert_eq!(
+        action_ids(&get(&client, "/api/v0/all?count=2&take=last")),
+        vec![4, 5]
+    );
+    assert_eq!(
+        action_ids(&get(&client, "/api/v0/all?count=2&take=first")),
+        vec![1, 2]
+    );
+    assert_eq!(
+        action_ids(&get(&client, "/api/v0/status?id=2:last&count=1&take=first")),
+        vec![3]
+    );
+    let response = client.get("/api/v0/all?take=middle").dispatch();
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+
+    let response = client.get("/api/v0/foo").dispatch();
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+}
+
+#[test]
+fn query_typed_actions() {
+    let client = client();
+    put_status(&client, "public");
+    put_announcement(&client, "Talk", true);
+    assert_json_snapshot!(get(&client, "/api/v0/all"));
+}
+
+#[test]
+fn public_status() {
+    let client = client();
+    assert_json_snapshot!(get(&client, "/api/v0/status/current?public"));
+
+    // private is closed for the public
+    put_status(&client, "private");
+    assert_eq!(
+        get(&client, "/api/v0/status/current?public")["changed"]["id"],
+        json!(1)
+    );
+
+    put_status(&client, "public");
+    assert_json_snapshot!(get(&client, "/api/v0/status/current?public"));
+}
+
+#[test]
+fn public_message() {
+    let client = client();
+    let response = client
+        .put("/api/v0")
+        .body(
+            json!({"type": "status", "user": "Hans Acker", "status": "public", "note": "",
+                "public_message": "x".repeat(81)})
+            .to_string(),
+        )
+        .dispatch();
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+
+    put(
+        &client,
+        json!({"type": "status", "user": "Hans Acker", "status": "public", "note": "only us",
+            "public_message": "open house, bring your own project", "until": FUTURE}),
+    );
+    assert_json_snapshot!(get(&client, "/api/v0/status/current?public"));
+    let until_local = Local.timestamp_opt(FUTURE, 0).unwrap();
+    assert_eq!(
+        get(&client, "/spaceapi")["state"]["message"],
+        json!(format!(
+            "open house, bring your own project (open until {})",
+            until_local.format("%Y-%m-%d %H:%M")
+        ))
+    );
+
+    // the message belongs to the last status, even if it did not change the public status
+    for message in ["members only", "back at 20:00"] {
+        put(
+            &client,
+            json!({"type": "status", "user": "Hans Acker", "status": "private", "note": "",
+                "public_message": message}),
+        );
+    }
+    let public = get(&client, "/api/v0/status/current?public");
+    assert_eq!(public["public_message"], json!("back at 20:00"));
+    assert_eq!(public["changed"]["public_message"], json!("members only"));
+    assert_eq!(
+        get(&client, "/spaceapi")["state"]["message"],
+        json!("back at 20:00")
+    );
+    put_status(&client, "closed");
+    assert_eq!(
+        get(&client, "/api/v0/status/current?public")["public_message"],
+        json!(null)
+    );
+}
+
+#[test]
+fn public_announcements() {
+    let client = client();
+    put_announcement(&client, "Secret meeting", false);
+    put_announcement(&client, "Open workshop", true);
+    assert_json_snapshot!(get(&client, "/api/v0/announcement/current?public"));
+}
+
+#[test]
+fn ics() {
+    let client = client();
+    put_announcement(&client, "Secret meeting", false);
+    put_announcement(&client, "Open workshop", true);
+
+    let response = client.get("/api/v0/announcement/current.ics").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(
+        response.content_type(),
+        Some(ContentType::new("text", "calendar").with_params(("charset", "utf-8")))
+    );
+    assert_snapshot!(response.into_string().unwrap());
+
+    let response = client
+        .get("/api/v0/announcement/current.ics?public")
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let ics = response.into_string().unwrap();
+    assert!(ics.contains("SUMMARY:Open workshop"));
+    assert!(!ics.contains("Secret meeting"));
+}
+
+#[test]
+fn rsvp() {
+    let clock = TestClock::new(NOW);
+    let client = client_with_clock(None, clock.clone());
+    let talk = put_announcement(&client, "Talk", true);
+    let rsvp = |user: &str, answer: &str| {
+        put(
+            &client,
+            json!({"type": "rsvp", "aid": talk, "user": user, "answer": answer}),
+        )
+    };
+    rsvp("Hans Acker", "yes");
+    rsvp("Frank Nord", "maybe");
+    put(
+        &client,
+        json!({"type": "rsvp", "aid": talk, "user": "Frank Nord", "answer": "yes",
+            "note": "bringing a projector"}),
+    );
+    rsvp("Erika Muster", "no");
+    assert_json_snapshot!(get(&client, &format!("/api/v0/announcement/{talk}/rsvp")));
+
+    let counts = json!({"yes": 2, "no": 1, "maybe": 0});
+    assert_eq!(
+        get(&client, "/api/v0/announcement/current")["actions"][0]["rsvp"],
+        counts
+    );
+    // only the counts are public
+    let public = get(&client, "/api/v0/announcement/current?public");
+    assert_eq!(public["actions"][0]["rsvp"], counts);
+    assert!(!public.to_string().contains("Frank Nord"));
+
+    let ics = client
+        .get("/api/v0/announcement/current.ics")
+        .dispatch()
+        .into_string()
+        .unwrap();
+    assert!(
+        ics.contains("ATTENDEE;CN=Frank Nord;PARTSTAT=ACCEPTED:urn:x-clubstatusd:user:Frank+Nord")
+    );
+    assert!(ics.contains("PARTSTAT=DECLINED"));
+    let public_ics = client
+        .get("/api/v0/announcement/current.ics?public")
+        .dispatch()
+        .into_string()
+        .unwrap();
+    assert!(!public_ics.contains("ATTENDEE"));
+
+    let answer = |aid: u64| {
+        client
+            .put("/api/v0")
+            .body(
+                json!({"type": "rsvp", "aid": aid, "user": "Hans Acker", "answer": "yes"})
+                    .to_string(),
+            )
+            .dispatch()
+    };
+    // unknown announcements
+    let response = answer(12345);
+    assert_eq!(response.status(), Status::NotFound);
+    assert_eq!(
+        json(response),
+        json!({"error": "unknown or deleted announcement 12345"})
+    );
+    let response = client.get("/api/v0/announcement/12345/rsvp").dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+
+    // deleted announcements
+    let cancelled = put_announcement(&client, "Cancelled", true);
+    put(
+        &client,
+        json!({"type": "announcement", "method": "del", "aid": cancelled, "user": "Frank Nord"}),
+    );
+    assert_eq!(answer(cancelled).status(), Status::NotFound);
+
+    // ended announcements
+    clock.advance(FUTURE + 3600 - NOW);
+    let response = answer(talk);
+    assert_eq!(response.status(), Status::Conflict);
+    assert_eq!(
+        json(response),
+        json!({"error": format!("announcement {talk} has ended")})
+    );
+}
+
+#[test]
+fn announcement_description() {
+    let client = client();
+    // several KB, longer than a whole request used to be
+    let description =
+        "## Soldering for beginners\n\nBring your own kit, or buy one for 5 €.\n".repeat(60);
+    let aid = put(
+        &client,
+        json!({
+            "type": "announcement",
+            "method": "new",
+            "user": "Frank Nord",
+            "note": "Soldering workshop",
+            "from": FUTURE,
+            "to": FUTURE + 3600,
+            "public": true,
+            "url": null,
+            "description": description,
+        }),
+    );
+    put_announcement(&client, "Without description", true);
+
+    let current = get(&client, "/api/v0/announcement/current");
+    assert_eq!(current["actions"][0]["aid"], aid);
+    assert_eq!(current["actions"][0]["note"], json!("Soldering workshop"));
+    assert_eq!(current["actions"][0]["description"], json!(description));
+    assert_eq!(current["actions"][1]["description"], json!(null));
+    let public = get(&client, "/api/v0/announcement/current?public");
+    assert_eq!(public["actions"][0]["description"], json!(description));
+
+    let ics = client
+        .get("/api/v0/announcement/current.ics")
+        .dispatch()
+        .into_string()
+        .unwrap();
+    assert!(ics.contains("SUMMARY:Soldering workshop"));
+    assert!(ics.contains("DESCRIPTION:## Soldering for beginners\\n\\nBring your own kit"));
+
+    let response = client
+        .put("/api/v0")
+        .body(
+            json!({
+                "type": "announcement",
+                "method": "new",
+                "user": "Frank Nord",
+                "note": "Too long",
+                "from": FUTURE,
+                "to": FUTURE + 3600,
+                "public": true,
+                "url": null,
+                "description": "x".repeat(super::DESCRIPTION_MAX + 1),
+            })
+            .to_string(),
+        )
+        .dispatch();
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+}
+
+#[test]
+fn resources() {
+    let resource = |name: &str, overlap| super::Resource {
+        name: String::from(name),
+        overlap,
+    };
+    let settings = super::Settings {
+        resources: vec![
+            resource("lasercutter", super::OverlapCheck::Refuse),
+            resource("lecture_room", super::OverlapCheck::Warn),
+        ],
+        ..Default::default()
+    };
+    let client =
+        Client::tracked(rocket_with_settings(None, TestClock::new(NOW), settings)).unwrap();
+    let book = |note: &str, from: i64, to: i64, resources: Value| {
+        client
+            .put("/api/v0")
+            .body(
+                json!({
+                    "type": "announcement",
+                    "method": "new",
+                    "user": "Frank Nord",
+                    "note": note,
+                    "from": from,
+                    "to": to,
+                    "public": false,
+                    "url": null,
+                    "resources": resources,
+                })
+                .to_string(),
+            )
+            .dispatch()
+    };
+    let cutting = json(book(
+        "Cutting",
+        FUTURE,
+        FUTURE + 3600,
+        json!(["lasercutter"]),
+    ));
+    let talk = json(book("Talk", FUTURE, FUTURE + 3600, json!(["lecture_room"])));
+
+    let response = book(
+        "More cutting",
+        FUTURE + 1800,
+        FUTURE + 7200,
+        json!(["lasercutter"]),
+    );
+    assert_eq!(response.status(), Status::Conflict);
+    assert_eq!(
+        json(response),
+        json!({"error": format!("lasercutter is already booked for 'Cutting' (announcement {cutting})")})
+    );
+    // overlapping bookings of the lecture room are only warned about
+    let workshop = json(book(
+        "Workshop",
+        FUTURE + 1800,
+        FUTURE + 7200,
+        json!(["lecture_room"]),
+    ));
+    let after = json(book(
+        "After cutting",
+        FUTURE + 3600,
+        FUTURE + 7200,
+        json!(["lasercutter"]),
+    ));
+    // moving a booking doesn't conflict with itself
+    put(
+        &client,
+        json!({"type": "announcement", "method": "mod", "aid": cutting, "user": "Frank Nord",
+            "note": "Cutting", "from": FUTURE - 1800, "to": FUTURE + 1800, "public": false,
+            "url": null, "resources": ["lasercutter"]}),
+    );
+
+    assert_eq!(
+        get(&client, "/api/v0/resource/lasercutter/busy"),
+        json!({"busy": [
+            {"aid": cutting, "from": FUTURE - 1800, "to": FUTURE + 1800},
+            {"aid": after, "from": FUTURE + 3600, "to": FUTURE + 7200},
+        ]})
+    );
+    assert_eq!(
+        get(&client, "/api/v0/resource/lecture_room/busy")["busy"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|b| b["aid"].clone())
+            .collect::<Vec<_>>(),
+        vec![talk.clone(), workshop]
+    );
+    let response = client.get("/api/v0/resource/ballroom/busy").dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+
+    let response = client
+        .get("/api/v0/resource/lecture_room/bookings.ics")
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let ics = response.into_string().unwrap();
+    assert!(ics.contains("SUMMARY:Talk\r\n"));
+    assert!(ics.contains("SUMMARY:Workshop\r\n"));
+    assert!(ics.contains("RESOURCES:lecture_room\r\n"));
+    assert!(!ics.contains("Cutting"));
+    let response = client
+        .get("/api/v0/resource/ballroom/bookings.ics")
+        .dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+
+    let response = book("Dance", FUTURE, FUTURE + 3600, json!(["ballroom"]));
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+    let response = client
+        .put("/api/v0")
+        .body(
+            json!({"type": "announcement", "method": "new", "user": "Frank Nord",
+                "note": "Weekly cutting", "from": FUTURE, "to": FUTURE + 3600, "public": false,
+                "url": null, "rrule": "FREQ=WEEKLY", "resources": ["lasercutter"]})
+            .to_string(),
+        )
+        .dispatch();
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+
+    // the bookings are only shown to members
+    put(
+        &client,
+        json!({"type": "announcement", "method": "mod", "aid": talk, "user": "Frank Nord",
+            "note": "Talk", "from": FUTURE, "to": FUTURE + 3600, "public": true,
+            "url": null, "resources": ["lecture_room"]}),
+    );
+    let ics = client
+        .get("/api/v0/announcement/current.ics")
+        .dispatch()
+        .into_string()
+        .unwrap();
+    assert!(ics.contains("RESOURCES:lecture_room\r\n"));
+    let ics = client
+        .get("/api/v0/announcement/current.ics?public")
+        .dispatch()
+        .into_string()
+        .unwrap();
+    assert!(ics.contains("SUMMARY:Talk\r\n"));
+    assert!(!ics.contains("RESOURCES"));
+}
+
+#[test]
+fn announcement_categories() {
+    let settings = super::Settings {
+        announcement_categories: vec![String::from("plenum"), String::from("workshop")],
+        ..Default::default()
+    };
+    let client =
+        Client::tracked(rocket_with_settings(None, TestClock::new(NOW), settings)).unwrap();
+    let announce = |note: &str, public: bool, category: Value, tags: Value| {
+        client
+            .put("/api/v0")
+            .body(
+                json!({
+                    "type": "announcement",
+                    "method": "new",
+                    "user": "Frank Nord",
+                    "note": note,
+                    "from": FUTURE,
+                    "to": FUTURE + 3600,
+                    "public": public,
+                    "url": null,
+                    "category": category,
+                    "tags": tags,
+                })
+                .to_string(),
+            )
+            .dispatch()
+    };
+    let response = announce("Party", true, json!("party"), json!([]));
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+    let response = announce("Soldering", true, json!("workshop"), json!(["a,b"]));
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+
+    let soldering = json(announce(
+        "Soldering for beginners",
+        true,
+        json!("workshop"),
+        json!(["soldering", "beginners"]),
+    ));
+    let sewing = json(announce("Sewing", false, json!("workshop"), json!([])));
+    let plenum = json(announce("Plenum", true, json!("plenum"), json!(["orga"])));
+    json(announce(
+        "Hans is coming at 8",
+        false,
+        json!(null),
+        json!([]),
+    ));
+
+    let ids = |uri: &str| {
+        get(&client, uri)["actions"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|a| a["aid"].clone())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(ids("/api/v0/announcement/current").len(), 4);
+    assert_eq!(
+        ids("/api/v0/announcement/current?category=workshop"),
+        vec![soldering.clone(), sewing]
+    );
+    assert_eq!(
+        ids("/api/v0/announcement/current?category=workshop&tag=beginners"),
+        vec![soldering.clone()]
+    );
+    assert_eq!(
+        ids("/api/v0/announcement/current?public&category=workshop"),
+        vec![soldering.clone()]
+    );
+    assert_eq!(
+        ids("/api/v0/announcement/current?public&tag=orga"),
+        vec![plenum.clone()]
+    );
+    assert_eq!(
+        get(&client, "/api/v0/announcement?category=plenum")["actions"][0]["tags"],
+        json!(["orga"])
+    );
+
+    let ics = client
+        .get("/api/v0/announcement/current.ics?category=workshop")
+        .dispatch()
+        .into_string()
+        .unwrap();
+    assert!(
+        ics.contains("CATEGORIES:workshop\r\nCATEGORIES:soldering\r\nCATEGORIES:beginners\r\n")
+    );
+    assert!(ics.contains("SUMMARY:Sewing"));
+    assert!(!ics.contains("Plenum"));
+    let public_ics = client
+        .get("/api/v0/announcement/current.ics?public&category=workshop")
+        .dispatch()
+        .into_string()
+        .unwrap();
+    assert!(public_ics.contains("SUMMARY:Soldering for beginners"));
+    assert!(!public_ics.contains("Sewing"));
+}
+
+#[test]
+fn announcement_window() {
+    let settings = super::Settings {
+        announcement_limit: Some(3),
+        ..Default::default()
+    };
+    let clock = TestClock::new(NOW);
+    let client = Client::tracked(rocket_with_settings(None, clock.clone(), settings)).unwrap();
+    let announce = |note: &str, from: i64, public: bool| {
+        put(
+            &client,
+            json!({
+                "type": "announcement",
+                "method": "new",
+                "user": "Frank Nord",
+                "note": note,
+                "from": from,
+                "to": from + 3600,
+                "public": public,
+                "url": null,
+            }),
+        )
+    };
+    let day = 86400;
+    let first = announce("First", NOW + day, true);
+    let second = announce("Second", NOW + 2 * day, false);
+    let third = announce("Third", NOW + 3 * day, true);
+    let fourth = announce("Fourth", NOW + 4 * day, true);
+    let fifth = announce("Fifth", NOW + 5 * day, true);
+    clock.set(NOW + 2 * day + 7200);
+
+    let ids = |uri: &str| {
+        get(&client, uri)["actions"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|a| a["aid"].clone())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        ids("/api/v0/announcement/current"),
+        vec![third.clone(), fourth.clone(), fifth.clone()]
+    );
+    // the configured limit caps the page size
+    assert_eq!(
+        ids("/api/v0/announcement/current?count=10&since=now-172800"),
+        vec![first.clone(), second.clone(), third.clone()]
+    );
+    assert_eq!(
+        ids("/api/v0/announcement/current?count=2&offset=1&since=now-172800"),
+        vec![second.clone(), third.clone()]
+    );
+    assert_eq!(
+        ids(&format!(
+            "/api/v0/announcement/current?since={}&until={}",
+            NOW + 2 * day,
+            NOW + 4 * day
+        )),
+        vec![second.clone(), third.clone()]
+    );
+    assert_eq!(
+        ids("/api/v0/announcement/current?public&until=now%2B172800"),
+        vec![third.clone(), fourth.clone()]
+    );
+    let response = client
+        .get("/api/v0/announcement/current?since=yesterday")
+        .dispatch();
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+
+    assert_eq!(
+        ids("/api/v0/announcement/archive"),
+        vec![second.clone(), first.clone()]
+    );
+    assert_eq!(
+        ids("/api/v0/announcement/archive?count=1&offset=1"),
+        vec![first.clone()]
+    );
+    assert_eq!(ids("/api/v0/announcement/archive?public"), vec![first]);
+
+    let ics = client
+        .get("/api/v0/announcement/current.ics?since=now-172800&until=now")
+        .dispatch()
+        .into_string()
+        .unwrap();
+    assert!(ics.contains("SUMMARY:First"));
+    assert!(ics.contains("SUMMARY:Second"));
+    assert!(!ics.contains("SUMMARY:Third"));
+    let public_ics = client
+        .get("/api/v0/announcement/current.ics?public&offset=1")
+        .dispatch()
+        .into_string()
+        .unwrap();
+    assert!(!public_ics.contains("SUMMARY:Third"));
+    assert!(public_ics.contains("SUMMARY:Fifth"));
+}
+
+#[test]
+fn status_history_ics() {
+    let settings = super::Settings {
+        status_history_range: Some(150),
+        ..Default::default()
+    };
+    let clock = TestClock::new(NOW);
+    let client = Client::tracked(rocket_with_settings(None, clock.clone(), settings)).unwrap();
+    for status in ["private", "public", "closed", "public"] {
+        clock.advance(100);
+        put_status(&client, status);
+    }
+    clock.advance(100);
+
+    let ics = |uri: &str| client.get(uri).dispatch().into_string().unwrap();
+    let all = ics("/api/v0/status/history.ics?since=0");
+    assert_eq!(all.matches("BEGIN:VEVENT").count(), 3);
+    assert!(all.contains("SUMMARY:open (private)\r\n"));
+    assert!(all.contains("DTSTART:20231114T221500Z\r\n"));
+    assert!(all.contains("DTEND:20231114T221640Z\r\n"));
+    assert!(all.contains("SUMMARY:open (public)\r\n"));
+    assert!(all.contains("DESCRIPTION:test\r\n"));
+    // the space is still open, so the last period ends now
+    assert!(all.contains("DTEND:20231114T222140Z\r\n"));
+
+    // the configured range
+    let recent = ics("/api/v0/status/history.ics");
+    assert_eq!(recent.matches("BEGIN:VEVENT").count(), 1);
+    assert!(recent.contains("DTSTART:20231114T222000Z"));
+    let early = ics(&format!(
+        "/api/v0/status/history.ics?since=0&until={}",
+        NOW + 150
+    ));
+    assert_eq!(early.matches("BEGIN:VEVENT").count(), 1);
+    assert!(early.contains("SUMMARY:open (private)"));
+
+    // private counts as closed
+    let public = ics("/api/v0/status/history.ics?public&since=0");
+    assert_eq!(public.matches("BEGIN:VEVENT").count(), 2);
+    assert_eq!(public.matches("SUMMARY:open\r\n").count(), 2);
+    assert!(public.contains("DTSTART:20231114T221640Z\r\n"));
+    assert!(public.contains("DTEND:20231114T221820Z\r\n"));
+    assert!(!public.contains("DESCRIPTION"));
+
+    // the period going on at `since` starts there
+    let clipped = ics(&format!("/api/v0/status/history.ics?since={}", NOW + 150));
+    assert_eq!(clipped.matches("BEGIN:VEVENT").count(), 3);
+    assert!(clipped.contains("SUMMARY:open (private)\r\n"));
+    assert!(clipped.contains("DTSTART:20231114T221550Z\r\n"));
+    assert!(!clipped.contains("DTSTART:20231114T221500Z\r\n"));
+    let clipped = ics(&format!(
+        "/api/v0/status/history.ics?public&since={}",
+        NOW + 250
+    ));
+    assert_eq!(clipped.matches("BEGIN:VEVENT").count(), 2);
+    assert!(clipped.contains("DTSTART:20231114T221730Z\r\n"));
+    assert!(clipped.contains("DTEND:20231114T221820Z\r\n"));
+    // but not if it ended right there
+    let exact = ics(&format!("/api/v0/status/history.ics?since={}", NOW + 200));
+    assert_eq!(exact.matches("BEGIN:VEVENT").count(), 2);
+}
+
+#[test]
+fn spaceapi() {
+    let client = client();
+    assert_json_snapshot!(get(&client, "/spaceapi"));
+
+    // private is closed for the public
+    put_status(&client, "private");
+    assert_eq!(get(&client, "/spaceapi")["state"]["open"], json!(false));
+    put_status(&client, "public");
+    let state = &get(&client, "/spaceapi")["state"];
+    assert_eq!(state["open"], json!(true));
+    assert_ne!(state["lastchange"], json!(0));
+}
+
+fn spaceapi_v15(extra: Value) -> spaceapi::Status {
+    let mut config = json!({
+        "api_compatibility": ["14", "15"],
+        "space": "Slopspace",
+        "logo": "http://your-space.org/img/logo.png",
+        "url": "http://your-space.org",
+        "location": {"lon": 9.236, "lat": 48.777},
+        "contact": {"email": "info@your-space.org"},
+    });
+    config
+        .as_object_mut()
+        .unwrap()
+        .extend(extra.as_object().unwrap().clone());
+    super::spaceapi_schema::parse(&config.to_string()).unwrap()
+}
+
+#[test]
+fn spaceapi_schema() {
+    let settings = super::Settings {
+        spaceapi_static: Some(spaceapi_v15(json!({}))),
+        zones: vec![String::from("workshop")],
+        spaceapi_presence: super::SpaceapiPresence {
+            count: true,
+            public_names: vec![],
+        },
+        spaceapi_events: Some(super::SpaceapiEvents {
+            window: 86400,
+            count: 10,
+        }),
+        ..Default::default()
+    };
+    let client =
+        Client::tracked(rocket_with_settings(None, TestClock::new(NOW), settings)).unwrap();
+    put(
+        &client,
+        json!({"type": "status", "user": "Hans Acker", "status": "public", "note": "",
+            "public_message": "open house", "until": "now+3600"}),
+    );
+    put(
+        &client,
+        json!({"type": "status", "user": "Hans Acker", "status": "public", "note": "",
+            "zone": "workshop"}),
+    );
+    put(
+        &client,
+        json!({"type": "announcement", "method": "new", "user": "Frank Nord", "note": "Talk",
+            "from": "now+60", "to": "now+3600", "public": true,
+            "url": "https://example.org/talk"}),
+    );
+    let output = get(&client, "/spaceapi");
+    assert_eq!(output["api_compatibility"], json!(["14", "15"]));
+    assert_eq!(super::spaceapi_schema::validate(&output), Ok(()));
+}
+
+#[test]
+fn spaceapi_schema_errors() {
+    let parse = |json: &str| super::spaceapi_schema::parse(json).unwrap_err();
+    assert!(parse("{").starts_with("invalid JSON: "));
+    assert!(parse(r#"{"space": "Slopspace"}"#).starts_with("missing field `logo`"));
+    assert_eq!(
+        parse(r#"{"space": "Slopspace", "projects": ["https://your-space.org", 42]}"#),
+        "projects[1]: invalid type: integer `42`, expected a string"
+    );
+
+    let settings = super::Settings {
+        spaceapi_static: Some(spaceapi_v15(json!({
+            "api_compatibility": ["15", "16"],
+            "cam": [],
+        }))),
+        ..Default::default()
+    };
+    let mut storage = SqliteStorage::open_in_memory().unwrap();
+    ensure_initialized(&mut storage).unwrap();
+    let errors = super::check_spaceapi(&mut storage, &settings, NOW).unwrap_err();
+    assert_json_snapshot!(errors);
+}
+
+#[test]
+fn spaceapi_events() {
+    let settings = super::Settings {
+        spaceapi_static: Some(spaceapi_static()),
+        spaceapi_events: Some(super::SpaceapiEvents {
+            window: 86400,
+            count: 3,
+        }),
+        ..Default::default()
+    };
+    let clock = TestClock::new(NOW);
+    let client = Client::tracked(rocket_with_settings(None, clock.clone(), settings)).unwrap();
+    let announcement = |note: &str, from: i64, public: bool| {
+        put(
+            &client,
+            json!({"type": "announcement", "method": "new", "user": "Frank Nord", "note": note,
+                "from": from, "to": from + 3600, "public": public}),
+        )
+    };
+    assert_eq!(get(&client, "/spaceapi")["events"], json!([]));
+
+    put(
+        &client,
+        json!({"type": "status", "user": "Hans Acker", "status": "public", "note": "secret",
+            "public_message": "open house"}),
+    );
+    // private is closed for the public, and not a change from closed
+    clock.advance(60);
+    put_status(&client, "private");
+    announcement("Workshop", NOW + 3600, true);
+    announcement("Board meeting", NOW + 7200, false);
+    announcement("Next month", NOW + 30 * 86400, true);
+    assert_json_snapshot!(get(&client, "/spaceapi")["events"]);
+
+    // only the newest ones
+    clock.advance(60);
+    put_status(&client, "public");
+    let events = get(&client, "/spaceapi")["events"].clone();
+    let types: Vec<_> = events
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["type"].as_str().unwrap())
+        .collect();
+    assert_eq!(types, vec!["announcement", "open", "closed"]);
+}
+
+#[test]
+fn spaceapi_people_now_present() {
+    let client = client();
+    put_status(&client, "public");
+    // not shown unless configured
+    assert_eq!(get(&client, "/spaceapi")["sensors"], json!(null));
+
+    let settings = super::Settings {
+        spaceapi_static: Some(spaceapi_static()),
+        spaceapi_presence: super::SpaceapiPresence {
+            count: true,
+            public_names: vec![],
+        },
+        ..Default::default()
+    };
+    let client =
+        Client::tracked(rocket_with_settings(None, TestClock::new(NOW), settings)).unwrap();
+    // nor while closed
+    assert_eq!(get(&client, "/spaceapi")["sensors"], json!(null));
+    put_status(&client, "public");
+    assert_eq!(
+        get(&client, "/spaceapi")["sensors"],
+        json!({"people_now_present": [{"value": 0}]})
+    );
+
+    // named users, instead of a sensor from the static data
+    let mut storage = SqliteStorage::open_in_memory().unwrap();
+    ensure_initialized(&mut storage).unwrap();
+    let user = |name: &str| PresentNamedUser {
+        name: UserName::new(name.into()),
+        since: NOW,
+        status: PresentUserStatus::Present,
+    };
+    PresenceAction::new_with_time(
+        String::new(),
+        NOW,
+        vec![user("Hans Acker"), user("Frank Nord")],
+        0.0,
+    )
+    .store(&mut storage, None)
+    .unwrap();
+    let mut spaceapi_static = spaceapi_static();
+    spaceapi_static.sensors =
+        Some(serde_json::from_value(json!({"people_now_present": [{"value": 42}]})).unwrap());
+    let settings = super::Settings {
+        spaceapi_static: Some(spaceapi_static),
+        spaceapi_presence: super::SpaceapiPresence {
+            count: true,
+            public_names: vec![UserName::new("Hans Acker".into())],
+        },
+        ..Default::default()
+    };
+    let client = Client::tracked(rocket_with_storage(
+        storage,
+        None,
+        TestClock::new(NOW),
+        settings,
+    ))
+    .unwrap();
+    put_status(&client, "public");
+    assert_eq!(
+        get(&client, "/spaceapi")["sensors"],
+        json!({"people_now_present": [{"value": 2, "names": ["Hans Acker"]}]})
+    );
+}
+
+#[test]
+fn cors() {
+    let client = client();
+    let response = client.options("/api/v0").dispatch();
+    assert_eq!(
+        response.headers().get_one("Access-Control-Allow-Origin"),
+        Some("*")
+    );
+    assert_eq!(
+        response.headers().get_one("Access-Control-Allow-Headers"),
+        Some("Authorization")
+    );
+}
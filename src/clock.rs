@@ -0,0 +1,44 @@
+use std::sync::Arc;
+#[cfg(test)]
+use std::sync::atomic::{AtomicI64, Ordering};
+
+use chrono::Utc;
+
+/// Source of the current time, so time dependent logic can be tested without sleeping.
+pub trait Clock: Send + Sync {
+    /// Current UNIX timestamp, in seconds.
+    fn now(&self) -> i64;
+}
+
+pub type SharedClock = Arc<dyn Clock>;
+
+pub struct SystemClock;
+impl Clock for SystemClock {
+    fn now(&self) -> i64 {
+        Utc::now().timestamp()
+    }
+}
+
+/// A clock that only moves when told to.
+#[cfg(test)]
+pub struct TestClock(AtomicI64);
+#[cfg(test)]
+impl TestClock {
+    pub fn new(now: i64) -> Arc<Self> {
+        Arc::new(TestClock(AtomicI64::new(now)))
+    }
+
+    pub fn set(&self, now: i64) {
+        self.0.store(now, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, seconds: i64) {
+        self.0.fetch_add(seconds, Ordering::SeqCst);
+    }
+}
+#[cfg(test)]
+impl Clock for TestClock {
+    fn now(&self) -> i64 {
+        self.0.load(Ordering::SeqCst)
+    }
+}
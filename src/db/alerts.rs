@@ -0,0 +1,263 @@
+//! Alerts when the status and the presence disagree, eg. the space is public but nobody is there.
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::api::mqtt::MqttSendQueue;
+use crate::clock::SharedClock;
+use crate::db::{DbCon, Error, Storage};
+use clubstatus_types::Status;
+
+/// How often the rules are evaluated, in seconds.
+const INTERVAL: u64 = 60;
+
+pub type SharedAlerts = Arc<Mutex<Alerts>>;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Alert {
+    /// Identifies the rule, also used in the MQTT topic.
+    pub name: &'static str,
+    /// Since when the status and the presence disagree.
+    pub since: i64,
+    pub message: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AlertChange {
+    Raised(Alert),
+    Cleared(&'static str),
+}
+
+/// Thresholds for the rules.
+pub struct AlertRules {
+    /// Seconds the status has to be public with nobody present.
+    pub open_empty_after: i64,
+    /// How many people (named and anonymous) count as occupied.
+    pub closed_occupied_people: f32,
+    /// Seconds the status has to be closed with the space occupied.
+    pub closed_occupied_after: i64,
+}
+
+impl Default for AlertRules {
+    fn default() -> Self {
+        AlertRules {
+            open_empty_after: 3600,
+            closed_occupied_people: 3.0,
+            closed_occupied_after: 600,
+        }
+    }
+}
+
+pub struct Alerts {
+    rules: AlertRules,
+    active: Vec<Alert>,
+    /// When we first saw the space occupied while closed. Presence actions are created whenever
+    /// anyone joins or leaves, so this can't be derived from the last one.
+    occupied_since: Option<i64>,
+}
+
+impl Alerts {
+    pub fn new(rules: AlertRules) -> Self {
+        Alerts {
+            rules,
+            active: Vec::new(),
+            occupied_since: None,
+        }
+    }
+
+    pub fn active(&self) -> &[Alert] {
+        &self.active
+    }
+
+    /// Evaluates the rules, and returns which alerts have been raised or cleared since the last
+    /// check.
+    pub fn check(
+        &mut self,
+        storage: &mut dyn Storage,
+        now: i64,
+    ) -> Result<Vec<AlertChange>, Error> {
+        let status = storage.get_last_status()?;
+        let presence = storage.get_last_presence()?;
+        let people = presence.users.len() as f32 + presence.anonymous_users;
+        let mut current = Vec::new();
+
+        if status.status == Status::Public && people == 0.0 {
+            let since = presence.action.time.max(status.action.time);
+            if now - since >= self.rules.open_empty_after {
+                current.push(Alert {
+                    name: "open_but_empty",
+                    since,
+                    message: format!(
+                        "status is public, but nobody has been present for {} min",
+                        (now - since) / 60
+                    ),
+                });
+            }
+        }
+
+        if status.status == Status::Closed && people >= self.rules.closed_occupied_people {
+            let since = status
+                .action
+                .time
+                .max(*self.occupied_since.get_or_insert(now));
+            if now - since >= self.rules.closed_occupied_after {
+                current.push(Alert {
+                    name: "closed_but_occupied",
+                    since,
+                    message: format!("status is closed, but {people:.1} people are present"),
+                });
+            }
+        } else if people < self.rules.closed_occupied_people {
+            self.occupied_since = None;
+        }
+
+        let mut changes = Vec::new();
+        for alert in self.active.iter() {
+            if !current.iter().any(|a| a.name == alert.name) {
+                changes.push(AlertChange::Cleared(alert.name));
+            }
+        }
+        for alert in current.iter() {
+            if !self.active.iter().any(|a| a.name == alert.name) {
+                changes.push(AlertChange::Raised(alert.clone()));
+            }
+        }
+        self.active = current;
+        Ok(changes)
+    }
+}
+
+pub fn start(
+    shared_con: Arc<Mutex<DbCon>>,
+    mqtt: Option<&MqttSendQueue>,
+    clock: SharedClock,
+    alerts: SharedAlerts,
+) {
+    let mqtt = mqtt.cloned();
+    thread::Builder::new()
+        .name(String::from("alerts"))
+        .spawn(move || {
+            loop {
+                let changes = {
+                    let mut con = shared_con.lock().unwrap();
+                    alerts.lock().unwrap().check(&mut **con, clock.now())
+                };
+                let changes = changes.unwrap_or_else(|err| {
+                    eprintln!("Error checking alerts: {err}");
+                    Vec::new()
+                });
+                for change in changes {
+                    match &change {
+                        AlertChange::Raised(alert) => println!("Alert: {}", alert.message),
+                        AlertChange::Cleared(name) => println!("Alert cleared: {name}"),
+                    }
+                    if let Some(m) = &mqtt {
+                        m.try_send_alert(change);
+                    }
+                }
+                thread::sleep(Duration::new(INTERVAL, 0));
+            }
+        })
+        .unwrap();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::sqlite::SqliteStorage;
+    use crate::db::{DbStored, ensure_initialized};
+    use clubstatus_types::{PresenceAction, StatusAction, UserName};
+
+    fn storage() -> SqliteStorage {
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+        ensure_initialized(&mut storage).unwrap();
+        storage
+    }
+
+    fn status(storage: &mut SqliteStorage, status: Status, time: i64) {
+        StatusAction::new(
+            String::new(),
+            time,
+            UserName::new("Hans Acker".into()),
+            status,
+        )
+        .store(storage, None)
+        .unwrap();
+    }
+
+    fn anonymous(storage: &mut SqliteStorage, anonymous_users: f32, time: i64) {
+        PresenceAction::new_with_time(String::new(), time, vec![], anonymous_users)
+            .store(storage, None)
+            .unwrap();
+    }
+
+    fn names(changes: &[AlertChange]) -> Vec<String> {
+        changes
+            .iter()
+            .map(|c| match c {
+                AlertChange::Raised(a) => format!("+{}", a.name),
+                AlertChange::Cleared(name) => format!("-{name}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn open_but_empty() {
+        let mut storage = storage();
+        let mut alerts = Alerts::new(AlertRules::default());
+        status(&mut storage, Status::Public, 1000);
+        assert!(alerts.check(&mut storage, 1000 + 3599).unwrap().is_empty());
+        assert_eq!(
+            alerts.check(&mut storage, 1000 + 3600).unwrap(),
+            vec![AlertChange::Raised(Alert {
+                name: "open_but_empty",
+                since: 1000,
+                message: String::from("status is public, but nobody has been present for 60 min"),
+            })]
+        );
+        // only raised once
+        assert!(alerts.check(&mut storage, 1000 + 3660).unwrap().is_empty());
+        assert_eq!(alerts.active().len(), 1);
+
+        anonymous(&mut storage, 1.0, 5000);
+        assert_eq!(
+            names(&alerts.check(&mut storage, 5000).unwrap()),
+            vec!["-open_but_empty"]
+        );
+        assert!(alerts.active().is_empty());
+    }
+
+    #[test]
+    fn closed_but_occupied() {
+        let mut storage = storage();
+        let mut alerts = Alerts::new(AlertRules::default());
+        anonymous(&mut storage, 2.0, 1000);
+        assert!(alerts.check(&mut storage, 1000).unwrap().is_empty());
+        anonymous(&mut storage, 3.0, 2000);
+        assert!(alerts.check(&mut storage, 2000).unwrap().is_empty());
+        // someone joining does not restart the timer
+        anonymous(&mut storage, 4.0, 2300);
+        assert!(alerts.check(&mut storage, 2599).unwrap().is_empty());
+        assert_eq!(
+            names(&alerts.check(&mut storage, 2600).unwrap()),
+            vec!["+closed_but_occupied"]
+        );
+        assert_eq!(alerts.active()[0].since, 2000);
+
+        status(&mut storage, Status::Private, 3000);
+        assert_eq!(
+            names(&alerts.check(&mut storage, 3000).unwrap()),
+            vec!["-closed_but_occupied"]
+        );
+        // closing again while still occupied starts the timer anew
+        status(&mut storage, Status::Closed, 4000);
+        assert!(alerts.check(&mut storage, 4599).unwrap().is_empty());
+        assert_eq!(
+            names(&alerts.check(&mut storage, 4600).unwrap()),
+            vec!["+closed_but_occupied"]
+        );
+    }
+}
@@ -0,0 +1,231 @@
+//! Notices when announcements start and end, so they can be published on MQTT at that time
+//! instead of only when they are created or changed.
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::api::mqtt::MqttSendQueue;
+use crate::clock::SharedClock;
+use crate::db::{DbCon, Error, Storage};
+use clubstatus_types::AnnouncementAction;
+
+/// How often announcements are checked, in seconds.
+const INTERVAL: u64 = 10;
+
+#[derive(Debug)]
+pub enum AnnouncementEvent {
+    Started(AnnouncementAction),
+    Ended(AnnouncementAction),
+    /// The announcement starts in this many seconds, one of the configured reminders.
+    StartsIn(i64, AnnouncementAction),
+    /// The announcements running now, whenever they change.
+    Running(Vec<AnnouncementAction>),
+}
+
+pub struct AnnouncementEvents {
+    /// Seconds before the start of an announcement to send a `StartsIn` event.
+    reminders: Vec<i64>,
+    /// Events up to this time have been sent.
+    last_check: Option<i64>,
+    /// Action ids of the running announcements at the last check.
+    running: Option<Vec<u64>>,
+}
+
+impl AnnouncementEvents {
+    pub fn new(reminders: Vec<i64>) -> Self {
+        AnnouncementEvents {
+            reminders,
+            last_check: None,
+            running: None,
+        }
+    }
+
+    /// Returns the events that happened since the last check. The first check only returns the
+    /// running announcements, so restarts don't repeat events. After an error, the next check
+    /// catches up on the missed events.
+    pub fn check(
+        &mut self,
+        storage: &mut dyn Storage,
+        now: i64,
+    ) -> Result<Vec<AnnouncementEvent>, Error> {
+        let last = self.last_check.unwrap_or(now);
+        let between = |time: i64| last < time && time <= now;
+        // includes the ones that ended since the last check
+        let announcements = storage.get_current_announcements(last)?;
+        self.last_check = Some(now);
+
+        let mut events = Vec::new();
+        for announcement in &announcements {
+            for &before in &self.reminders {
+                if between(announcement.from - before) && announcement.from > now {
+                    events.push(AnnouncementEvent::StartsIn(before, announcement.clone()));
+                }
+            }
+            if between(announcement.from) {
+                events.push(AnnouncementEvent::Started(announcement.clone()));
+            }
+            if between(announcement.to) {
+                events.push(AnnouncementEvent::Ended(announcement.clone()));
+            }
+        }
+
+        let running: Vec<AnnouncementAction> = announcements
+            .into_iter()
+            .filter(|a| a.from <= now && now < a.to)
+            .collect();
+        // modifying a running announcement changes the list as well
+        let ids: Vec<u64> = running.iter().filter_map(|a| a.action.id).collect();
+        if self.running.as_ref() != Some(&ids) {
+            self.running = Some(ids);
+            events.push(AnnouncementEvent::Running(running));
+        }
+        Ok(events)
+    }
+}
+
+pub fn start(
+    shared_con: Arc<Mutex<DbCon>>,
+    mqtt: &MqttSendQueue,
+    clock: SharedClock,
+    mut events: AnnouncementEvents,
+) {
+    let mqtt = mqtt.clone();
+    thread::Builder::new()
+        .name(String::from("announcement_events"))
+        .spawn(move || {
+            loop {
+                let changes = {
+                    let mut con = shared_con.lock().unwrap();
+                    events.check(&mut **con, clock.now())
+                };
+                let changes = changes.unwrap_or_else(|err| {
+                    eprintln!("Error checking announcements: {err}");
+                    Vec::new()
+                });
+                for event in changes {
+                    mqtt.try_send_announcement_event(event);
+                }
+                thread::sleep(Duration::new(INTERVAL, 0));
+            }
+        })
+        .unwrap();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::sqlite::SqliteStorage;
+    use crate::db::{DbStored, ensure_initialized};
+    use clubstatus_types::{AnnouncementMethod, BaseAction, UserName};
+
+    const FROM: i64 = 1700161200;
+
+    fn announce(storage: &mut dyn Storage, from: i64, to: i64) -> u64 {
+        AnnouncementAction {
+            action: BaseAction::new_with_time(String::from("Plenum"), FROM - 86400),
+            method: AnnouncementMethod::New,
+            aid: None,
+            user: UserName::new("plenumsbot".into()),
+            from,
+            to,
+            public: true,
+            url: None,
+            series: None,
+            recurrence_id: None,
+            category: None,
+            tags: Vec::new(),
+            description: None,
+            resources: Vec::new(),
+        }
+        .store(storage, None)
+        .unwrap()
+    }
+
+    /// (kind, aid) of the events, `Running` with the running aids.
+    fn summary(events: &[AnnouncementEvent]) -> Vec<(String, Vec<u64>)> {
+        events
+            .iter()
+            .map(|e| match e {
+                AnnouncementEvent::Started(a) => (String::from("started"), vec![a.aid.unwrap()]),
+                AnnouncementEvent::Ended(a) => (String::from("ended"), vec![a.aid.unwrap()]),
+                AnnouncementEvent::StartsIn(s, a) => {
+                    (format!("starts_in/{s}"), vec![a.aid.unwrap()])
+                }
+                AnnouncementEvent::Running(list) => (
+                    String::from("running"),
+                    list.iter().map(|a| a.aid.unwrap()).collect(),
+                ),
+            })
+            .collect()
+    }
+
+    fn event(kind: &str, aids: &[u64]) -> (String, Vec<u64>) {
+        (String::from(kind), aids.to_vec())
+    }
+
+    #[test]
+    fn starts_ends_and_reminders() {
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+        ensure_initialized(&mut storage).unwrap();
+        let plenum = announce(&mut storage, FROM, FROM + 3600);
+        let workshop = announce(&mut storage, FROM + 1800, FROM + 7200);
+        let mut events = AnnouncementEvents::new(vec![900]);
+
+        assert_eq!(
+            summary(&events.check(&mut storage, FROM - 1000).unwrap()),
+            vec![event("running", &[])]
+        );
+        assert!(events.check(&mut storage, FROM - 950).unwrap().is_empty());
+        assert_eq!(
+            summary(&events.check(&mut storage, FROM - 900).unwrap()),
+            vec![event("starts_in/900", &[plenum])]
+        );
+        // checks can be late
+        assert_eq!(
+            summary(&events.check(&mut storage, FROM + 1).unwrap()),
+            vec![event("started", &[plenum]), event("running", &[plenum])]
+        );
+        // the reminder is too late once the workshop started
+        assert_eq!(
+            summary(&events.check(&mut storage, FROM + 1800).unwrap()),
+            vec![
+                event("started", &[workshop]),
+                event("running", &[plenum, workshop]),
+            ]
+        );
+        assert_eq!(
+            summary(&events.check(&mut storage, FROM + 3600).unwrap()),
+            vec![event("ended", &[plenum]), event("running", &[workshop])]
+        );
+        assert!(events.check(&mut storage, FROM + 3610).unwrap().is_empty());
+        assert_eq!(
+            summary(&events.check(&mut storage, FROM + 9000).unwrap()),
+            vec![event("ended", &[workshop]), event("running", &[])]
+        );
+    }
+
+    #[test]
+    fn first_check_does_not_repeat_events() {
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+        ensure_initialized(&mut storage).unwrap();
+        let plenum = announce(&mut storage, FROM, FROM + 3600);
+        let mut events = AnnouncementEvents::new(vec![]);
+        assert_eq!(
+            summary(&events.check(&mut storage, FROM + 10).unwrap()),
+            vec![event("running", &[plenum])]
+        );
+
+        // changing a running announcement updates the list
+        let mut longer = storage.get_last_announcement(plenum).unwrap().unwrap();
+        longer.action = BaseAction::new_with_time(String::from("Plenum"), FROM + 20);
+        longer.method = AnnouncementMethod::Mod;
+        longer.to += 1800;
+        longer.store(&mut storage, None).unwrap();
+        assert_eq!(
+            summary(&events.check(&mut storage, FROM + 30).unwrap()),
+            vec![event("running", &[plenum])]
+        );
+        assert!(events.check(&mut storage, FROM + 3600).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,205 @@
+//! Closes the space when nobody has been present for a while, because the last one to leave forgot
+//! to.
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::api::mqtt::MqttSendQueue;
+use crate::clock::SharedClock;
+use crate::db::{DbCon, DbStored, Error, SYSTEM_USER, Storage};
+use clubstatus_types::{Status, StatusAction, UserName};
+
+/// How often to check whether the space is empty, in seconds.
+const INTERVAL: u64 = 60;
+
+pub struct AutoClose {
+    /// Seconds without any presence before closing.
+    grace_period: i64,
+    /// Only warn instead of closing.
+    dry_run: bool,
+    /// Last status action we warned about in dry run mode, to only warn once.
+    warned_for: Option<u64>,
+}
+
+impl AutoClose {
+    pub fn new(grace_period: i64, dry_run: bool) -> Self {
+        AutoClose {
+            grace_period,
+            dry_run,
+            warned_for: None,
+        }
+    }
+
+    /// Closes the space if the status is public or private, and neither named nor anonymous users
+    /// have been present for the grace period. Returns the id of the created status action.
+    ///
+    /// The grace period starts with the last presence or status action, whichever is newer, so
+    /// opening the space without presence tracking does not close it right away.
+    pub fn check(
+        &mut self,
+        storage: &mut dyn Storage,
+        mqtt: Option<&MqttSendQueue>,
+        now: i64,
+    ) -> Result<Option<u64>, Error> {
+        let status = storage.get_last_status()?;
+        if status.status == Status::Closed {
+            return Ok(None);
+        }
+        let presence = storage.get_last_presence()?;
+        if !presence.users.is_empty() || presence.anonymous_users > 0.0 {
+            return Ok(None);
+        }
+        let empty_since = presence.action.time.max(status.action.time);
+        if now < empty_since + self.grace_period {
+            return Ok(None);
+        }
+
+        let minutes = (now - empty_since) / 60;
+        if self.dry_run {
+            if self.warned_for != status.action.id {
+                eprintln!(
+                    "Warning: status is still {:?}, but nobody has been present for {minutes} \
+                     min (auto_close.dry_run is set, not closing)",
+                    status.status
+                );
+                self.warned_for = status.action.id;
+            }
+            return Ok(None);
+        }
+        Ok(StatusAction::new(
+            format!("closed automatically, nobody present for {minutes} min"),
+            now,
+            UserName::new(SYSTEM_USER.into()),
+            Status::Closed,
+        )
+        .store(storage, mqtt))
+    }
+}
+
+pub fn start(
+    shared_con: Arc<Mutex<DbCon>>,
+    mqtt: Option<&MqttSendQueue>,
+    clock: SharedClock,
+    mut auto_close: AutoClose,
+) {
+    let mqtt = mqtt.cloned();
+    thread::Builder::new()
+        .name(String::from("auto_close"))
+        .spawn(move || {
+            loop {
+                {
+                    let mut con = shared_con.lock().unwrap();
+                    if let Err(err) = auto_close.check(&mut **con, mqtt.as_ref(), clock.now()) {
+                        eprintln!("Error checking whether to close automatically: {err}");
+                    }
+                }
+                thread::sleep(Duration::new(INTERVAL, 0));
+            }
+        })
+        .unwrap();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::ensure_initialized;
+    use crate::db::sqlite::SqliteStorage;
+    use clubstatus_types::{PresenceAction, PresentNamedUser, PresentUserStatus};
+
+    const GRACE: i64 = 3600;
+
+    fn storage_with_status(status: Status, time: i64) -> SqliteStorage {
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+        ensure_initialized(&mut storage).unwrap();
+        StatusAction::new(
+            String::new(),
+            time,
+            UserName::new("Hans Acker".into()),
+            status,
+        )
+        .store(&mut storage, None)
+        .unwrap();
+        storage
+    }
+
+    fn presence(storage: &mut SqliteStorage, time: i64, users: &[&str], anonymous_users: f32) {
+        let users = users
+            .iter()
+            .map(|name| PresentNamedUser {
+                name: UserName::new(name.to_string()),
+                since: time,
+                status: PresentUserStatus::Present,
+            })
+            .collect();
+        PresenceAction::new_with_time(String::new(), time, users, anonymous_users)
+            .store(storage, None)
+            .unwrap();
+    }
+
+    #[test]
+    fn closes_after_grace_period() {
+        let mut storage = storage_with_status(Status::Public, 1000);
+        let mut auto_close = AutoClose::new(GRACE, false);
+        assert_eq!(
+            auto_close
+                .check(&mut storage, None, 1000 + GRACE - 1)
+                .unwrap(),
+            None
+        );
+
+        // someone was there, and left
+        presence(&mut storage, 2000, &["Hans Acker"], 0.0);
+        assert_eq!(
+            auto_close.check(&mut storage, None, 2000 + GRACE).unwrap(),
+            None
+        );
+        presence(&mut storage, 3000, &[], 0.0);
+        assert_eq!(
+            auto_close
+                .check(&mut storage, None, 3000 + GRACE - 1)
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            auto_close.check(&mut storage, None, 3000 + GRACE).unwrap(),
+            Some(6)
+        );
+        let last = storage.get_last_status().unwrap();
+        assert_eq!(last.status, Status::Closed);
+        assert_eq!(last.user.as_str(), SYSTEM_USER);
+        assert_eq!(
+            last.action.note,
+            "closed automatically, nobody present for 60 min"
+        );
+        assert_eq!(
+            auto_close.check(&mut storage, None, 4000 + GRACE).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn anonymous_presence_keeps_open() {
+        let mut storage = storage_with_status(Status::Private, 1000);
+        presence(&mut storage, 1000, &[], 0.5);
+        let mut auto_close = AutoClose::new(GRACE, false);
+        assert_eq!(
+            auto_close
+                .check(&mut storage, None, 1000 + 10 * GRACE)
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn dry_run_does_not_close() {
+        let mut storage = storage_with_status(Status::Public, 1000);
+        let mut auto_close = AutoClose::new(GRACE, true);
+        assert_eq!(
+            auto_close.check(&mut storage, None, 1000 + GRACE).unwrap(),
+            None
+        );
+        assert_eq!(auto_close.warned_for, Some(3));
+        assert_eq!(storage.get_last_status().unwrap().status, Status::Public);
+    }
+}
@@ -0,0 +1,1112 @@
+//! Tests every storage backend has to pass.
+//!
+//! Backends instantiate them with `conformance_tests!(|| <fresh, empty storage>)`.
+
+use chrono::Utc;
+
+use crate::api::{AnnouncementFilter, IdExpr, RangeExpr, Take};
+use crate::db::{
+    AnnouncementWindow, DbStored, Error, SCHEMA_VERSION, Storage, current_keyholder,
+    ensure_initialized, overlapping_bookings, resource_bookings,
+};
+use crate::model::QueryActionType;
+use clubstatus_types::{
+    AnnouncementAction, AnnouncementMethod, AnnouncementSeries, BaseAction, HandoverAction,
+    PresenceAction, PresentNamedUser, PresentUserStatus, RsvpAction, RsvpAnswer, ScheduledStatus,
+    Status, StatusAction, TypedAction, UserName,
+};
+
+macro_rules! conformance_tests {
+    ($factory:expr) => {
+        $crate::db::conformance::conformance_tests!(
+            $factory;
+            initialized,
+            interrupted_initialization,
+            migrations_rerun,
+            status_changed_flags,
+            public_status_changes,
+            status_get_by_id,
+            status_revert,
+            scheduled_status,
+            keyholder_handover,
+            zone_status,
+            announcement_lifecycle,
+            announcement_current,
+            announcement_window,
+            announcement_series,
+            announcement_categories,
+            announcement_resources,
+            sent_reminders,
+            rsvp,
+            presence_roundtrip,
+            query_filters,
+        );
+    };
+    // the factory returns a pointer to the storage, which can clean up after the test when dropped
+    ($factory:expr; $($name:ident),* $(,)?) => {
+        $(
+            #[test]
+            fn $name() {
+                let mut storage = ($factory)();
+                $crate::db::conformance::$name(&mut *storage);
+            }
+        )*
+    };
+}
+pub(crate) use conformance_tests;
+
+fn status(storage: &mut dyn Storage, status: Status) -> StatusAction {
+    let mut action = StatusAction::new(
+        String::from("note"),
+        Utc::now().timestamp(),
+        UserName::new("Hans Acker".into()),
+        status,
+    );
+    action.store(storage, None).unwrap();
+    action
+}
+
+fn announcement(from: i64, to: i64, public: bool) -> AnnouncementAction {
+    AnnouncementAction {
+        action: BaseAction::new_with_time(String::from("announcement"), Utc::now().timestamp()),
+        method: AnnouncementMethod::New,
+        aid: None,
+        user: UserName::new("Frank Nord".into()),
+        from,
+        to,
+        public,
+        url: None,
+        series: None,
+        recurrence_id: None,
+        category: None,
+        tags: Vec::new(),
+        description: None,
+        resources: Vec::new(),
+    }
+}
+
+pub(crate) fn initialized(storage: &mut dyn Storage) {
+    assert!(!storage.is_initialized().unwrap());
+    ensure_initialized(storage).unwrap();
+    assert!(storage.is_initialized().unwrap());
+    // running it again must not insert the initial actions again
+    ensure_initialized(storage).unwrap();
+
+    let last = storage.get_last_status().unwrap();
+    assert_eq!(last.status, Status::Closed);
+    assert_eq!(last.action.id, Some(1));
+    let presence = storage.get_last_presence().unwrap();
+    assert_eq!(presence.action.id, Some(2));
+    assert!(presence.users.is_empty());
+}
+
+pub(crate) fn interrupted_initialization(storage: &mut dyn Storage) {
+    // the tables have been created, but not the initial actions
+    storage.create_tables().unwrap();
+    ensure_initialized(storage).unwrap();
+    assert_eq!(storage.get_last_status().unwrap().status, Status::Closed);
+    assert!(storage.get_last_presence().unwrap().users.is_empty());
+}
+
+pub(crate) fn migrations_rerun(storage: &mut dyn Storage) {
+    ensure_initialized(storage).unwrap();
+    assert_eq!(storage.schema_version().unwrap(), SCHEMA_VERSION);
+    // the steps skip what the database has already
+    for version in 1..=SCHEMA_VERSION {
+        storage.migrate(version).unwrap();
+    }
+    assert_eq!(storage.schema_version().unwrap(), SCHEMA_VERSION);
+    let public = status(storage, Status::Public);
+    assert_eq!(
+        storage.get_last_status().unwrap().action.id,
+        public.action.id
+    );
+}
+
+pub(crate) fn status_changed_flags(storage: &mut dyn Storage) {
+    ensure_initialized(storage).unwrap();
+    let initial = storage.get_last_status().unwrap();
+
+    let private = status(storage, Status::Private);
+    assert_eq!(
+        storage.get_last_status().unwrap().action.id,
+        private.action.id
+    );
+    assert_eq!(
+        storage
+            .get_last_changed_zone_status(None)
+            .unwrap()
+            .action
+            .id,
+        private.action.id
+    );
+    assert_eq!(
+        storage.get_last_changed_public_status().unwrap().action.id,
+        initial.action.id
+    );
+
+    let public = status(storage, Status::Public);
+    let public_again = status(storage, Status::Public);
+    assert_eq!(
+        storage.get_last_status().unwrap().action.id,
+        public_again.action.id
+    );
+    assert_eq!(
+        storage
+            .get_last_changed_zone_status(None)
+            .unwrap()
+            .action
+            .id,
+        public.action.id
+    );
+    assert_eq!(
+        storage.get_last_changed_public_status().unwrap().action.id,
+        public.action.id
+    );
+}
+
+pub(crate) fn public_status_changes(storage: &mut dyn Storage) {
+    ensure_initialized(storage).unwrap();
+    let mut at = |time: i64, status: Status, zone: Option<&str>| {
+        let mut action = StatusAction {
+            zone: zone.map(String::from),
+            ..StatusAction::new(
+                String::new(),
+                time,
+                UserName::new("Hans Acker".into()),
+                status,
+            )
+        };
+        action.store(storage, None).unwrap()
+    };
+    let opened = at(100, Status::Public, None);
+    let closed = at(200, Status::Private, None);
+    let closing = at(300, Status::Closed, None);
+    at(400, Status::Public, Some("workshop"));
+    let public = at(500, Status::Public, None);
+    let ids = |storage: &mut dyn Storage, since, count| {
+        storage
+            .get_public_status_changes(since, count)
+            .unwrap()
+            .iter()
+            .map(|s| s.action.id.unwrap())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(ids(storage, 150, 10), vec![public, closed]);
+    assert_eq!(ids(storage, 150, 1), vec![public]);
+    assert_eq!(ids(storage, 0, 10).len(), 4);
+
+    // all changes, also between private and closed
+    let changes = storage
+        .get_status_changes(150, 10)
+        .unwrap()
+        .iter()
+        .map(|s| s.action.id.unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(changes, vec![public, closing, closed]);
+
+    // the changes the status at a time comes from
+    let before = |result: Result<StatusAction, Error>| result.unwrap().action.id.unwrap();
+    assert_eq!(before(storage.get_status_change_before(150)), opened);
+    assert_eq!(before(storage.get_status_change_before(300)), closed);
+    assert_eq!(before(storage.get_status_change_before(301)), closing);
+    assert_eq!(before(storage.get_public_status_change_before(450)), closed);
+    assert!(matches!(
+        storage.get_status_change_before(0),
+        Err(Error::NotFound)
+    ));
+}
+
+pub(crate) fn status_get_by_id(storage: &mut dyn Storage) {
+    ensure_initialized(storage).unwrap();
+    let stored = status(storage, Status::Public);
+    let loaded = storage.get_status(stored.action.id.unwrap()).unwrap();
+    assert_eq!(loaded.action.time, stored.action.time);
+    assert_eq!(loaded.action.note, stored.action.note);
+    assert_eq!(loaded.user, stored.user);
+    assert_eq!(loaded.status, Status::Public);
+    assert_eq!((loaded.until, loaded.revert_to), (None, None));
+
+    let mut until = StatusAction {
+        until: Some(1234),
+        revert_to: Some(Status::Private),
+        public_message: Some(String::from("open house")),
+        ..StatusAction::new(
+            String::new(),
+            1000,
+            UserName::new("Hans Acker".into()),
+            Status::Public,
+        )
+    };
+    until.store(storage, None).unwrap();
+    let loaded = storage.get_last_status().unwrap();
+    assert_eq!(loaded.until, Some(1234));
+    assert_eq!(loaded.revert_to, Some(Status::Private));
+    assert_eq!(loaded.public_message.as_deref(), Some("open house"));
+
+    assert!(matches!(
+        storage.get_status(12345),
+        Err(crate::db::Error::NotFound)
+    ));
+}
+
+pub(crate) fn status_revert(storage: &mut dyn Storage) {
+    ensure_initialized(storage).unwrap();
+    let mut public = StatusAction {
+        until: Some(1234),
+        revert_to: Some(Status::Private),
+        public_message: Some(String::from("open house")),
+        ..StatusAction::new(
+            String::new(),
+            Utc::now().timestamp(),
+            UserName::new("Hans Acker".into()),
+            Status::Public,
+        )
+    };
+    public.store(storage, None).unwrap();
+    let closed = status(storage, Status::Closed);
+    let revert = |storage: &mut dyn Storage, id: Option<u64>| {
+        let mut revert = StatusAction {
+            reverts: id,
+            ..StatusAction::new(
+                String::from("mis-tap"),
+                Utc::now().timestamp(),
+                UserName::new("Hans Acker".into()),
+                Status::Closed,
+            )
+        };
+        revert.store(storage, None)
+    };
+
+    // only the last status action can be reverted
+    assert_eq!(revert(storage, public.action.id), None);
+    let reverted = revert(storage, closed.action.id).unwrap();
+    let last = storage.get_last_status().unwrap();
+    assert_eq!(last.action.id, Some(reverted));
+    assert_eq!(last.reverts, closed.action.id);
+    assert_eq!(last.status, Status::Public);
+    assert_eq!(
+        (last.until, last.revert_to),
+        (Some(1234), Some(Status::Private))
+    );
+    assert_eq!(last.public_message.as_deref(), Some("open house"));
+    // neither the reverted action nor the revert count as a change
+    assert_eq!(
+        storage
+            .get_last_changed_zone_status(None)
+            .unwrap()
+            .action
+            .id,
+        public.action.id
+    );
+    assert_eq!(
+        storage.get_last_changed_public_status().unwrap().action.id,
+        public.action.id
+    );
+    assert_eq!(
+        storage.get_last_status_before(reverted).unwrap().action.id,
+        public.action.id
+    );
+    // a revert can't be reverted
+    assert_eq!(revert(storage, Some(reverted)), None);
+
+    let private = status(storage, Status::Private);
+    assert_eq!(
+        storage
+            .get_last_changed_zone_status(None)
+            .unwrap()
+            .action
+            .id,
+        private.action.id
+    );
+    assert_eq!(
+        storage.get_last_changed_public_status().unwrap().action.id,
+        private.action.id
+    );
+}
+
+pub(crate) fn scheduled_status(storage: &mut dyn Storage) {
+    ensure_initialized(storage).unwrap();
+    let mut scheduled = |at, status| {
+        let mut scheduled = ScheduledStatus {
+            id: None,
+            time: 10,
+            at,
+            note: String::from("workshop"),
+            user: UserName::new("Hans Acker".into()),
+            status,
+            until: Some(at + 60),
+            revert_to: Some(Status::Private),
+            keyholder: Some(UserName::new("Frank Nord".into())),
+            public_message: Some(String::from("open workshop")),
+        };
+        storage.insert_scheduled_status(&mut scheduled).unwrap();
+        scheduled
+    };
+    let later = scheduled(200, Status::Closed);
+    let sooner = scheduled(100, Status::Public);
+    assert_ne!(later.id, sooner.id);
+
+    let loaded = storage.get_scheduled_statuses().unwrap();
+    let ids: Vec<_> = loaded.iter().map(|s| s.id).collect();
+    assert_eq!(ids, vec![sooner.id, later.id]);
+    assert_eq!((loaded[0].time, loaded[0].at), (10, 100));
+    assert_eq!(loaded[0].note, "workshop");
+    assert_eq!(loaded[0].user.as_str(), "Hans Acker");
+    assert_eq!(loaded[0].status, Status::Public);
+    assert_eq!(loaded[0].until, Some(160));
+    assert_eq!(loaded[0].revert_to, Some(Status::Private));
+    assert_eq!(
+        loaded[0].keyholder,
+        Some(UserName::new("Frank Nord".into()))
+    );
+    assert_eq!(loaded[0].public_message.as_deref(), Some("open workshop"));
+
+    assert!(storage.delete_scheduled_status(sooner.id.unwrap()).unwrap());
+    assert!(!storage.delete_scheduled_status(sooner.id.unwrap()).unwrap());
+    let ids: Vec<_> = storage
+        .get_scheduled_statuses()
+        .unwrap()
+        .iter()
+        .map(|s| s.id)
+        .collect();
+    assert_eq!(ids, vec![later.id]);
+    // scheduled changes are not actions
+    assert_eq!(storage.get_last_status().unwrap().action.id, Some(1));
+}
+
+pub(crate) fn keyholder_handover(storage: &mut dyn Storage) {
+    ensure_initialized(storage).unwrap();
+    let name = |name: &str| Some(UserName::new(name.into()));
+    let handover = |storage: &mut dyn Storage, to: &str| {
+        HandoverAction {
+            action: BaseAction::new_with_time(String::new(), Utc::now().timestamp()),
+            user: UserName::new("Hans Acker".into()),
+            from: None,
+            to: UserName::new(to.into()),
+        }
+        .store(storage, None)
+    };
+
+    // nobody holds the key while the space is closed
+    assert_eq!(handover(storage, "Frank Nord"), None);
+    assert!(storage.get_last_handover().unwrap().is_none());
+    assert_eq!(current_keyholder(storage).unwrap(), None);
+
+    // whoever opens is responsible
+    let private = status(storage, Status::Private);
+    assert_eq!(private.keyholder, name("Hans Acker"));
+    let id = handover(storage, "Frank Nord").unwrap();
+    let loaded = storage.get_handover(id).unwrap();
+    assert_eq!(
+        (loaded.from, Some(loaded.to)),
+        (name("Hans Acker"), name("Frank Nord"))
+    );
+    assert_eq!(
+        storage.get_last_handover().unwrap().unwrap().action.id,
+        Some(id)
+    );
+    assert_eq!(current_keyholder(storage).unwrap(), name("Frank Nord"));
+
+    // switching to public keeps the keyholder, closing clears it
+    let public = status(storage, Status::Public);
+    let loaded = storage.get_status(public.action.id.unwrap()).unwrap();
+    assert_eq!(loaded.keyholder, name("Frank Nord"));
+    let closed = status(storage, Status::Closed);
+    assert_eq!(closed.keyholder, None);
+    assert_eq!(current_keyholder(storage).unwrap(), None);
+
+    let mut named = StatusAction {
+        keyholder: name("Frank Nord"),
+        ..StatusAction::new(
+            String::new(),
+            Utc::now().timestamp(),
+            UserName::new("Hans Acker".into()),
+            Status::Public,
+        )
+    };
+    named.store(storage, None).unwrap();
+    assert_eq!(current_keyholder(storage).unwrap(), name("Frank Nord"));
+}
+
+pub(crate) fn zone_status(storage: &mut dyn Storage) {
+    ensure_initialized(storage).unwrap();
+    let initial = storage.get_last_status().unwrap();
+    let zone = |storage: &mut dyn Storage, status: Status| {
+        let mut action = StatusAction {
+            zone: Some(String::from("workshop")),
+            ..StatusAction::new(
+                String::new(),
+                Utc::now().timestamp(),
+                UserName::new("Hans Acker".into()),
+                status,
+            )
+        };
+        action.store(storage, None).unwrap()
+    };
+    assert!(matches!(
+        storage.get_last_zone_status(Some("workshop")),
+        Err(crate::db::Error::NotFound)
+    ));
+
+    let public = zone(storage, Status::Public);
+    zone(storage, Status::Public);
+    let changed = |storage: &mut dyn Storage| {
+        let changed = storage.get_last_changed_zone_status(Some("workshop"));
+        let public = storage.get_last_changed_public_zone_status(Some("workshop"));
+        (changed.unwrap().action.id, public.unwrap().action.id)
+    };
+    assert_eq!(changed(storage), (Some(public), Some(public)));
+    let private = zone(storage, Status::Private);
+    let last = storage.get_last_zone_status(Some("workshop")).unwrap();
+    assert_eq!(last.action.id, Some(private));
+    assert_eq!(last.zone.as_deref(), Some("workshop"));
+    assert_eq!(last.keyholder, None);
+    assert_eq!(changed(storage), (Some(private), Some(private)));
+    let closed = zone(storage, Status::Closed);
+    assert_eq!(changed(storage), (Some(closed), Some(private)));
+
+    // the status of the whole space is not affected
+    assert_eq!(
+        storage.get_last_status().unwrap().action.id,
+        initial.action.id
+    );
+    assert_eq!(
+        storage
+            .get_last_changed_zone_status(None)
+            .unwrap()
+            .action
+            .id,
+        initial.action.id
+    );
+    let global = status(storage, Status::Public);
+    assert_eq!(
+        storage
+            .get_last_zone_status(Some("workshop"))
+            .unwrap()
+            .status,
+        Status::Closed
+    );
+    assert_eq!(
+        storage.get_last_status().unwrap().action.id,
+        global.action.id
+    );
+}
+
+pub(crate) fn announcement_lifecycle(storage: &mut dyn Storage) {
+    ensure_initialized(storage).unwrap();
+    let now = Utc::now().timestamp();
+
+    let mut new = announcement(now + 10, now + 20, true);
+    new.url = Some("https://example.org/".parse().unwrap());
+    new.description = Some("Bring your own laptop.\n".repeat(300));
+    let id = new.store(storage, None).unwrap();
+    assert_eq!(new.aid, Some(id));
+    let loaded = storage.get_announcement(id).unwrap();
+    assert_eq!(loaded.method, AnnouncementMethod::New);
+    assert_eq!(loaded.aid, Some(id));
+    assert_eq!((loaded.from, loaded.to), (now + 10, now + 20));
+    assert!(loaded.public);
+    assert_eq!(loaded.url, new.url);
+    assert_eq!(loaded.description, new.description);
+
+    let mut modified = announcement(now + 30, now + 40, false);
+    modified.method = AnnouncementMethod::Mod;
+    modified.aid = Some(id);
+    modified.description = Some(String::from("Laptops are provided."));
+    let mod_id = modified.store(storage, None).unwrap();
+    let last = storage.get_last_announcement(id).unwrap().unwrap();
+    assert_eq!(last.action.id, Some(mod_id));
+    assert_eq!(last.method, AnnouncementMethod::Mod);
+    assert_eq!((last.from, last.to), (now + 30, now + 40));
+
+    let mut deleted = announcement(0, 0, true);
+    deleted.method = AnnouncementMethod::Del;
+    deleted.aid = Some(id);
+    deleted.action.note = String::new();
+    deleted.store(storage, None).unwrap();
+    let last = storage.get_last_announcement(id).unwrap().unwrap();
+    assert_eq!(last.method, AnnouncementMethod::Del);
+    // deleted announcements keep the values from before the deletion
+    assert_eq!(last.action.note, "announcement");
+    assert_eq!((last.from, last.to), (now + 30, now + 40));
+    assert!(!last.public);
+    assert_eq!(last.description.as_deref(), Some("Laptops are provided."));
+
+    // deleted announcements can neither be modified nor deleted again
+    let mut modified = announcement(now, now, false);
+    modified.method = AnnouncementMethod::Mod;
+    modified.aid = Some(id);
+    assert_eq!(modified.store(storage, None), None);
+    let mut deleted = announcement(0, 0, false);
+    deleted.method = AnnouncementMethod::Del;
+    deleted.aid = Some(id);
+    assert_eq!(deleted.store(storage, None), None);
+
+    // unknown announcement
+    let mut modified = announcement(now, now, false);
+    modified.method = AnnouncementMethod::Mod;
+    modified.aid = Some(12345);
+    assert_eq!(modified.store(storage, None), None);
+    assert!(storage.get_last_announcement(12345).unwrap().is_none());
+}
+
+pub(crate) fn announcement_current(storage: &mut dyn Storage) {
+    ensure_initialized(storage).unwrap();
+    let now = Utc::now().timestamp();
+
+    let mut past = announcement(now - 20, now - 10, true);
+    past.store(storage, None).unwrap();
+    let mut later = announcement(now + 100, now + 200, false);
+    later.store(storage, None).unwrap();
+    let mut running = announcement(now - 100, now + 100, true);
+    running.store(storage, None).unwrap();
+    let mut deleted = announcement(now, now + 100, true);
+    deleted.store(storage, None).unwrap();
+    let mut del = announcement(0, 0, false);
+    del.method = AnnouncementMethod::Del;
+    del.aid = deleted.aid;
+    del.store(storage, None).unwrap();
+
+    let current: Vec<_> = storage
+        .get_current_announcements(now)
+        .unwrap()
+        .into_iter()
+        .map(|a| a.aid)
+        .collect();
+    assert_eq!(current, vec![running.aid, later.aid]);
+    let current_public: Vec<_> = storage
+        .get_current_public_announcements(now)
+        .unwrap()
+        .into_iter()
+        .map(|a| a.aid)
+        .collect();
+    assert_eq!(current_public, vec![running.aid]);
+}
+
+pub(crate) fn announcement_window(storage: &mut dyn Storage) {
+    ensure_initialized(storage).unwrap();
+    let now = Utc::now().timestamp();
+
+    let mut old = announcement(now - 300, now - 200, true);
+    old.store(storage, None).unwrap();
+    let mut past = announcement(now - 100, now - 10, false);
+    past.store(storage, None).unwrap();
+    let mut running = announcement(now - 100, now + 100, true);
+    running.store(storage, None).unwrap();
+    let mut later = AnnouncementAction {
+        category: Some(String::from("workshop")),
+        ..announcement(now + 100, now + 200, true)
+    };
+    later.store(storage, None).unwrap();
+    let mut much_later = announcement(now + 1000, now + 2000, false);
+    much_later.store(storage, None).unwrap();
+
+    let mut aids = |window: &AnnouncementWindow, filter: &AnnouncementFilter| -> Vec<_> {
+        storage
+            .get_announcements(window, filter)
+            .unwrap()
+            .into_iter()
+            .map(|a| a.aid)
+            .collect()
+    };
+    let no_filter = AnnouncementFilter::default();
+    let all = AnnouncementWindow::default();
+    assert_eq!(
+        aids(&all, &no_filter),
+        vec![old.aid, past.aid, running.aid, later.aid, much_later.aid]
+    );
+    let window = AnnouncementWindow {
+        since: Some(now - 150),
+        until: Some(now + 1000),
+        ..Default::default()
+    };
+    assert_eq!(
+        aids(&window, &no_filter),
+        vec![past.aid, running.aid, later.aid]
+    );
+    let page = AnnouncementWindow {
+        offset: 1,
+        limit: Some(2),
+        ..window.clone()
+    };
+    assert_eq!(aids(&page, &no_filter), vec![running.aid, later.aid]);
+    let public = AnnouncementWindow {
+        public: true,
+        ..window
+    };
+    assert_eq!(aids(&public, &no_filter), vec![running.aid, later.aid]);
+    let workshops = AnnouncementFilter {
+        category: Some(String::from("workshop")),
+        tag: None,
+    };
+    assert_eq!(aids(&all, &workshops), vec![later.aid]);
+
+    // the archive lists the most recently ended announcements first
+    let archive = AnnouncementWindow {
+        ended_before: Some(now),
+        ..Default::default()
+    };
+    assert_eq!(aids(&archive, &no_filter), vec![past.aid, old.aid]);
+    let archive_page = AnnouncementWindow {
+        offset: 1,
+        limit: Some(1),
+        ..archive
+    };
+    assert_eq!(aids(&archive_page, &no_filter), vec![old.aid]);
+}
+
+pub(crate) fn announcement_categories(storage: &mut dyn Storage) {
+    ensure_initialized(storage).unwrap();
+    let now = Utc::now().timestamp();
+    let tags = |tags: &[&str]| tags.iter().map(|&t| String::from(t)).collect::<Vec<_>>();
+    let mut workshop = AnnouncementAction {
+        category: Some(String::from("workshop")),
+        tags: tags(&["soldering", "beginners"]),
+        ..announcement(now + 100, now + 200, true)
+    };
+    workshop.store(storage, None).unwrap();
+    let mut plenum = AnnouncementAction {
+        category: Some(String::from("plenum")),
+        ..announcement(now + 100, now + 200, true)
+    };
+    plenum.store(storage, None).unwrap();
+    let mut visit = announcement(now + 100, now + 200, false);
+    visit.store(storage, None).unwrap();
+
+    let loaded = storage.get_announcement(workshop.aid.unwrap()).unwrap();
+    assert_eq!(loaded.category.as_deref(), Some("workshop"));
+    assert_eq!(loaded.tags, tags(&["soldering", "beginners"]));
+    let current: Vec<_> = storage
+        .get_current_announcements(now)
+        .unwrap()
+        .into_iter()
+        .map(|a| (a.category, a.tags))
+        .collect();
+    assert_eq!(
+        current,
+        vec![
+            (
+                Some(String::from("workshop")),
+                tags(&["soldering", "beginners"])
+            ),
+            (Some(String::from("plenum")), Vec::new()),
+            (None, Vec::new()),
+        ]
+    );
+
+    // deleting keeps the category
+    let mut deleted = AnnouncementAction {
+        method: AnnouncementMethod::Del,
+        aid: plenum.aid,
+        ..announcement(0, 0, false)
+    };
+    deleted.store(storage, None).unwrap();
+    assert_eq!(deleted.category.as_deref(), Some("plenum"));
+
+    let query = |storage: &mut dyn Storage, category: Option<&str>, tag: Option<&str>| {
+        let filter = AnnouncementFilter {
+            category: category.map(String::from),
+            tag: tag.map(String::from),
+        };
+        storage
+            .query(
+                QueryActionType::All,
+                RangeExpr::Range(IdExpr::Int(0), IdExpr::Last),
+                RangeExpr::Range(i64::MIN, i64::MAX),
+                100,
+                Take::First,
+                &filter,
+            )
+            .unwrap()
+            .into_iter()
+            .map(|a| match a {
+                TypedAction::Announcement(a) => a.action.id.unwrap(),
+                other => panic!("unexpected action: {other:?}"),
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        query(storage, Some("plenum"), None),
+        vec![plenum.action.id.unwrap(), deleted.action.id.unwrap()]
+    );
+    let workshop_id = workshop.action.id.unwrap();
+    assert_eq!(query(storage, None, Some("soldering")), vec![workshop_id]);
+    assert_eq!(query(storage, None, Some("beginners")), vec![workshop_id]);
+    assert_eq!(query(storage, None, Some("solder")), Vec::<u64>::new());
+    assert_eq!(
+        query(storage, Some("workshop"), Some("beginners")),
+        vec![workshop_id]
+    );
+    assert_eq!(
+        query(storage, Some("plenum"), Some("soldering")),
+        Vec::<u64>::new()
+    );
+}
+
+pub(crate) fn announcement_resources(storage: &mut dyn Storage) {
+    ensure_initialized(storage).unwrap();
+    let now = Utc::now().timestamp();
+    let booking = |from, to, resources: &[&str]| AnnouncementAction {
+        resources: resources.iter().map(|&r| String::from(r)).collect(),
+        ..announcement(from, to, false)
+    };
+    let mut lasercutter = booking(now + 200, now + 300, &["lasercutter"]);
+    lasercutter.store(storage, None).unwrap();
+    let mut both = booking(now + 100, now + 200, &["lecture_room", "lasercutter"]);
+    both.store(storage, None).unwrap();
+    booking(now - 200, now - 100, &["lasercutter"])
+        .store(storage, None)
+        .unwrap();
+    booking(now, now + 1000, &[]).store(storage, None).unwrap();
+
+    let loaded = storage.get_announcement(both.aid.unwrap()).unwrap();
+    assert_eq!(loaded.resources, vec!["lecture_room", "lasercutter"]);
+    let aids = |bookings: Vec<AnnouncementAction>| -> Vec<_> {
+        bookings.into_iter().map(|a| a.aid.unwrap()).collect()
+    };
+    // ordered by start, without the ended booking
+    assert_eq!(
+        aids(resource_bookings(storage, "lasercutter", now).unwrap()),
+        vec![both.aid.unwrap(), lasercutter.aid.unwrap()]
+    );
+    assert_eq!(
+        aids(resource_bookings(storage, "lecture_room", now).unwrap()),
+        vec![both.aid.unwrap()]
+    );
+
+    let overlapping = |storage: &mut dyn Storage, booking: &AnnouncementAction| {
+        overlapping_bookings(storage, booking)
+            .unwrap()
+            .into_iter()
+            .map(|(resource, a)| (resource, a.aid.unwrap()))
+            .collect::<Vec<_>>()
+    };
+    let new = booking(now + 150, now + 250, &["lasercutter", "lecture_room"]);
+    assert_eq!(
+        overlapping(storage, &new),
+        vec![
+            (String::from("lasercutter"), both.aid.unwrap()),
+            (String::from("lasercutter"), lasercutter.aid.unwrap()),
+            (String::from("lecture_room"), both.aid.unwrap()),
+        ]
+    );
+    // touching bookings don't overlap
+    assert!(overlapping(storage, &booking(now + 300, now + 400, &["lasercutter"])).is_empty());
+    // neither does the modified booking with itself
+    let moved = AnnouncementAction {
+        method: AnnouncementMethod::Mod,
+        aid: lasercutter.aid,
+        ..booking(now + 250, now + 350, &["lasercutter"])
+    };
+    assert!(overlapping(storage, &moved).is_empty());
+}
+
+pub(crate) fn announcement_series(storage: &mut dyn Storage) {
+    ensure_initialized(storage).unwrap();
+    let now = Utc::now().timestamp();
+
+    let mut series = AnnouncementSeries {
+        id: None,
+        time: now,
+        user: UserName::new("plenumsbot".into()),
+        note: String::from("Plenum"),
+        rrule: String::from("FREQ=WEEKLY;INTERVAL=2;BYDAY=TH"),
+        timezone: String::from("Europe/Berlin"),
+        from: now + 100,
+        duration: 3600,
+        public: true,
+        url: Some("https://example.org/plenum".parse().unwrap()),
+        category: Some(String::from("plenum")),
+        tags: vec![String::from("orga"), String::from("hybrid")],
+        description: Some(String::from("Agenda: https://example.org/plenum/pad")),
+        materialized_until: now,
+        deleted: false,
+    };
+    let id = storage.insert_announcement_series(&mut series).unwrap();
+    assert_eq!(series.id, Some(id));
+    let loaded = storage.get_announcement_series(id).unwrap();
+    assert_eq!(loaded.rrule, series.rrule);
+    assert_eq!(loaded.timezone, "Europe/Berlin");
+    assert_eq!((loaded.from, loaded.duration), (now + 100, 3600));
+    assert_eq!(loaded.url, series.url);
+    assert_eq!(loaded.category.as_deref(), Some("plenum"));
+    assert_eq!(loaded.tags, series.tags);
+    assert_eq!(loaded.description, series.description);
+    assert!(loaded.public && !loaded.deleted);
+    assert!(matches!(
+        storage.get_announcement_series(12345),
+        Err(Error::NotFound)
+    ));
+
+    // occurrences keep their series when modified or deleted
+    let occurrence = |from| AnnouncementAction {
+        series: Some(id),
+        recurrence_id: Some(from),
+        ..announcement(from, from + 3600, true)
+    };
+    let mut first = occurrence(now + 200);
+    first.store(storage, None).unwrap();
+    let mut second = occurrence(now + 100);
+    second.store(storage, None).unwrap();
+    announcement(now, now + 10, true)
+        .store(storage, None)
+        .unwrap();
+    let mut moved = AnnouncementAction {
+        method: AnnouncementMethod::Mod,
+        aid: first.aid,
+        ..announcement(now + 300, now + 400, true)
+    };
+    moved.store(storage, None).unwrap();
+    assert_eq!(
+        (moved.series, moved.recurrence_id),
+        (Some(id), Some(now + 200))
+    );
+    let mut deleted = AnnouncementAction {
+        method: AnnouncementMethod::Del,
+        aid: second.aid,
+        ..announcement(0, 0, false)
+    };
+    deleted.store(storage, None).unwrap();
+    let occurrences: Vec<_> = storage
+        .get_series_announcements(id)
+        .unwrap()
+        .into_iter()
+        .map(|a| (a.aid, a.method, a.recurrence_id))
+        .collect();
+    assert_eq!(
+        occurrences,
+        vec![
+            (second.aid, AnnouncementMethod::Del, Some(now + 100)),
+            (first.aid, AnnouncementMethod::Mod, Some(now + 200)),
+        ]
+    );
+
+    series.materialized_until = now + 1000;
+    series.deleted = true;
+    storage.update_announcement_series(&series).unwrap();
+    let loaded = storage.get_announcement_series(id).unwrap();
+    assert_eq!(loaded.materialized_until, now + 1000);
+    assert!(loaded.deleted);
+    assert!(storage.get_active_announcement_series().unwrap().is_empty());
+    series.id = Some(12345);
+    assert!(matches!(
+        storage.update_announcement_series(&series),
+        Err(Error::NotFound)
+    ));
+}
+
+pub(crate) fn sent_reminders(storage: &mut dyn Storage) {
+    ensure_initialized(storage).unwrap();
+    assert!(!storage.reminder_sent(3, "plenum", 1000).unwrap());
+    storage
+        .insert_sent_reminder(3, "plenum", 1000, 900)
+        .unwrap();
+    // sending it twice is not an error
+    storage
+        .insert_sent_reminder(3, "plenum", 1000, 950)
+        .unwrap();
+    assert!(storage.reminder_sent(3, "plenum", 1000).unwrap());
+    assert!(!storage.reminder_sent(3, "plenum", 2000).unwrap());
+    assert!(!storage.reminder_sent(3, "day_before", 1000).unwrap());
+    assert!(!storage.reminder_sent(4, "plenum", 1000).unwrap());
+}
+
+pub(crate) fn rsvp(storage: &mut dyn Storage) {
+    ensure_initialized(storage).unwrap();
+    let now = Utc::now().timestamp();
+    let aid = announcement(now + 100, now + 200, true)
+        .store(storage, None)
+        .unwrap();
+    let answer = |storage: &mut dyn Storage, aid: u64, user: &str, answer: RsvpAnswer| {
+        RsvpAction {
+            action: BaseAction::new_with_time(String::from("bringing snacks"), now),
+            aid,
+            user: UserName::new(user.into()),
+            answer,
+        }
+        .store(storage, None)
+    };
+
+    let id = answer(storage, aid, "Hans Acker", RsvpAnswer::Maybe).unwrap();
+    let loaded = storage.get_rsvp(id).unwrap();
+    assert_eq!((loaded.aid, loaded.answer), (aid, RsvpAnswer::Maybe));
+    assert_eq!(loaded.action.note, "bringing snacks");
+    assert!(matches!(storage.get_rsvp(aid), Err(Error::NotFound)));
+
+    // the last answer of every user counts
+    answer(storage, aid, "Frank Nord", RsvpAnswer::No).unwrap();
+    answer(storage, aid, "Hans Acker", RsvpAnswer::Yes).unwrap();
+    let rsvps: Vec<(String, RsvpAnswer)> = storage
+        .get_rsvps(aid)
+        .unwrap()
+        .into_iter()
+        .map(|r| (r.user.to_string(), r.answer))
+        .collect();
+    assert_eq!(
+        rsvps,
+        vec![
+            (String::from("Frank Nord"), RsvpAnswer::No),
+            (String::from("Hans Acker"), RsvpAnswer::Yes),
+        ]
+    );
+
+    // unknown, deleted and ended announcements can't be answered
+    assert_eq!(answer(storage, 12345, "Hans Acker", RsvpAnswer::Yes), None);
+    let ended = announcement(now - 200, now - 100, true)
+        .store(storage, None)
+        .unwrap();
+    assert_eq!(answer(storage, ended, "Hans Acker", RsvpAnswer::Yes), None);
+    AnnouncementAction {
+        method: AnnouncementMethod::Del,
+        aid: Some(aid),
+        ..announcement(0, 0, true)
+    }
+    .store(storage, None)
+    .unwrap();
+    assert_eq!(answer(storage, aid, "Hans Acker", RsvpAnswer::No), None);
+    assert_eq!(storage.get_rsvps(aid).unwrap().len(), 2);
+
+    match &storage
+        .query(
+            QueryActionType::Rsvp,
+            RangeExpr::Single(IdExpr::Int(id)),
+            RangeExpr::Range(0, i64::MAX),
+            10,
+            Take::First,
+            &AnnouncementFilter::default(),
+        )
+        .unwrap()[..]
+    {
+        [TypedAction::Rsvp(rsvp)] => assert_eq!(rsvp.action.id, Some(id)),
+        other => panic!("unexpected actions: {other:?}"),
+    }
+}
+
+pub(crate) fn presence_roundtrip(storage: &mut dyn Storage) {
+    ensure_initialized(storage).unwrap();
+    let user = |name: &str, status| PresentNamedUser {
+        name: UserName::new(name.into()),
+        since: 42,
+        status,
+    };
+    let mut action = PresenceAction::new(
+        String::new(),
+        vec![
+            user("Hans Acker", PresentUserStatus::Present),
+            user("Frank Nord", PresentUserStatus::Joined),
+            user("Gone", PresentUserStatus::Left),
+        ],
+        2.5,
+    );
+    let id = action.store(storage, None).unwrap();
+
+    let loaded = storage.get_last_presence().unwrap();
+    assert_eq!(loaded.action.id, Some(id));
+    assert_eq!(loaded.anonymous_users, 2.5);
+    let names: Vec<_> = loaded.users.iter().map(|u| u.name.as_str()).collect();
+    assert_eq!(names, vec!["Frank Nord", "Hans Acker"]);
+    assert!(loaded.users.iter().all(|u| u.since == 42));
+    assert_eq!(storage.get_presence(id).unwrap().users.len(), 2);
+}
+
+pub(crate) fn query_filters(storage: &mut dyn Storage) {
+    ensure_initialized(storage).unwrap();
+    let now = Utc::now().timestamp();
+    let s1 = status(storage, Status::Public);
+    let mut a = announcement(now, now + 10, false);
+    a.store(storage, None).unwrap();
+    let s2 = status(storage, Status::Closed);
+
+    let ids = |actions: Vec<TypedAction>| -> Vec<u64> {
+        actions
+            .iter()
+            .map(|a| match a {
+                TypedAction::Status(a) => a.action.id.unwrap(),
+                TypedAction::Announcement(a) => a.action.id.unwrap(),
+                TypedAction::Presence(a) => a.action.id.unwrap(),
+                TypedAction::Handover(a) => a.action.id.unwrap(),
+                TypedAction::Rsvp(a) => a.action.id.unwrap(),
+            })
+            .collect()
+    };
+    let all = || RangeExpr::Range(IdExpr::Int(0), IdExpr::Last);
+    let any_time = || RangeExpr::Range(i64::MIN, i64::MAX);
+    let none = AnnouncementFilter::default();
+
+    let actions = storage
+        .query(
+            QueryActionType::All,
+            all(),
+            any_time(),
+            100,
+            Take::Last,
+            &none,
+        )
+        .unwrap();
+    assert_eq!(ids(actions), vec![1, 2, 3, 4, 5]);
+
+    let actions = storage
+        .query(
+            QueryActionType::Status,
+            all(),
+            any_time(),
+            2,
+            Take::Last,
+            &none,
+        )
+        .unwrap();
+    assert_eq!(
+        ids(actions),
+        vec![s1.action.id.unwrap(), s2.action.id.unwrap()]
+    );
+
+    let actions = storage
+        .query(
+            QueryActionType::All,
+            all(),
+            any_time(),
+            2,
+            Take::First,
+            &none,
+        )
+        .unwrap();
+    assert_eq!(ids(actions), vec![1, 2]);
+
+    let actions = storage
+        .query(
+            QueryActionType::All,
+            RangeExpr::Single(IdExpr::Int(4)),
+            any_time(),
+            1,
+            Take::Last,
+            &none,
+        )
+        .unwrap();
+    assert!(matches!(&actions[..], [TypedAction::Announcement(_)]));
+
+    let actions = storage
+        .query(
+            QueryActionType::All,
+            RangeExpr::Range(IdExpr::Int(2), IdExpr::Int(4)),
+            any_time(),
+            100,
+            Take::Last,
+            &none,
+        )
+        .unwrap();
+    assert_eq!(ids(actions), vec![2, 3, 4]);
+
+    // the initial actions have time=0
+    let actions = storage
+        .query(
+            QueryActionType::All,
+            all(),
+            RangeExpr::Single(0),
+            100,
+            Take::Last,
+            &none,
+        )
+        .unwrap();
+    assert_eq!(ids(actions), vec![1, 2]);
+}
@@ -0,0 +1,330 @@
+//! Changes the status according to a door sensor (eg. a reed switch on the lock) publishing on
+//! MQTT.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::api::mqtt::MqttSendQueue;
+use crate::clock::SharedClock;
+use crate::db::{DbCon, DbStored, Error, SYSTEM_USER, Storage};
+use clubstatus_types::{Status, StatusAction, UserName};
+
+/// How often pending transitions are checked, in seconds.
+const INTERVAL: u64 = 1;
+
+pub type SharedDoor = Arc<Mutex<Door>>;
+
+/// The last message on `topic` has exactly `payload`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct DoorCondition {
+    pub topic: String,
+    pub payload: String,
+}
+
+/// Changes the status to `status` while all conditions hold.
+#[derive(Clone, Debug, Deserialize)]
+pub struct DoorRule {
+    pub conditions: Vec<DoorCondition>,
+    pub status: Status,
+}
+
+pub struct DoorConfig {
+    /// The status changes are attributed to this user.
+    pub user: UserName,
+    /// Seconds the payloads have to stay the same before the status is changed.
+    pub debounce: i64,
+    /// Seconds after someone else changed the status, during which the sensor is ignored.
+    pub manual_override: i64,
+    /// The first rule whose conditions hold applies.
+    pub rules: Vec<DoorRule>,
+}
+
+#[derive(Debug, PartialEq)]
+struct TopicState {
+    payload: String,
+    since: i64,
+    /// Number of the change that set the payload, see `Door::changes`.
+    change: u64,
+}
+
+pub struct Door {
+    config: DoorConfig,
+    /// The last payload on each topic.
+    topics: HashMap<String, TopicState>,
+    /// Counts the payload changes on all topics.
+    changes: u64,
+    /// The changes up to this one have been acted on, or dropped.
+    handled: u64,
+}
+
+impl Door {
+    pub fn new(config: DoorConfig) -> Self {
+        Door {
+            config,
+            topics: HashMap::new(),
+            changes: 0,
+            handled: 0,
+        }
+    }
+
+    /// The topics to subscribe to.
+    pub fn topics(&self) -> Vec<String> {
+        let mut topics: Vec<String> = self
+            .config
+            .rules
+            .iter()
+            .flat_map(|r| r.conditions.iter().map(|c| c.topic.clone()))
+            .collect();
+        topics.sort();
+        topics.dedup();
+        topics
+    }
+
+    /// Remembers the payload of an incoming message. Repeating the payload does not restart the
+    /// debounce time, another payload does, so a bouncing sensor does not change the status. Once
+    /// the payload has been acted on, repeating it counts as a change again.
+    pub fn handle_message(&mut self, topic: &str, payload: &[u8], now: i64) {
+        let payload = String::from_utf8_lossy(payload);
+        if self
+            .topics
+            .get(topic)
+            .is_some_and(|s| s.payload == payload && s.change > self.handled)
+        {
+            return;
+        }
+        self.changes += 1;
+        self.topics.insert(
+            topic.to_string(),
+            TopicState {
+                payload: payload.into_owned(),
+                since: now,
+                change: self.changes,
+            },
+        );
+    }
+
+    /// The states of the topics in the conditions of the first rule that holds.
+    fn matching_rule(&self) -> Option<(&DoorRule, Vec<&TopicState>)> {
+        self.config.rules.iter().find_map(|rule| {
+            let states = rule
+                .conditions
+                .iter()
+                .map(|c| self.topics.get(&c.topic).filter(|s| s.payload == c.payload))
+                .collect::<Option<Vec<_>>>()?;
+            Some((rule, states))
+        })
+    }
+
+    /// Changes the status once the payloads matching a rule have been stable for the debounce
+    /// time, returning the id of the created status action. Each change of the payloads is acted
+    /// on once.
+    ///
+    /// Transitions are dropped if the status already matches, or if someone else than the door
+    /// sensor and clubstatusd itself changed the status within the manual override window.
+    ///
+    /// Closing is not subject to the keyholder check (`keyholder.close`): locking the door takes
+    /// a key, so whoever did it holds one.
+    pub fn check(
+        &mut self,
+        storage: &mut dyn Storage,
+        mqtt: Option<&MqttSendQueue>,
+        now: i64,
+    ) -> Result<Option<u64>, Error> {
+        let Some((rule, states)) = self.matching_rule() else {
+            return Ok(None);
+        };
+        let change = states.iter().map(|s| s.change).max().unwrap_or_default();
+        let since = states.iter().map(|s| s.since).max().unwrap_or_default();
+        if change <= self.handled || now - since < self.config.debounce {
+            return Ok(None);
+        }
+        let status = rule.status;
+        let note = rule
+            .conditions
+            .iter()
+            .map(|c| format!("{} {}", c.topic, c.payload))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let last = storage.get_last_status()?;
+        self.handled = self.changes;
+        if last.status == status {
+            return Ok(None);
+        }
+        let by_human = last.user != self.config.user && last.user.as_str() != SYSTEM_USER;
+        if by_human && now - last.action.time < self.config.manual_override {
+            println!(
+                "Ignoring door sensor ({note}), {} changed the status {} s ago",
+                last.user,
+                now - last.action.time
+            );
+            return Ok(None);
+        }
+        Ok(StatusAction::new(
+            format!("door sensor: {note}"),
+            now,
+            self.config.user.clone(),
+            status,
+        )
+        .store(storage, mqtt))
+    }
+}
+
+pub fn start(
+    shared_con: Arc<Mutex<DbCon>>,
+    mqtt: Option<&MqttSendQueue>,
+    clock: SharedClock,
+    door: SharedDoor,
+) {
+    let mqtt = mqtt.cloned();
+    thread::Builder::new()
+        .name(String::from("door"))
+        .spawn(move || {
+            loop {
+                let checked = {
+                    let mut con = shared_con.lock().unwrap();
+                    door.lock()
+                        .unwrap()
+                        .check(&mut **con, mqtt.as_ref(), clock.now())
+                };
+                if let Err(err) = checked {
+                    eprintln!("Error checking the door sensor: {err}");
+                }
+                thread::sleep(Duration::new(INTERVAL, 0));
+            }
+        })
+        .unwrap();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::ensure_initialized;
+    use crate::db::sqlite::SqliteStorage;
+
+    const LOCK: &str = "space/door/lock";
+    const KEYSWITCH: &str = "space/door/keyswitch";
+
+    fn rule(conditions: &[(&str, &str)], status: Status) -> DoorRule {
+        DoorRule {
+            conditions: conditions
+                .iter()
+                .map(|&(topic, payload)| DoorCondition {
+                    topic: String::from(topic),
+                    payload: String::from(payload),
+                })
+                .collect(),
+            status,
+        }
+    }
+
+    fn door(rules: Vec<DoorRule>) -> Door {
+        Door::new(DoorConfig {
+            user: UserName::new("door".into()),
+            debounce: 5,
+            manual_override: 600,
+            rules,
+        })
+    }
+
+    fn lock_door() -> Door {
+        door(vec![
+            rule(&[(LOCK, "locked")], Status::Closed),
+            rule(&[(LOCK, "unlocked")], Status::Public),
+        ])
+    }
+
+    fn storage() -> SqliteStorage {
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+        ensure_initialized(&mut storage).unwrap();
+        storage
+    }
+
+    #[test]
+    fn debounces() {
+        let mut storage = storage();
+        let mut door = lock_door();
+        door.handle_message(LOCK, b"unlocked", 1000);
+        assert_eq!(door.check(&mut storage, None, 1004).unwrap(), None);
+        // repeating the payload does not restart the debounce time
+        door.handle_message(LOCK, b"unlocked", 1003);
+        assert_eq!(door.check(&mut storage, None, 1005).unwrap(), Some(3));
+        let last = storage.get_last_status().unwrap();
+        assert_eq!((last.status, last.user.as_str()), (Status::Public, "door"));
+        assert_eq!(last.action.note, "door sensor: space/door/lock unlocked");
+
+        // bouncing back cancels the transition
+        door.handle_message(LOCK, b"locked", 2000);
+        door.handle_message(LOCK, b"ajar", 2001);
+        assert_eq!(door.check(&mut storage, None, 2010).unwrap(), None);
+        // other topics are ignored
+        door.handle_message(LOCK, b"locked", 3000);
+        door.handle_message("space/light", b"off", 3001);
+        assert_eq!(door.check(&mut storage, None, 3005).unwrap(), Some(4));
+    }
+
+    #[test]
+    fn manual_override() {
+        let mut storage = storage();
+        let mut door = lock_door();
+        StatusAction::new(
+            String::new(),
+            1000,
+            UserName::new("Hans Acker".into()),
+            Status::Private,
+        )
+        .store(&mut storage, None)
+        .unwrap();
+        door.handle_message(LOCK, b"unlocked", 1100);
+        assert_eq!(door.check(&mut storage, None, 1599).unwrap(), None);
+        // the transition has been dropped
+        assert_eq!(door.check(&mut storage, None, 1600).unwrap(), None);
+
+        door.handle_message(LOCK, b"unlocked", 1600);
+        assert_eq!(door.check(&mut storage, None, 1605).unwrap(), Some(4));
+        // the sensor's own changes don't start the override window
+        door.handle_message(LOCK, b"locked", 1610);
+        assert_eq!(door.check(&mut storage, None, 1615).unwrap(), Some(5));
+    }
+
+    #[test]
+    fn combines_topics() {
+        let mut storage = storage();
+        let mut door = door(vec![
+            rule(&[(LOCK, "locked")], Status::Closed),
+            rule(&[(LOCK, "unlocked"), (KEYSWITCH, "1")], Status::Private),
+            rule(&[(LOCK, "unlocked"), (KEYSWITCH, "2")], Status::Public),
+        ]);
+        // the keyswitch alone does not open
+        door.handle_message(KEYSWITCH, b"2", 1000);
+        assert_eq!(door.check(&mut storage, None, 1010).unwrap(), None);
+        // the debounce time starts with the last of the payloads
+        door.handle_message(LOCK, b"unlocked", 1020);
+        assert_eq!(door.check(&mut storage, None, 1024).unwrap(), None);
+        assert_eq!(door.check(&mut storage, None, 1025).unwrap(), Some(3));
+        let last = storage.get_last_status().unwrap();
+        assert_eq!(last.status, Status::Public);
+        assert_eq!(
+            last.action.note,
+            "door sensor: space/door/lock unlocked, space/door/keyswitch 2"
+        );
+
+        // a message on another topic does not cancel a pending change
+        door.handle_message(LOCK, b"locked", 2000);
+        door.handle_message(KEYSWITCH, b"1", 2002);
+        assert_eq!(door.check(&mut storage, None, 2005).unwrap(), Some(4));
+        assert_eq!(storage.get_last_status().unwrap().status, Status::Closed);
+        // nor does it repeat one
+        door.handle_message(KEYSWITCH, b"2", 2010);
+        assert_eq!(door.check(&mut storage, None, 2020).unwrap(), None);
+
+        door.handle_message(LOCK, b"unlocked", 3000);
+        door.handle_message(KEYSWITCH, b"1", 3001);
+        assert_eq!(door.check(&mut storage, None, 3006).unwrap(), Some(5));
+        assert_eq!(storage.get_last_status().unwrap().status, Status::Private);
+    }
+}
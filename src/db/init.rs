@@ -1,114 +1,48 @@
-use std::fs;
-use std::path::Path;
-
-use rusqlite::{Connection, Error, Transaction, params};
-
-use crate::db::DbStored;
+use crate::db::{DbStored, Error, Storage};
 use clubstatus_types::{PresenceAction, Status, StatusAction, UserName};
 
-pub fn ensure_initialized(path: &Path) -> Result<(), Error> {
-    if fs::metadata(path).is_err() {
-        println!("creating db at {:?}", path);
-        let mut con = Connection::open(path)?;
-        let transaction = con.transaction().unwrap();
-        create_tables(&transaction);
-        insert_initial_status(&transaction);
-        insert_initial_presence(&transaction);
-        transaction.commit().unwrap();
+/// Version of the schema created by `Storage::create_tables`. Databases created by older versions
+/// of clubstatusd are brought up to it by `Storage::migrate`, one version at a time.
+pub const SCHEMA_VERSION: u32 = 12;
+
+/// Creates or migrates the tables and inserts the initial actions, all while holding the write
+/// lock, which makes it one transaction with SQLite. On errors the lock is not released, so
+/// nothing is committed.
+pub fn ensure_initialized(storage: &mut dyn Storage) -> Result<(), Error> {
+    storage.lock_writes()?;
+    if !storage.is_initialized()? {
+        println!("initializing db");
+        storage.create_tables()?;
+    } else {
+        for version in storage.schema_version()? + 1..=SCHEMA_VERSION {
+            println!("migrating db to schema version {version}");
+            storage.migrate(version)?;
+        }
     }
-    Ok(())
-}
-
-fn create_tables(tx: &Transaction) {
-    /*
-     * types:
-     *   0: status
-     *   1: announcement
-     *   2: presence
-     */
-    tx.execute(
-        "CREATE TABLE action (
-                     id INTEGER PRIMARY KEY AUTOINCREMENT,
-                     time INTEGER NOT NULL,
-                     type INTEGER NOT NULL,
-                     note TEXT NOT NULL
-                 )",
-        params![],
-    )
-    .unwrap();
-
-    /*
-     * status:
-     *   0: closed
-     *   1: private
-     *   2: public
-     * changed: boolean
-     */
-    tx.execute(
-        "CREATE TABLE status_action (
-                 id INTEGER PRIMARY KEY,
-                 user TEXT NOT NULL,
-                 status INTEGER NOT NULL,
-                 changed INTEGER NOT NULL,
-                 public_changed INTEGER NOT NULL
-             )",
-        params![],
-    )
-    .unwrap();
-
-    /*
-     * method:
-     *   0: new
-     *   1: mod
-     *   2: del
-     * public: boolean
-     */
-    tx.execute(
-        "CREATE TABLE announcement_action (
-                 id INTEGER PRIMARY KEY,
-                 method INTEGER,
-                 aid INTEGER,
-                 user TEXT NOT NULL,
-                 'from' INTEGER,
-                 'to' INTEGER,
-                 public INTEGER,
-                 url TEXT
-             )",
-        params![],
-    )
-    .unwrap();
-
-    tx.execute(
-        "CREATE TABLE presence_action (
-                 id INTEGER,
-                 user TEXT NOT NULL,
-                 since INTEGER
-             )",
-        params![],
-    )
-    .unwrap();
-
-    tx.execute(
-        "CREATE TABLE presence_anon_action (
-                 id INTEGER,
-                 anonymous_users FLOAT
-             )",
-        params![],
-    )
-    .unwrap();
+    // also completes databases whose initialization has been interrupted, as PostgreSQL commits
+    // the tables on their own
+    match storage.get_last_status() {
+        Err(Error::NotFound) => insert_initial_status(storage),
+        result => drop(result?),
+    }
+    match storage.get_last_presence() {
+        Err(Error::NotFound) => insert_initial_presence(storage),
+        result => drop(result?),
+    }
+    storage.unlock_writes()
 }
 
-fn insert_initial_status(tx: &Transaction) {
+fn insert_initial_status(storage: &mut dyn Storage) {
     let mut status_action = StatusAction::new(
         "initial state".into(),
         0,
         UserName::new("Hans Acker".into()),
         Status::Closed,
     );
-    status_action.store(tx, None).unwrap();
+    status_action.store(storage, None).unwrap();
 }
 
-fn insert_initial_presence(tx: &Transaction) {
+fn insert_initial_presence(storage: &mut dyn Storage) {
     let mut presence_action = PresenceAction::new_with_time("initial state".into(), 0, vec![], 0.0);
-    presence_action.store(tx, None).unwrap();
+    presence_action.store(storage, None).unwrap();
 }
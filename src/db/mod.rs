@@ -1,46 +1,289 @@
-use std::path::Path;
-
-use rusqlite::types::ToSql;
-use rusqlite::{Connection, Error, Transaction, params};
+use std::fmt;
 
 use crate::api::mqtt::MqttSendQueue;
-use crate::api::{IdExpr, PresenceRequest, RangeExpr, Take};
+use crate::api::{AnnouncementFilter, IdExpr, PresenceRequest, RangeExpr, Take};
 use crate::model::QueryActionType;
 use clubstatus_types::{
-    AnnouncementAction, AnnouncementMethod, BaseAction, PresenceAction, PresentNamedUser,
-    PresentUserStatus, Status, StatusAction, TypedAction, UserName,
+    AnnouncementAction, AnnouncementMethod, AnnouncementSeries, HandoverAction, PresenceAction,
+    RsvpAction, ScheduledStatus, Status, StatusAction, TypedAction, UserName,
 };
 
+pub mod alerts;
+pub mod announcement_events;
+pub mod auto_close;
+#[cfg(test)]
+mod conformance;
+pub mod door;
 mod init;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+pub mod reminders;
+pub mod schedule;
+pub mod series;
+pub mod sqlite;
+
+pub use crate::db::init::{SCHEMA_VERSION, ensure_initialized};
+
+/// The user status actions created by clubstatusd itself are attributed to.
+pub const SYSTEM_USER: &str = "clubstatusd";
+
+/// The storage backend shared between the API, the MQTT handler and the presence tracker.
+pub type DbCon = Box<dyn Storage>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The requested action does not exist.
+    NotFound,
+    /// An announcement series with an invalid rrule or timezone.
+    InvalidSeries(String),
+    Sqlite(rusqlite::Error),
+    #[cfg(feature = "postgres")]
+    Postgres(::postgres::Error),
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotFound => f.write_str("not found"),
+            Error::InvalidSeries(e) => write!(f, "invalid series: {e}"),
+            Error::Sqlite(e) => write!(f, "sqlite: {e}"),
+            #[cfg(feature = "postgres")]
+            Error::Postgres(e) => write!(f, "postgres: {e}"),
+        }
+    }
+}
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        match e {
+            rusqlite::Error::QueryReturnedNoRows => Error::NotFound,
+            e => Error::Sqlite(e),
+        }
+    }
+}
+#[cfg(feature = "postgres")]
+impl From<::postgres::Error> for Error {
+    fn from(e: ::postgres::Error) -> Self {
+        Error::Postgres(e)
+    }
+}
 
-pub use crate::db::init::ensure_initialized;
-
-pub type DbCon = Connection;
-
+/// Opens (and if necessary initializes) the SQLite database at the given path.
 pub fn connect(path_str: &str) -> Result<DbCon, Error> {
-    let path = Path::new(path_str);
-    ensure_initialized(path).unwrap();
-    Connection::open(path)
+    let mut storage = sqlite::SqliteStorage::open(path_str)?;
+    ensure_initialized(&mut storage)?;
+    Ok(Box::new(storage))
 }
 
-pub trait DbStored {
-    fn store(&mut self, con: &Transaction, mqtt: Option<&MqttSendQueue>) -> Option<u64>;
+/// Connects to (and if necessary initializes) a PostgreSQL database.
+///
+/// `params` is either a `postgres://` URL or a libpq style key=value string.
+#[cfg(feature = "postgres")]
+pub fn connect_postgres(params: &str) -> Result<DbCon, Error> {
+    let mut storage = postgres::PostgresStorage::connect(params)?;
+    ensure_initialized(&mut storage)?;
+    Ok(Box::new(storage))
 }
 
-pub trait DbStoredTyped {
-    fn store(&mut self, type_: i64, con: &DbCon) -> Option<u64>;
+/// Which announcements `Storage::get_announcements` returns, ordered by `from`.
+#[derive(Clone, Debug, Default)]
+pub struct AnnouncementWindow {
+    /// Only announcements ending at or after this time.
+    pub since: Option<i64>,
+    /// Only announcements starting before this time.
+    pub until: Option<i64>,
+    /// Only announcements that ended before this time. These are ordered newest first, for the
+    /// archive.
+    pub ended_before: Option<i64>,
+    /// Only announcements with `public=true`.
+    pub public: bool,
+    /// Skips this many announcements.
+    pub offset: u64,
+    /// Returns at most this many announcements, all if `None`.
+    pub limit: Option<u64>,
+}
+impl AnnouncementWindow {
+    /// All announcements that have not ended before `now`.
+    pub fn current(now: i64) -> Self {
+        AnnouncementWindow {
+            since: Some(now),
+            ..Default::default()
+        }
+    }
 }
 
-impl DbStoredTyped for BaseAction {
-    fn store(&mut self, type_: i64, con: &DbCon) -> Option<u64> {
-        con.execute(
-            "INSERT INTO action (time, type, note) VALUES (?, ?, ?)",
-            params![&self.time, &type_, &self.note],
+/**
+ * All operations clubstatusd needs from a database.
+ *
+ * Every insert_* method has to be atomic, and has to set the id of the stored action. Validation
+ * (eg. "can this announcement still be modified?") is done by the callers in this module, so
+ * backends only need to persist and load actions.
+ */
+pub trait Storage: Send {
+    /// Returns whether the tables have been created already.
+    fn is_initialized(&mut self) -> Result<bool, Error>;
+    /// Creates the tables in their latest form, recording `SCHEMA_VERSION`.
+    fn create_tables(&mut self) -> Result<(), Error>;
+    /// The recorded schema version, 0 for databases from before it was recorded.
+    fn schema_version(&mut self) -> Result<u32, Error>;
+    /// Brings the schema from `version - 1` to `version` and records it, atomically. Tables and
+    /// columns that exist already are skipped, as databases created by development versions may
+    /// have some of the changes of a version.
+    fn migrate(&mut self, version: u32) -> Result<(), Error>;
+
+    /// Until the matching `unlock_writes`, other clubstatusd instances sharing the database wait
+    /// in their `lock_writes`. `DbStored::store` holds this lock while it checks the last actions
+    /// and inserts the new one. Calls can be nested, only the outermost pair locks.
+    fn lock_writes(&mut self) -> Result<(), Error>;
+    fn unlock_writes(&mut self) -> Result<(), Error>;
+
+    fn insert_status(
+        &mut self,
+        action: &mut StatusAction,
+        changed: bool,
+        public_changed: bool,
+    ) -> Result<u64, Error>;
+    fn get_status(&mut self, id: u64) -> Result<StatusAction, Error>;
+    /// Last status action of the whole space.
+    fn get_last_status(&mut self) -> Result<StatusAction, Error> {
+        self.get_last_zone_status(None)
+    }
+    /// Last status action which changed the status between public and {private, closed}.
+    /// Reverted actions are skipped.
+    fn get_last_changed_public_status(&mut self) -> Result<StatusAction, Error> {
+        self.get_last_changed_public_zone_status(None)
+    }
+    /// Last status action of `zone`, `None` being the whole space. Returns `Error::NotFound` for
+    /// zones without any status action yet.
+    fn get_last_zone_status(&mut self, zone: Option<&str>) -> Result<StatusAction, Error>;
+    /// Last status action of `zone` which changed its status. Reverted actions are skipped.
+    fn get_last_changed_zone_status(&mut self, zone: Option<&str>) -> Result<StatusAction, Error>;
+    /// Last status action of `zone` which changed its status between public and
+    /// {private, closed}. Reverted actions are skipped.
+    fn get_last_changed_public_zone_status(
+        &mut self,
+        zone: Option<&str>,
+    ) -> Result<StatusAction, Error>;
+    /// Status actions of the whole space since `since` which changed the status, newest first,
+    /// at most `count`. Reverted actions are skipped.
+    fn get_status_changes(&mut self, since: i64, count: u64) -> Result<Vec<StatusAction>, Error>;
+    /// Status actions of the whole space since `since` which changed the status between public
+    /// and {private, closed}, newest first, at most `count`. Reverted actions are skipped.
+    fn get_public_status_changes(
+        &mut self,
+        since: i64,
+        count: u64,
+    ) -> Result<Vec<StatusAction>, Error>;
+    /// Last status action of the whole space before `time` which changed the status. Reverted
+    /// actions are skipped.
+    fn get_status_change_before(&mut self, time: i64) -> Result<StatusAction, Error>;
+    /// Last status action of the whole space before `time` which changed the status between
+    /// public and {private, closed}. Reverted actions are skipped.
+    fn get_public_status_change_before(&mut self, time: i64) -> Result<StatusAction, Error>;
+    /// Last status action of the whole space with an id lower than `id`, skipping reverted
+    /// actions.
+    fn get_last_status_before(&mut self, id: u64) -> Result<StatusAction, Error>;
+
+    /// Sets the id of the stored status change.
+    fn insert_scheduled_status(&mut self, scheduled: &mut ScheduledStatus) -> Result<u64, Error>;
+    /// Status changes that have neither been applied nor cancelled yet, ordered by `at`.
+    fn get_scheduled_statuses(&mut self) -> Result<Vec<ScheduledStatus>, Error>;
+    /// Returns whether a scheduled status change with this id existed.
+    fn delete_scheduled_status(&mut self, id: u64) -> Result<bool, Error>;
+
+    fn insert_handover(&mut self, action: &mut HandoverAction) -> Result<u64, Error>;
+    fn get_handover(&mut self, id: u64) -> Result<HandoverAction, Error>;
+    fn get_last_handover(&mut self) -> Result<Option<HandoverAction>, Error>;
+
+    /// For `AnnouncementMethod::New` the new action id is also used as aid.
+    fn insert_announcement(&mut self, action: &mut AnnouncementAction) -> Result<u64, Error>;
+    fn get_announcement(&mut self, id: u64) -> Result<AnnouncementAction, Error>;
+    /// Last action concerning the announcement `aid`.
+    fn get_last_announcement(&mut self, aid: u64) -> Result<Option<AnnouncementAction>, Error>;
+    /// Announcements within `window` matching `filter`, deleted announcements are skipped.
+    fn get_announcements(
+        &mut self,
+        window: &AnnouncementWindow,
+        filter: &AnnouncementFilter,
+    ) -> Result<Vec<AnnouncementAction>, Error>;
+    /// Announcements that have not ended before `now`, and have not been deleted.
+    fn get_current_announcements(&mut self, now: i64) -> Result<Vec<AnnouncementAction>, Error> {
+        self.get_announcements(
+            &AnnouncementWindow::current(now),
+            &AnnouncementFilter::default(),
         )
-        .unwrap();
-        let action_id = con.last_insert_rowid() as u64;
-        Some(action_id)
     }
+    /// Like `get_current_announcements`, but only announcements with `public=true`.
+    fn get_current_public_announcements(
+        &mut self,
+        now: i64,
+    ) -> Result<Vec<AnnouncementAction>, Error> {
+        let window = AnnouncementWindow {
+            public: true,
+            ..AnnouncementWindow::current(now)
+        };
+        self.get_announcements(&window, &AnnouncementFilter::default())
+    }
+    /// Last action of every occurrence of the series, including deleted occurrences, ordered by
+    /// `recurrence_id`.
+    fn get_series_announcements(&mut self, series: u64) -> Result<Vec<AnnouncementAction>, Error>;
+
+    /// Sets the id of the stored series.
+    fn insert_announcement_series(&mut self, series: &mut AnnouncementSeries)
+    -> Result<u64, Error>;
+    /// Overwrites the stored series with the same id.
+    fn update_announcement_series(&mut self, series: &AnnouncementSeries) -> Result<(), Error>;
+    fn get_announcement_series(&mut self, id: u64) -> Result<AnnouncementSeries, Error>;
+    /// Series that have not been deleted, ordered by id.
+    fn get_active_announcement_series(&mut self) -> Result<Vec<AnnouncementSeries>, Error>;
+
+    /// Records that the reminder named `reminder` has been sent at `time` for the announcement
+    /// `aid` starting at `from`.
+    fn insert_sent_reminder(
+        &mut self,
+        aid: u64,
+        reminder: &str,
+        from: i64,
+        time: i64,
+    ) -> Result<(), Error>;
+    /// Whether the reminder has been sent for the announcement starting at `from`. Moving the
+    /// announcement makes it due again.
+    fn reminder_sent(&mut self, aid: u64, reminder: &str, from: i64) -> Result<bool, Error>;
+
+    fn insert_rsvp(&mut self, action: &mut RsvpAction) -> Result<u64, Error>;
+    fn get_rsvp(&mut self, id: u64) -> Result<RsvpAction, Error>;
+    /// The last answer of every user to the announcement `aid`, ordered by user.
+    fn get_rsvps(&mut self, aid: u64) -> Result<Vec<RsvpAction>, Error>;
+
+    /// Users with status `Left` are not stored.
+    fn insert_presence(&mut self, action: &mut PresenceAction) -> Result<u64, Error>;
+    fn get_presence(&mut self, id: u64) -> Result<PresenceAction, Error>;
+    fn get_last_presence(&mut self) -> Result<PresenceAction, Error>;
+
+    /// With a category or tag in `filter`, only announcements matching it are returned.
+    fn query(
+        &mut self,
+        type_: QueryActionType,
+        id: RangeExpr<IdExpr>,
+        time: RangeExpr<i64>,
+        count: u64,
+        take: Take,
+        filter: &AnnouncementFilter,
+    ) -> Result<Vec<TypedAction>, Error>;
+}
+
+pub trait DbStored {
+    fn store(&mut self, storage: &mut dyn Storage, mqtt: Option<&MqttSendQueue>) -> Option<u64>;
+}
+
+/// Runs `f` while holding the write lock, so the stored action is based on the latest state of
+/// the database, even if another instance writes to it at the same time.
+fn with_write_lock<T>(
+    storage: &mut dyn Storage,
+    f: impl FnOnce(&mut dyn Storage) -> Option<T>,
+) -> Option<T> {
+    storage.lock_writes().unwrap();
+    let result = f(storage);
+    storage.unlock_writes().unwrap();
+    result
 }
 
 /*
@@ -48,104 +291,146 @@ impl DbStoredTyped for BaseAction {
  */
 
 impl DbStored for StatusAction {
-    fn store(&mut self, tx: &Transaction, mqtt: Option<&MqttSendQueue>) -> Option<u64> {
-        match self.action.id {
-            None => {
-                let (changed, public_changed) = match status::get_last(tx) {
-                    Ok(current_status) => {
-                        let curr_status_pub = match current_status.status {
-                            Status::Public => Status::Public,
-                            _ => Status::Closed,
-                        };
-                        let new_status_pub = match self.status {
-                            Status::Public => Status::Public,
-                            _ => Status::Closed,
+    fn store(&mut self, storage: &mut dyn Storage, mqtt: Option<&MqttSendQueue>) -> Option<u64> {
+        if self.action.id.is_some() {
+            return None;
+        }
+        let action_id = with_write_lock(storage, |storage| {
+            let previous = match self.reverts {
+                // only the status of the whole space can be reverted
+                Some(_) if self.zone.is_some() => return None,
+                Some(reverted_id) => {
+                    if revert_refusal(storage, reverted_id).ok()?.is_some() {
+                        return None;
+                    }
+                    // restore the status from before the reverted action
+                    let previous = storage.get_last_status_before(reverted_id).ok()?;
+                    self.status = previous.status;
+                    self.until = previous.until;
+                    self.revert_to = previous.revert_to;
+                    self.keyholder = keyholder_after(storage, &previous).unwrap();
+                    self.public_message = previous.public_message.clone();
+                    Ok(previous)
+                }
+                None => {
+                    let last = storage.get_last_zone_status(self.zone.as_deref());
+                    // keyholders are only tracked for the whole space
+                    if self.status == Status::Closed || self.zone.is_some() {
+                        self.keyholder = None;
+                    } else if self.keyholder.is_none() {
+                        // switching between public and private keeps the keyholder, otherwise
+                        // whoever opens the space is responsible
+                        let current = match &last {
+                            Ok(last) => keyholder_after(storage, last).unwrap(),
+                            Err(_) => None,
                         };
-                        (
-                            current_status.status != self.status,
-                            curr_status_pub != new_status_pub,
-                        )
+                        self.keyholder = Some(current.unwrap_or_else(|| self.user.clone()));
                     }
-                    Err(_) => (true, true),
-                };
-                let action_id = DbStoredTyped::store(&mut self.action, 0, tx).unwrap();
-                tx.execute(
-                    "INSERT INTO status_action (id, user, status, changed, public_changed) \
-                     VALUES (?, ?, ?, ?, ?)",
-                    params![
-                        &(action_id as i64),
-                        &self.user,
-                        &self.status,
-                        &(changed as i64),
-                        &(public_changed as i64),
-                    ],
-                )
-                .unwrap();
-                self.action.id = Some(action_id);
-                println!("Stored new action: {:?}", self);
-                if let Some(m) = mqtt {
-                    m.try_send(TypedAction::Status(self.clone()));
+                    last
                 }
-                Some(action_id)
-            }
-            Some(_) => None,
+            };
+            let (changed, public_changed) = match previous {
+                Ok(current_status) => {
+                    let curr_status_pub = match current_status.status {
+                        Status::Public => Status::Public,
+                        _ => Status::Closed,
+                    };
+                    let new_status_pub = match self.status {
+                        Status::Public => Status::Public,
+                        _ => Status::Closed,
+                    };
+                    (
+                        current_status.status != self.status,
+                        curr_status_pub != new_status_pub,
+                    )
+                }
+                Err(_) => (true, true),
+            };
+            Some(
+                storage
+                    .insert_status(self, changed, public_changed)
+                    .unwrap(),
+            )
+        })?;
+        println!("Stored new action: {:?}", self);
+        if let Some(m) = mqtt {
+            m.try_send(TypedAction::Status(self.clone()));
         }
+        Some(action_id)
     }
 }
 
-pub mod status {
-    use super::*;
-    use rusqlite::{Error, Row};
-
-    fn row_to_status_action(row: &Row) -> Result<StatusAction, Error> {
-        Ok(StatusAction {
-            action: BaseAction {
-                id: Some(row.get::<_, i64>(0)? as u64),
-                time: row.get(1)?,
-                note: row.get(3)?,
-            },
-            user: row.get(5)?,
-            status: row.get(6)?,
-        })
+/// The keyholder while `status` is the last status action, taking handovers stored after it into
+/// account.
+fn keyholder_after(
+    storage: &mut dyn Storage,
+    status: &StatusAction,
+) -> Result<Option<UserName>, Error> {
+    if status.status == Status::Closed {
+        return Ok(None);
     }
+    Ok(match storage.get_last_handover()? {
+        Some(handover) if handover.action.id > status.action.id => Some(handover.to),
+        _ => status.keyholder.clone(),
+    })
+}
 
-    pub fn get_by_id(id: u64, con: &DbCon) -> Result<StatusAction, Error> {
-        con.query_row(
-            "SELECT * FROM action JOIN status_action WHERE \
-             action.id = ? AND status_action.id = ?",
-            params![&(id as i64), &(id as i64)],
-            row_to_status_action,
-        )
-    }
+/// Who is responsible for the space right now, `None` while it is closed.
+pub fn current_keyholder(storage: &mut dyn Storage) -> Result<Option<UserName>, Error> {
+    let last = storage.get_last_status()?;
+    keyholder_after(storage, &last)
+}
 
-    pub fn get_last(con: &DbCon) -> Result<StatusAction, Error> {
-        con.query_row(
-            "SELECT * FROM action JOIN status_action WHERE action.type = 0 AND \
-             action.id = status_action.id \
-             ORDER BY action.id DESC LIMIT 1",
-            params![],
-            row_to_status_action,
-        )
+/// Why the status action `reverts` can't be reverted, `None` if it can. Only the last status
+/// action can be reverted, and reverts can't be reverted.
+pub fn revert_refusal(storage: &mut dyn Storage, reverts: u64) -> Result<Option<String>, Error> {
+    let last = storage.get_last_status()?;
+    if last.action.id != Some(reverts) {
+        return Ok(Some(format!(
+            "{reverts} is not the last status action, which is {}",
+            last.action.id.unwrap_or_default()
+        )));
+    }
+    if last.reverts.is_some() {
+        return Ok(Some(format!("{reverts} is a revert itself")));
     }
+    match storage.get_last_status_before(reverts) {
+        Ok(_) => Ok(None),
+        Err(Error::NotFound) => Ok(Some(format!("there is no status before {reverts}"))),
+        Err(err) => Err(err),
+    }
+}
 
-    pub fn get_last_changed(con: &DbCon) -> Result<StatusAction, Error> {
-        con.query_row(
-            "SELECT * FROM action JOIN status_action WHERE action.type = 0 AND \
-             action.id = status_action.id AND status_action.changed = 1 \
-             ORDER BY action.id DESC LIMIT 1",
-            params![],
-            row_to_status_action,
-        )
+/*
+ * Handovers
+ */
+
+/// Why a handover can't be stored, `None` if it can: nobody is responsible while the space is
+/// closed.
+pub fn handover_refusal(storage: &mut dyn Storage) -> Result<Option<String>, Error> {
+    if storage.get_last_status()?.status == Status::Closed {
+        return Ok(Some(String::from("the space is closed")));
     }
+    Ok(None)
+}
 
-    pub fn get_last_changed_public(con: &DbCon) -> Result<StatusAction, Error> {
-        con.query_row(
-            "SELECT * FROM action JOIN status_action WHERE action.type = 0 AND \
-             action.id = status_action.id AND status_action.public_changed = 1 \
-             ORDER BY action.id DESC LIMIT 1",
-            params![],
-            row_to_status_action,
-        )
+impl DbStored for HandoverAction {
+    fn store(&mut self, storage: &mut dyn Storage, mqtt: Option<&MqttSendQueue>) -> Option<u64> {
+        if self.action.id.is_some() {
+            return None;
+        }
+        let action_id = with_write_lock(storage, |storage| {
+            if handover_refusal(storage).ok()?.is_some() {
+                return None;
+            }
+            self.from = current_keyholder(storage).unwrap();
+            Some(storage.insert_handover(self).unwrap())
+        })?;
+        println!("Stored new action: {:?}", self);
+        if let Some(m) = mqtt {
+            m.try_send(TypedAction::Handover(self.clone()));
+        }
+        Some(action_id)
     }
 }
 
@@ -154,185 +439,139 @@ pub mod status {
  */
 
 impl DbStored for AnnouncementAction {
-    fn store(&mut self, tx: &Transaction, mqtt: Option<&MqttSendQueue>) -> Option<u64> {
-        match self.action.id {
-            None => {
-                match self.method {
-                    AnnouncementMethod::New => match self.aid {
-                        None => {
-                            let action_id = DbStoredTyped::store(&mut self.action, 1, tx).unwrap();
-                            tx.execute(
-                                "INSERT INTO announcement_action \
-                                 (id, method, aid, user, \"from\", \"to\", public, url) VALUES \
-                                 (?, ?, ?, ?, ?, ?, ?, ?)",
-                                params![
-                                    &(action_id as i64),
-                                    &0,
-                                    &(action_id as i64),
-                                    &self.user,
-                                    &self.from,
-                                    &self.to,
-                                    &(self.public as i64),
-                                    &self.url,
-                                ],
-                            )
-                            .unwrap();
-                            self.action.id = Some(action_id);
-                            self.aid = Some(action_id);
-                            println!("Stored new action: {:?}", self);
-                            if let Some(m) = mqtt {
-                                m.try_send(TypedAction::Announcement(self.clone()));
-                            }
-                            Some(action_id)
-                        }
-                        Some(_) => None,
-                    },
-                    AnnouncementMethod::Mod => match self.aid {
-                        None => None,
-                        Some(aid) => {
-                            // check if last action is method=new|mod
-                            let _last_action = match announcements::get_last(aid, tx).unwrap() {
-                                None => return None,
-                                Some(AnnouncementAction {
-                                    method: AnnouncementMethod::Del,
-                                    ..
-                                }) => return None,
-                                Some(a) => a,
-                            };
-                            let action_id = DbStoredTyped::store(&mut self.action, 1, tx).unwrap();
-                            tx.execute("INSERT INTO announcement_action (id, method, aid, user, 'from', 'to', public, url) VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
-                                    params![&(action_id as i64), &1, &(aid as i64),
-                                      &self.user, &self.from, &self.to, &(self.public as i64), &self.url]).unwrap();
-                            self.action.id = Some(action_id);
-                            println!("Stored new action: {:?}", self);
-                            if let Some(m) = mqtt {
-                                m.try_send(TypedAction::Announcement(self.clone()));
-                            }
-                            Some(action_id)
-                        }
-                    },
-                    AnnouncementMethod::Del => match self.aid {
-                        None => None,
-                        Some(aid) => {
-                            // check if last action is method=new|mod
-                            let last_action = match announcements::get_last(aid, tx).unwrap() {
-                                None => return None,
-                                Some(AnnouncementAction {
-                                    method: AnnouncementMethod::Del,
-                                    ..
-                                }) => return None,
-                                Some(a) => a,
-                            };
-                            self.action.note = last_action.action.note;
-                            self.from = last_action.from;
-                            self.to = last_action.to;
-                            self.public = last_action.public;
-                            let action_id = DbStoredTyped::store(&mut self.action, 1, tx).unwrap();
-                            tx.execute("INSERT INTO announcement_action (id, method, aid, user, 'from', 'to', public, url) VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
-                                    params![&(action_id as i64), &2, &(aid as i64),
-                                      &self.user, &self.from, &self.to, &(self.public as i64), &self.url]).unwrap();
-                            self.action.id = Some(action_id);
-                            println!("Stored new action: {:?}", self);
-                            if let Some(m) = mqtt {
-                                m.try_send(TypedAction::Announcement(self.clone()));
-                            }
-                            Some(action_id)
+    fn store(&mut self, storage: &mut dyn Storage, mqtt: Option<&MqttSendQueue>) -> Option<u64> {
+        if self.action.id.is_some() {
+            return None;
+        }
+        let action_id = with_write_lock(storage, |storage| {
+            match self.method {
+                AnnouncementMethod::New => {
+                    if self.aid.is_some() {
+                        return None;
+                    }
+                }
+                AnnouncementMethod::Mod => {
+                    // check if last action is method=new|mod
+                    match storage.get_last_announcement(self.aid?).unwrap() {
+                        None
+                        | Some(AnnouncementAction {
+                            method: AnnouncementMethod::Del,
+                            ..
+                        }) => return None,
+                        Some(last_action) => {
+                            // a modified occurrence stays part of its series
+                            self.series = last_action.series;
+                            self.recurrence_id = last_action.recurrence_id;
                         }
-                    },
+                    }
+                }
+                AnnouncementMethod::Del => {
+                    // check if last action is method=new|mod
+                    let last_action = match storage.get_last_announcement(self.aid?).unwrap() {
+                        None
+                        | Some(AnnouncementAction {
+                            method: AnnouncementMethod::Del,
+                            ..
+                        }) => return None,
+                        Some(a) => a,
+                    };
+                    self.action.note = last_action.action.note;
+                    self.from = last_action.from;
+                    self.to = last_action.to;
+                    self.public = last_action.public;
+                    self.category = last_action.category;
+                    self.tags = last_action.tags;
+                    self.description = last_action.description;
+                    self.resources = last_action.resources;
+                    self.series = last_action.series;
+                    self.recurrence_id = last_action.recurrence_id;
                 }
             }
-            Some(_) => None,
+            Some(storage.insert_announcement(self).unwrap())
+        })?;
+        println!("Stored new action: {:?}", self);
+        if let Some(m) = mqtt {
+            m.try_send(TypedAction::Announcement(self.clone()));
         }
+        Some(action_id)
     }
 }
 
-pub mod announcements {
-    use super::*;
-    use chrono::*;
-    use rusqlite::{Error, Row};
-
-    fn row_to_announcement_action(row: &Row) -> Result<AnnouncementAction, Error> {
-        Ok(AnnouncementAction {
-            action: BaseAction {
-                id: Some(row.get::<_, i64>(0)? as u64),
-                time: row.get(1)?,
-                note: row.get(3)?,
-            },
-            method: row.get(5)?,
-            aid: Some(row.get::<_, i64>(6)? as u64),
-            user: row.get(7)?,
-            from: row.get(8)?,
-            to: row.get(9)?,
-            public: match row.get(10)? {
-                0 => false,
-                1 => true,
-                unexpected => panic!("unexpected value for public: {unexpected}"),
-            },
-            url: row.get(11)?,
-        })
-    }
-
-    pub fn get_by_id(id: u64, con: &DbCon) -> Result<AnnouncementAction, Error> {
-        con.query_row(
-            "SELECT * FROM action JOIN announcement_action WHERE action.type = 1 AND \
-             action.id = ? AND announcement_action.id = ?",
-            params![&(id as i64), &(id as i64)],
-            row_to_announcement_action,
-        )
-    }
+/// Announcements booking `resource` that have not ended before `now`, ordered by `from`.
+pub fn resource_bookings(
+    storage: &mut dyn Storage,
+    resource: &str,
+    now: i64,
+) -> Result<Vec<AnnouncementAction>, Error> {
+    let mut bookings: Vec<_> = storage
+        .get_current_announcements(now)?
+        .into_iter()
+        .filter(|a| a.resources.iter().any(|r| r == resource))
+        .collect();
+    bookings.sort_by_key(|a| a.from);
+    Ok(bookings)
+}
 
-    pub fn get_last(aid: u64, con: &DbCon) -> Result<Option<AnnouncementAction>, Error> {
-        let res = con.query_row(
-            "SELECT * FROM action JOIN announcement_action WHERE action.type = 1 AND \
-             action.id = announcement_action.id AND announcement_action.aid = ? \
-             ORDER BY action.id DESC LIMIT 1",
-            params![&(aid as i64)],
-            row_to_announcement_action,
-        );
-        match res {
-            Ok(announcement_action) => Ok(Some(announcement_action)),
-            Err(Error::QueryReturnedNoRows) => Ok(None),
-            Err(e) => Err(e),
+/// Other announcements booking one of the resources of `booking` at the same time, together
+/// with the resource.
+pub fn overlapping_bookings(
+    storage: &mut dyn Storage,
+    booking: &AnnouncementAction,
+) -> Result<Vec<(String, AnnouncementAction)>, Error> {
+    let mut overlapping = Vec::new();
+    for resource in &booking.resources {
+        for other in resource_bookings(storage, resource, booking.from)? {
+            if other.aid != booking.aid && other.from < booking.to && booking.from < other.to {
+                overlapping.push((resource.clone(), other));
+            }
         }
     }
+    Ok(overlapping)
+}
 
-    pub fn get_current(con: &DbCon) -> Result<Vec<AnnouncementAction>, Error> {
-        let mut stmt = con
-            .prepare(
-                "SELECT * FROM action JOIN announcement_action WHERE \
-                 action.id IN ( \
-                 SELECT max(id) FROM announcement_action GROUP BY aid \
-                 ) AND \
-                 action.id = announcement_action.id AND \
-                 ? <= \"to\" AND \
-                 announcement_action.method != 2 \
-                 ORDER BY \"from\" LIMIT 30",
-            )
-            .unwrap();
-        let now = Utc::now().timestamp();
-        let actions_iter = stmt.query_map([&now], row_to_announcement_action).unwrap();
-        let actions: Vec<AnnouncementAction> = actions_iter.map(|action| action.unwrap()).collect();
-        Ok(actions)
+/*
+ * RSVPs
+ */
+
+/// Why an RSVP to `aid` at `time` can't be stored, `None` if it can. Returns `Error::NotFound` for
+/// unknown and deleted announcements.
+pub fn rsvp_refusal(
+    storage: &mut dyn Storage,
+    aid: u64,
+    time: i64,
+) -> Result<Option<String>, Error> {
+    match storage.get_last_announcement(aid)? {
+        None
+        | Some(AnnouncementAction {
+            method: AnnouncementMethod::Del,
+            ..
+        }) => Err(Error::NotFound),
+        Some(announcement) if announcement.to <= time => {
+            Ok(Some(format!("announcement {aid} has ended")))
+        }
+        Some(_) => Ok(None),
     }
+}
 
-    pub fn get_current_public(con: &DbCon) -> Result<Vec<AnnouncementAction>, Error> {
-        let mut stmt = con
-            .prepare(
-                "SELECT * FROM action JOIN announcement_action WHERE \
-                 action.id IN ( \
-                 SELECT max(id) FROM announcement_action GROUP BY aid \
-                 ) AND \
-                 action.id = announcement_action.id AND \
-                 ? <= \"to\" AND \
-                 announcement_action.method != 2 AND \
-                 announcement_action.public = 1 \
-                 ORDER BY \"from\" LIMIT 30",
-            )
-            .unwrap();
-        let now = Utc::now().timestamp();
-        let actions_iter = stmt.query_map([&now], row_to_announcement_action).unwrap();
-        let actions: Vec<AnnouncementAction> = actions_iter.map(|action| action.unwrap()).collect();
-        Ok(actions)
+impl DbStored for RsvpAction {
+    fn store(&mut self, storage: &mut dyn Storage, mqtt: Option<&MqttSendQueue>) -> Option<u64> {
+        if self.action.id.is_some() {
+            return None;
+        }
+        let action_id = with_write_lock(storage, |storage| {
+            if rsvp_refusal(storage, self.aid, self.action.time)
+                .ok()?
+                .is_some()
+            {
+                return None;
+            }
+            Some(storage.insert_rsvp(self).unwrap())
+        })?;
+        println!("Stored new action: {:?}", self);
+        if let Some(m) = mqtt {
+            m.try_send(TypedAction::Rsvp(self.clone()));
+        }
+        Some(action_id)
     }
 }
 
@@ -341,34 +580,13 @@ pub mod announcements {
  */
 
 impl DbStored for PresenceAction {
-    fn store(&mut self, tx: &Transaction, mqtt: Option<&MqttSendQueue>) -> Option<u64> {
+    fn store(&mut self, storage: &mut dyn Storage, mqtt: Option<&MqttSendQueue>) -> Option<u64> {
         if self.action.id.is_some() {
             return None;
         }
-        tx.execute(
-            "INSERT INTO action (time, type, note) VALUES (?, ?, ?)",
-            params![&self.action.time, &2, &self.action.note],
-        )
-        .unwrap();
-        let action_id = tx.last_insert_rowid() as u64;
-        for user in self.users.iter() {
-            match user.status {
-                PresentUserStatus::Joined | PresentUserStatus::Present => {
-                    tx.execute(
-                        "INSERT INTO presence_action (id, user, since) VALUES (?, ?, ?)",
-                        params![&(action_id as i64), &user.name.as_str(), &user.since],
-                    )
-                    .unwrap();
-                }
-                PresentUserStatus::Left => {}
-            }
-        }
-        tx.execute(
-            "INSERT INTO presence_anon_action (id, anonymous_users) VALUES (?, ?)",
-            params![&(action_id as i64), &(self.anonymous_users as f64)],
-        )
-        .unwrap();
-        self.action.id = Some(action_id);
+        let action_id = with_write_lock(storage, |storage| {
+            Some(storage.insert_presence(self).unwrap())
+        })?;
         println!("Stored new action: {:?}", self);
         if let Some(m) = mqtt {
             m.try_send(TypedAction::Presence(self.clone()));
@@ -381,87 +599,28 @@ pub mod presence {
     use crate::api::mqtt::MqttSendQueue;
 
     use super::*;
-    use chrono::Utc;
+    use crate::clock::SharedClock;
+    use clubstatus_types::{PresentNamedUser, PresentUserStatus, UserName};
     use float_cmp::approx_eq;
-    use rusqlite::Row;
     use std::collections::HashMap;
     use std::sync::mpsc::{Receiver, SyncSender, TryRecvError, sync_channel};
     use std::sync::{Arc, Mutex};
     use std::thread;
     use std::time::Duration;
 
-    fn row_to_base_action(row: &Row) -> Result<BaseAction, Error> {
-        Ok(BaseAction {
-            id: Some(row.get::<_, i64>(0)? as u64),
-            time: row.get(1)?,
-            note: row.get(3)?,
-        })
-    }
-
-    fn get_by_base_action(action: BaseAction, con: &DbCon) -> Result<PresenceAction, Error> {
-        let mut stmt = con
-            .prepare("SELECT user, since FROM presence_action WHERE id = ?")
-            .unwrap();
-        let users_iter = stmt
-            .query_map([&(action.id.unwrap() as i64)], |row| {
-                Ok(PresentNamedUser {
-                    name: UserName::new(row.get(0)?),
-                    since: row.get(1)?,
-                    status: PresentUserStatus::Present,
-                })
-            })
-            .unwrap();
-        let mut users: Vec<PresentNamedUser> = users_iter.map(|user| user.unwrap()).collect();
-        users.sort_by_key(|u| u.name.clone()); //FIXME: why do i need this clone()??
-
-        let anonymous_users = con
-            .query_row(
-                "SELECT anonymous_users FROM presence_anon_action WHERE id = ?",
-                [&(action.id.unwrap() as i64)],
-                |row| row.get(0),
-            )
-            .unwrap_or(0.0) as f32;
-
-        Ok(PresenceAction {
-            action,
-            users,
-            anonymous_users,
-        })
-    }
-
-    pub fn get_by_id(id: u64, con: &DbCon) -> Result<PresenceAction, Error> {
-        let action_res = con.query_row(
-            "SELECT * FROM action WHERE id = ? AND type = 2",
-            params![&(id as i64)],
-            row_to_base_action,
-        );
-        match action_res {
-            Ok(action) => get_by_base_action(action, con),
-            Err(e) => Err(e),
-        }
-    }
-
-    pub fn get_last(con: &DbCon) -> Result<PresenceAction, Error> {
-        let action_res = con.query_row(
-            "SELECT * FROM action WHERE type = 2 ORDER BY id DESC LIMIT 1",
-            params![],
-            row_to_base_action,
-        );
-        match action_res {
-            Ok(action) => get_by_base_action(action, con),
-            Err(e) => Err(e),
-        }
-    }
+    /// Presence requests time out after this many seconds.
+    pub const TIMEOUT: i64 = 15 * 60;
 
     pub fn start_tracker(
         shared_con: Arc<Mutex<DbCon>>,
         mqtt: Option<&MqttSendQueue>,
+        clock: SharedClock,
     ) -> SyncSender<PresenceRequest> {
         let (tx, rx) = sync_channel::<PresenceRequest>(10);
         let mqtt = mqtt.cloned();
         thread::Builder::new()
             .name(String::from("presence_tracker"))
-            .spawn(move || tracker(shared_con, mqtt, rx))
+            .spawn(move || tracker(shared_con, mqtt, clock, rx))
             .unwrap();
         tx
     }
@@ -469,246 +628,280 @@ pub mod presence {
     fn tracker(
         shared_con: Arc<Mutex<DbCon>>,
         mqtt: Option<MqttSendQueue>,
+        clock: SharedClock,
         rx: Receiver<PresenceRequest>,
     ) {
-        #[derive(Debug)]
-        struct AnonymousPresence {
-            anonymous_users: f32,
-            last_seen: i64,
-        }
-        #[derive(Debug)]
-        struct UserPresence {
-            since: i64,
-            last_seen: i64,
-            status: PresentUserStatus,
-        }
         let last_action = {
-            let con = shared_con.lock().unwrap();
-            get_last(&con).expect("Database is missing initial presence action!")
+            let mut con = shared_con.lock().unwrap();
+            con.get_last_presence()
+                .expect("Database is missing initial presence action!")
         };
-        let mut now = Utc::now().timestamp();
-        let mut users: HashMap<UserName, UserPresence> = HashMap::new();
-        for user in last_action.users {
-            users.insert(
-                user.name,
-                UserPresence {
-                    since: user.since,
-                    last_seen: now,
-                    status: PresentUserStatus::Present,
-                },
-            );
-        }
-        let mut anonymous_presence: HashMap<u64, AnonymousPresence> = HashMap::new();
-        let mut changed = false;
+        let mut tracker = Tracker::new(last_action, clock.now());
         loop {
+            if let Some(mut presence_action) = tracker.tick(clock.now()) {
+                let mut con = shared_con.lock().unwrap();
+                presence_action.store(&mut **con, mqtt.as_ref());
+            }
+
+            thread::sleep(Duration::new(20, 0)); // create one presence action at most every 20s
+
+            // add requests to user list
+            let now = clock.now();
+            loop {
+                match rx.try_recv() {
+                    Ok(request) => tracker.handle_request(request, now),
+                    Err(TryRecvError::Empty) => {
+                        break;
+                    }
+                    Err(TryRecvError::Disconnected) => {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    struct AnonymousPresence {
+        anonymous_users: f32,
+        last_seen: i64,
+    }
+    #[derive(Debug)]
+    struct UserPresence {
+        since: i64,
+        last_seen: i64,
+        status: PresentUserStatus,
+    }
+
+    /// Keeps track of who is present, and creates presence actions on changes.
+    pub struct Tracker {
+        users: HashMap<UserName, UserPresence>,
+        anonymous_presence: HashMap<u64, AnonymousPresence>,
+        changed: bool,
+    }
+
+    impl Tracker {
+        /// Continue with the users present in the last stored presence action.
+        pub fn new(last_action: PresenceAction, now: i64) -> Self {
+            let mut users: HashMap<UserName, UserPresence> = HashMap::new();
+            for user in last_action.users {
+                users.insert(
+                    user.name,
+                    UserPresence {
+                        since: user.since,
+                        last_seen: now,
+                        status: PresentUserStatus::Present,
+                    },
+                );
+            }
+            Tracker {
+                users,
+                anonymous_presence: HashMap::new(),
+                changed: false,
+            }
+        }
+
+        pub fn handle_request(&mut self, request: PresenceRequest, now: i64) {
+            match request {
+                PresenceRequest::NamedUser { user: username } => {
+                    let presence = self.users.entry(username).or_insert_with(|| {
+                        self.changed = true;
+                        UserPresence {
+                            since: now,
+                            last_seen: now,
+                            status: PresentUserStatus::Joined,
+                        }
+                    });
+                    presence.last_seen = now;
+                }
+                PresenceRequest::AnonymousUsers {
+                    anonymous_client_id,
+                    anonymous_users,
+                } => {
+                    let entry = self
+                        .anonymous_presence
+                        .entry(anonymous_client_id)
+                        .or_insert_with(|| {
+                            self.changed = true;
+                            AnonymousPresence {
+                                anonymous_users,
+                                last_seen: now,
+                            }
+                        });
+                    if !approx_eq!(f32, anonymous_users, entry.anonymous_users, ulps = 2) {
+                        self.changed = true;
+                        entry.anonymous_users = anonymous_users;
+                    }
+                    entry.last_seen = now;
+                }
+            }
+        }
+
+        /// Times out users and returns a new presence action, if anything changed since the
+        /// last tick.
+        pub fn tick(&mut self, now: i64) -> Option<PresenceAction> {
             // scrape users with status=left
-            users.retain(|_, ref presence| {
+            self.users.retain(|_, presence| {
                 let keep = presence.status != PresentUserStatus::Left;
                 if !keep {
-                    changed = true;
+                    self.changed = true;
                 }
                 keep
             });
 
             // time out anonymous clients
-            anonymous_presence.retain(|_client_id, presence| {
-                let keep = presence.last_seen + 15 * 60 > now;
+            self.anonymous_presence.retain(|_client_id, presence| {
+                let keep = presence.last_seen + TIMEOUT > now;
                 if !keep {
-                    changed = true;
+                    self.changed = true;
                 }
                 keep
             });
 
             // presence requests time out after 15min + time slept
             // set these users' status to left
-            for (_user, presence) in users.iter_mut() {
-                // use values_mut() when stable
-                if presence.last_seen + 15 * 60 <= now {
+            for presence in self.users.values_mut() {
+                if presence.last_seen + TIMEOUT <= now {
                     presence.status = PresentUserStatus::Left;
-                    changed = true;
+                    self.changed = true;
                 }
             }
 
             // create action
-            let mut present_users = Vec::new();
-            for (user, presence) in users.iter() {
-                present_users.push(PresentNamedUser {
-                    name: user.clone(),
-                    since: presence.since,
-                    status: presence.status.clone(),
-                });
-            }
-            let anonymous_count = anonymous_presence.values().map(|a| a.anonymous_users).sum();
-            if changed {
-                let mut con = shared_con.lock().unwrap();
-                let mut presence_action =
-                    PresenceAction::new(String::from(""), present_users, anonymous_count);
-                let transaction = con.transaction().unwrap();
-                presence_action.store(&transaction, mqtt.as_ref());
-                transaction.commit().unwrap();
-                changed = false;
+            let mut presence_action = None;
+            if self.changed {
+                let mut present_users = Vec::new();
+                for (user, presence) in self.users.iter() {
+                    present_users.push(PresentNamedUser {
+                        name: user.clone(),
+                        since: presence.since,
+                        status: presence.status.clone(),
+                    });
+                }
+                let anonymous_count = self
+                    .anonymous_presence
+                    .values()
+                    .map(|a| a.anonymous_users)
+                    .sum();
+                presence_action = Some(PresenceAction::new_with_time(
+                    String::from(""),
+                    now,
+                    present_users,
+                    anonymous_count,
+                ));
+                self.changed = false;
             }
 
             // switch users with status=joined to present
-            for (_user, presence) in users.iter_mut() {
-                // use values_mut() when stable
+            for presence in self.users.values_mut() {
                 if presence.status == PresentUserStatus::Joined {
                     presence.status = PresentUserStatus::Present;
-                    changed = true;
+                    self.changed = true;
                 }
             }
 
-            thread::sleep(Duration::new(20, 0)); // create one presence action at most every 20s
-
-            // add requests to user list
-            now = Utc::now().timestamp();
-            loop {
-                match rx.try_recv() {
-                    Ok(PresenceRequest::NamedUser { user: username }) => {
-                        let presence = users.entry(username).or_insert_with(|| {
-                            changed = true;
-                            UserPresence {
-                                since: now,
-                                last_seen: now,
-                                status: PresentUserStatus::Joined,
-                            }
-                        });
-                        presence.last_seen = now;
-                    }
-                    Ok(PresenceRequest::AnonymousUsers {
-                        anonymous_client_id,
-                        anonymous_users,
-                    }) => {
-                        let entry = anonymous_presence
-                            .entry(anonymous_client_id)
-                            .or_insert_with(|| {
-                                changed = true;
-                                AnonymousPresence {
-                                    anonymous_users,
-                                    last_seen: now,
-                                }
-                            });
-                        if !approx_eq!(f32, anonymous_users, entry.anonymous_users, ulps = 2) {
-                            changed = true;
-                            entry.anonymous_users = anonymous_users;
-                        }
-                        entry.last_seen = now;
-                    }
-                    Err(TryRecvError::Empty) => {
-                        break;
-                    }
-                    Err(TryRecvError::Disconnected) => {
-                        return;
-                    }
-                }
-            }
+            presence_action
         }
     }
-}
 
-pub fn query(
-    type_: QueryActionType,
-    id: RangeExpr<IdExpr>,
-    time: RangeExpr<i64>,
-    count: u64,
-    take: Take,
-    con: &mut DbCon,
-) -> Result<Vec<TypedAction>, Error> {
-    let mut query_str = String::from("SELECT id, type FROM action WHERE");
-
-    // for livetime reasons we need to define these variables before params:
-    let id1;
-    let id2;
-    let time1;
-    let time2;
-    let count = count as i64;
-    let type_int: u8;
-
-    let mut params = Vec::<&dyn ToSql>::new();
-
-    match id {
-        RangeExpr::Single(IdExpr::Int(i)) => {
-            id1 = i as i64;
-            query_str.push_str(" id=?");
-            params.push(&id1);
-        }
-        RangeExpr::Single(IdExpr::Last) => {
-            query_str.push_str(" 1");
-        }
-        RangeExpr::Range(IdExpr::Int(i1), IdExpr::Int(i2)) => {
-            id1 = i1 as i64;
-            id2 = i2 as i64;
-            query_str.push_str(" id >= ? AND id <= ?");
-            params.push(&id1);
-            params.push(&id2);
-        }
-        RangeExpr::Range(IdExpr::Int(i1), IdExpr::Last) => {
-            id1 = i1 as i64;
-            query_str.push_str(" id >= ?");
-            params.push(&id1);
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        fn named(name: &str) -> PresenceRequest {
+            PresenceRequest::NamedUser {
+                user: UserName::new(name.into()),
+            }
         }
-        RangeExpr::Range(_, _) => {
-            panic!("this case should be unreachable");
+
+        fn statuses(action: &PresenceAction) -> Vec<(&str, PresentUserStatus)> {
+            let mut users: Vec<_> = action
+                .users
+                .iter()
+                .map(|u| (u.name.as_str(), u.status.clone()))
+                .collect();
+            users.sort_by_key(|(name, _)| *name);
+            users
         }
-    }
 
-    match time {
-        RangeExpr::Single(t) => {
-            time1 = t;
-            time2 = t;
+        #[test]
+        fn named_user_times_out() {
+            let initial = PresenceAction::new_with_time(String::new(), 0, vec![], 0.0);
+            let mut tracker = Tracker::new(initial, 1000);
+            assert!(tracker.tick(1000).is_none());
+
+            tracker.handle_request(named("Hans Acker"), 1000);
+            let action = tracker.tick(1020).unwrap();
+            assert_eq!(action.action.time, 1020);
+            assert_eq!(
+                statuses(&action),
+                vec![("Hans Acker", PresentUserStatus::Joined)]
+            );
+            let action = tracker.tick(1040).unwrap();
+            assert_eq!(
+                statuses(&action),
+                vec![("Hans Acker", PresentUserStatus::Present)]
+            );
+            assert!(tracker.tick(1060).is_none());
+
+            // renewing the presence keeps the user present
+            tracker.handle_request(named("Hans Acker"), 1000 + TIMEOUT - 1);
+            assert!(tracker.tick(1000 + TIMEOUT).is_none());
+
+            let action = tracker.tick(1000 + 2 * TIMEOUT - 1).unwrap();
+            assert_eq!(
+                statuses(&action),
+                vec![("Hans Acker", PresentUserStatus::Left)]
+            );
+            let action = tracker.tick(1000 + 2 * TIMEOUT + 19).unwrap();
+            assert!(action.users.is_empty());
+            assert!(tracker.tick(1000 + 2 * TIMEOUT + 39).is_none());
         }
-        RangeExpr::Range(t1, t2) => {
-            time1 = t1;
-            time2 = t2;
+
+        #[test]
+        fn users_from_last_action_time_out() {
+            let initial = PresenceAction::new_with_time(
+                String::new(),
+                0,
+                vec![PresentNamedUser {
+                    name: UserName::new("Frank Nord".into()),
+                    since: 10,
+                    status: PresentUserStatus::Present,
+                }],
+                0.0,
+            );
+            let mut tracker = Tracker::new(initial, 1000);
+            assert!(tracker.tick(1000 + TIMEOUT - 1).is_none());
+            let action = tracker.tick(1000 + TIMEOUT).unwrap();
+            assert_eq!(
+                statuses(&action),
+                vec![("Frank Nord", PresentUserStatus::Left)]
+            );
+            assert_eq!(action.users[0].since, 10);
         }
-    };
-    query_str.push_str(" AND \"time\" >= ? AND \"time\" <= ?");
-    params.push(&time1);
-    params.push(&time2);
-
-    if type_ != QueryActionType::All {
-        type_int = match type_ {
-            QueryActionType::Status => 0,
-            QueryActionType::Announcement => 1,
-            QueryActionType::Presence => 2,
-            _ => panic!(), // impossible
-        };
-        query_str.push_str(" AND type=?");
-        params.push(&type_int);
-    }
 
-    query_str.push_str(" ORDER BY id ");
-    query_str.push_str(match take {
-        Take::First => "ASC",
-        Take::Last => "DESC",
-    });
-    query_str.push_str(" LIMIT ?");
-    params.push(&count);
-
-    let mut stmt = con.prepare(&query_str[..]).unwrap();
-    let actions_iter = stmt
-        .query_map(&*params, |row| -> Result<TypedAction, Error> {
-            // FIXME: this looks very inefficient, are we doing a query _per returned action_!?
-            match row.get(1)? {
-                0 => Ok(TypedAction::Status(status::get_by_id(
-                    row.get::<_, i64>(0)? as u64,
-                    con,
-                )?)),
-                1 => Ok(TypedAction::Announcement(announcements::get_by_id(
-                    row.get::<_, i64>(0)? as u64,
-                    con,
-                )?)),
-                2 => Ok(TypedAction::Presence(presence::get_by_id(
-                    row.get::<_, i64>(0)? as u64,
-                    con,
-                )?)),
-                id => panic!("unknown action type in db: {}", id),
-            }
-        })
-        .unwrap();
-    let mut actions: Vec<TypedAction> = actions_iter.map(|res| res.unwrap()).collect();
-    if take == Take::Last {
-        actions.reverse();
+        #[test]
+        fn anonymous_users_time_out() {
+            let initial = PresenceAction::new_with_time(String::new(), 0, vec![], 0.0);
+            let mut tracker = Tracker::new(initial, 0);
+            let anonymous =
+                |anonymous_client_id, anonymous_users| PresenceRequest::AnonymousUsers {
+                    anonymous_client_id,
+                    anonymous_users,
+                };
+            tracker.handle_request(anonymous(1, 2.0), 0);
+            tracker.handle_request(anonymous(2, 0.5), 100);
+            assert_eq!(tracker.tick(120).unwrap().anonymous_users, 2.5);
+
+            // same count again is no change
+            tracker.handle_request(anonymous(1, 2.0), 200);
+            assert!(tracker.tick(220).is_none());
+            tracker.handle_request(anonymous(1, 3.0), 300);
+            assert_eq!(tracker.tick(320).unwrap().anonymous_users, 3.5);
+
+            assert!(tracker.tick(100 + TIMEOUT - 1).is_none());
+            assert_eq!(tracker.tick(100 + TIMEOUT).unwrap().anonymous_users, 3.0);
+            assert_eq!(tracker.tick(300 + TIMEOUT).unwrap().anonymous_users, 0.0);
+        }
     }
-    Ok(actions)
 }
@@ -0,0 +1,1572 @@
+use postgres::types::ToSql;
+use postgres::{Client, NoTls, Row};
+
+use crate::api::{AnnouncementFilter, IdExpr, RangeExpr, Take};
+use crate::db::{AnnouncementWindow, Error, SCHEMA_VERSION, Storage};
+use crate::model::QueryActionType;
+use clubstatus_types::{
+    AnnouncementAction, AnnouncementMethod, AnnouncementSeries, BaseAction, HandoverAction,
+    PresenceAction, PresentNamedUser, PresentUserStatus, RsvpAction, RsvpAnswer, ScheduledStatus,
+    Status, StatusAction, TypedAction, UserName,
+};
+
+/// Storage backend for running clubstatusd next to other services using PostgreSQL.
+///
+/// Uses the same tables and integer encodings as the SQLite backend.
+pub struct PostgresStorage {
+    client: Client,
+    /// How many `lock_writes` have not been unlocked yet.
+    write_locks: u32,
+}
+
+/// Key of the advisory lock taken by `lock_writes`.
+const WRITE_LOCK: i64 = 0x636c_7562_7374_6174;
+
+impl PostgresStorage {
+    pub fn connect(params: &str) -> Result<Self, Error> {
+        Ok(PostgresStorage {
+            client: blocking(|| Client::connect(params, NoTls))?,
+            write_locks: 0,
+        })
+    }
+
+    fn presence_by_base_action(&mut self, action: BaseAction) -> Result<PresenceAction, Error> {
+        let id = action.id.unwrap() as i64;
+        let mut users: Vec<PresentNamedUser> = self
+            .client
+            .query(
+                "SELECT \"user\", since FROM presence_action WHERE id = $1",
+                &[&id],
+            )?
+            .iter()
+            .map(|row| PresentNamedUser {
+                name: UserName::new(row.get(0)),
+                since: row.get(1),
+                status: PresentUserStatus::Present,
+            })
+            .collect();
+        users.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let anonymous_users = self
+            .client
+            .query_opt(
+                "SELECT anonymous_users FROM presence_anon_action WHERE id = $1",
+                &[&id],
+            )?
+            .map(|row| row.get(0))
+            .unwrap_or(0.0);
+
+        Ok(PresenceAction {
+            action,
+            users,
+            anonymous_users,
+        })
+    }
+}
+
+/// The synchronous postgres client runs its own tokio runtime, which panics when it is blocked on
+/// from within Rocket's runtime. So tell Rocket's runtime first that this thread will block.
+fn blocking<T>(f: impl FnOnce() -> T) -> T {
+    match rocket::tokio::runtime::Handle::try_current() {
+        Ok(_) => rocket::tokio::task::block_in_place(f),
+        Err(_) => f(),
+    }
+}
+
+fn status_to_int(status: Status) -> i16 {
+    match status {
+        Status::Public => 2,
+        Status::Private => 1,
+        Status::Closed => 0,
+    }
+}
+
+fn int_to_status(i: i16) -> Status {
+    match i {
+        0 => Status::Closed,
+        1 => Status::Private,
+        2 => Status::Public,
+        unexpected => panic!("unexpected value for status: {unexpected}"),
+    }
+}
+
+fn method_to_int(method: AnnouncementMethod) -> i16 {
+    match method {
+        AnnouncementMethod::New => 0,
+        AnnouncementMethod::Mod => 1,
+        AnnouncementMethod::Del => 2,
+    }
+}
+
+fn int_to_method(i: i16) -> AnnouncementMethod {
+    match i {
+        0 => AnnouncementMethod::New,
+        1 => AnnouncementMethod::Mod,
+        2 => AnnouncementMethod::Del,
+        unexpected => panic!("unexpected value for method: {unexpected}"),
+    }
+}
+
+fn answer_to_int(answer: RsvpAnswer) -> i16 {
+    match answer {
+        RsvpAnswer::Yes => 2,
+        RsvpAnswer::Maybe => 1,
+        RsvpAnswer::No => 0,
+    }
+}
+
+fn int_to_answer(i: i16) -> RsvpAnswer {
+    match i {
+        0 => RsvpAnswer::No,
+        1 => RsvpAnswer::Maybe,
+        2 => RsvpAnswer::Yes,
+        unexpected => panic!("unexpected value for answer: {unexpected}"),
+    }
+}
+
+fn row_to_base_action(row: &Row) -> BaseAction {
+    BaseAction {
+        id: Some(row.get::<_, i64>("id") as u64),
+        time: row.get("time"),
+        note: row.get("note"),
+    }
+}
+
+fn row_to_status_action(row: &Row) -> StatusAction {
+    StatusAction {
+        action: row_to_base_action(row),
+        user: UserName::new(row.get("user")),
+        status: int_to_status(row.get("status")),
+        until: row.get("until"),
+        revert_to: row.get::<_, Option<i16>>("revert_to").map(int_to_status),
+        reverts: row.get::<_, Option<i64>>("reverts").map(|id| id as u64),
+        keyholder: row.get::<_, Option<String>>("keyholder").map(UserName::new),
+        zone: row.get("zone"),
+        public_message: row.get("public_message"),
+    }
+}
+
+fn row_to_scheduled_status(row: &Row) -> ScheduledStatus {
+    ScheduledStatus {
+        id: Some(row.get::<_, i64>("id") as u64),
+        time: row.get("time"),
+        at: row.get("at"),
+        note: row.get("note"),
+        user: UserName::new(row.get("user")),
+        status: int_to_status(row.get("status")),
+        until: row.get("until"),
+        revert_to: row.get::<_, Option<i16>>("revert_to").map(int_to_status),
+        keyholder: row.get::<_, Option<String>>("keyholder").map(UserName::new),
+        public_message: row.get("public_message"),
+    }
+}
+
+fn row_to_handover_action(row: &Row) -> HandoverAction {
+    HandoverAction {
+        action: row_to_base_action(row),
+        user: UserName::new(row.get("user")),
+        from: row.get::<_, Option<String>>("from").map(UserName::new),
+        to: UserName::new(row.get("to")),
+    }
+}
+
+fn row_to_rsvp_action(row: &Row) -> RsvpAction {
+    RsvpAction {
+        action: row_to_base_action(row),
+        aid: row.get::<_, i64>("aid") as u64,
+        user: UserName::new(row.get("user")),
+        answer: int_to_answer(row.get("answer")),
+    }
+}
+
+fn row_to_announcement_action(row: &Row) -> AnnouncementAction {
+    AnnouncementAction {
+        action: row_to_base_action(row),
+        method: int_to_method(row.get("method")),
+        aid: Some(row.get::<_, i64>("aid") as u64),
+        user: UserName::new(row.get("user")),
+        from: row.get("from"),
+        to: row.get("to"),
+        public: row.get("public"),
+        url: row
+            .get::<_, Option<String>>("url")
+            .map(|url| url.parse().expect("invalid url in db")),
+        series: row.get::<_, Option<i64>>("series").map(|id| id as u64),
+        recurrence_id: row.get("recurrence_id"),
+        category: row.get("category"),
+        tags: row.get("tags"),
+        description: row.get("description"),
+        resources: row.get("resources"),
+    }
+}
+
+fn row_to_announcement_series(row: &Row) -> AnnouncementSeries {
+    AnnouncementSeries {
+        id: Some(row.get::<_, i64>("id") as u64),
+        time: row.get("time"),
+        user: UserName::new(row.get("user")),
+        note: row.get("note"),
+        rrule: row.get("rrule"),
+        timezone: row.get("timezone"),
+        from: row.get("from"),
+        duration: row.get("duration"),
+        public: row.get("public"),
+        url: row
+            .get::<_, Option<String>>("url")
+            .map(|url| url.parse().expect("invalid url in db")),
+        materialized_until: row.get("materialized_until"),
+        deleted: row.get("deleted"),
+        category: row.get("category"),
+        tags: row.get("tags"),
+        description: row.get("description"),
+    }
+}
+
+const STATUS_SELECT: &str = "SELECT action.id, action.time, action.note, \
+     status_action.\"user\", status_action.status, status_action.until, \
+     status_action.revert_to, status_action.reverts, status_action.keyholder, \
+     status_action.zone, status_action.public_message \
+     FROM action JOIN status_action ON action.id = status_action.id";
+
+/// Condition for STATUS_SELECT, skipping status actions that have been reverted.
+const NOT_REVERTED: &str = "NOT EXISTS \
+     (SELECT 1 FROM status_action AS r WHERE r.reverts = action.id)";
+
+const HANDOVER_SELECT: &str = "SELECT action.id, action.time, action.note, \
+     handover_action.\"user\", handover_action.\"from\", handover_action.\"to\" \
+     FROM action JOIN handover_action ON action.id = handover_action.id";
+
+const RSVP_SELECT: &str = "SELECT action.id, action.time, action.note, \
+     rsvp_action.aid, rsvp_action.\"user\", rsvp_action.answer \
+     FROM action JOIN rsvp_action ON action.id = rsvp_action.id";
+
+/// Condition for STATUS_SELECT, matching the zone in $1 (NULL for the whole space).
+const SAME_ZONE: &str = "status_action.zone IS NOT DISTINCT FROM $1";
+
+const ANNOUNCEMENT_SELECT: &str = "SELECT action.id, action.time, action.note, \
+     announcement_action.method, announcement_action.aid, announcement_action.\"user\", \
+     announcement_action.\"from\", announcement_action.\"to\", announcement_action.public, \
+     announcement_action.url, announcement_action.series, announcement_action.recurrence_id, \
+     announcement_action.category, announcement_action.tags, announcement_action.description, \
+     announcement_action.resources \
+     FROM action JOIN announcement_action ON action.id = announcement_action.id";
+
+const ANNOUNCEMENT_SERIES_SELECT: &str = "SELECT id, time, \"user\", note, rrule, timezone, \
+     \"from\", duration, public, url, materialized_until, deleted, category, tags, description \
+     FROM announcement_series";
+
+impl Storage for PostgresStorage {
+    fn is_initialized(&mut self) -> Result<bool, Error> {
+        blocking(|| {
+            let row = self
+                .client
+                .query_one("SELECT to_regclass('action') IS NOT NULL", &[])?;
+            Ok(row.get(0))
+        })
+    }
+
+    fn create_tables(&mut self) -> Result<(), Error> {
+        // see the SQLite backend for the meaning of the integer columns
+        blocking(|| {
+            let mut tx = self.client.transaction()?;
+            tx.batch_execute(
+                "CREATE TABLE action (
+                     id BIGSERIAL PRIMARY KEY,
+                     time BIGINT NOT NULL,
+                     type SMALLINT NOT NULL,
+                     note TEXT NOT NULL
+                 );
+                 CREATE TABLE status_action (
+                     id BIGINT PRIMARY KEY REFERENCES action (id),
+                     \"user\" TEXT NOT NULL,
+                     status SMALLINT NOT NULL,
+                     changed BOOLEAN NOT NULL,
+                     public_changed BOOLEAN NOT NULL,
+                     until BIGINT,
+                     revert_to SMALLINT,
+                     reverts BIGINT REFERENCES action (id),
+                     keyholder TEXT,
+                     zone TEXT,
+                     public_message TEXT
+                 );
+                 CREATE TABLE scheduled_status (
+                     id BIGSERIAL PRIMARY KEY,
+                     time BIGINT NOT NULL,
+                     at BIGINT NOT NULL,
+                     note TEXT NOT NULL,
+                     \"user\" TEXT NOT NULL,
+                     status SMALLINT NOT NULL,
+                     until BIGINT,
+                     revert_to SMALLINT,
+                     keyholder TEXT,
+                     public_message TEXT
+                 );
+                 CREATE TABLE handover_action (
+                     id BIGINT PRIMARY KEY REFERENCES action (id),
+                     \"user\" TEXT NOT NULL,
+                     \"from\" TEXT,
+                     \"to\" TEXT NOT NULL
+                 );
+                 CREATE TABLE announcement_action (
+                     id BIGINT PRIMARY KEY REFERENCES action (id),
+                     method SMALLINT NOT NULL,
+                     aid BIGINT NOT NULL,
+                     \"user\" TEXT NOT NULL,
+                     \"from\" BIGINT NOT NULL,
+                     \"to\" BIGINT NOT NULL,
+                     public BOOLEAN NOT NULL,
+                     url TEXT,
+                     series BIGINT,
+                     recurrence_id BIGINT,
+                     category TEXT,
+                     tags TEXT[] NOT NULL,
+                     description TEXT,
+                     resources TEXT[] NOT NULL
+                 );
+                 CREATE INDEX announcement_action_aid ON announcement_action (aid);
+                 CREATE TABLE announcement_series (
+                     id BIGSERIAL PRIMARY KEY,
+                     time BIGINT NOT NULL,
+                     \"user\" TEXT NOT NULL,
+                     note TEXT NOT NULL,
+                     rrule TEXT NOT NULL,
+                     timezone TEXT NOT NULL,
+                     \"from\" BIGINT NOT NULL,
+                     duration BIGINT NOT NULL,
+                     public BOOLEAN NOT NULL,
+                     url TEXT,
+                     materialized_until BIGINT NOT NULL,
+                     deleted BOOLEAN NOT NULL,
+                     category TEXT,
+                     tags TEXT[] NOT NULL,
+                     description TEXT
+                 );
+                 CREATE TABLE sent_reminder (
+                     aid BIGINT NOT NULL,
+                     reminder TEXT NOT NULL,
+                     \"from\" BIGINT NOT NULL,
+                     time BIGINT NOT NULL,
+                     PRIMARY KEY (aid, reminder, \"from\")
+                 );
+                 CREATE TABLE rsvp_action (
+                     id BIGINT PRIMARY KEY REFERENCES action (id),
+                     aid BIGINT NOT NULL,
+                     \"user\" TEXT NOT NULL,
+                     answer SMALLINT NOT NULL
+                 );
+                 CREATE INDEX rsvp_action_aid ON rsvp_action (aid);
+                 CREATE TABLE presence_action (
+                     id BIGINT NOT NULL REFERENCES action (id),
+                     \"user\" TEXT NOT NULL,
+                     since BIGINT NOT NULL
+                 );
+                 CREATE TABLE presence_anon_action (
+                     id BIGINT PRIMARY KEY REFERENCES action (id),
+                     anonymous_users REAL NOT NULL
+                 );
+                 CREATE TABLE schema_version (version INTEGER NOT NULL);",
+            )?;
+            tx.execute(
+                "INSERT INTO schema_version (version) VALUES ($1)",
+                &[&(SCHEMA_VERSION as i32)],
+            )?;
+            tx.commit()?;
+            Ok(())
+        })
+    }
+
+    fn schema_version(&mut self) -> Result<u32, Error> {
+        blocking(|| {
+            let exists: bool = self
+                .client
+                .query_one("SELECT to_regclass('schema_version') IS NOT NULL", &[])?
+                .get(0);
+            if !exists {
+                return Ok(0);
+            }
+            let row = self
+                .client
+                .query_one("SELECT version FROM schema_version", &[])?;
+            Ok(row.get::<_, i32>(0) as u32)
+        })
+    }
+
+    fn migrate(&mut self, version: u32) -> Result<(), Error> {
+        blocking(|| {
+            let mut tx = self.client.transaction()?;
+            match version {
+                // scheduled status changes
+                1 => tx.batch_execute(
+                    "CREATE TABLE IF NOT EXISTS scheduled_status (
+                         id BIGSERIAL PRIMARY KEY,
+                         time BIGINT NOT NULL,
+                         at BIGINT NOT NULL,
+                         note TEXT NOT NULL,
+                         \"user\" TEXT NOT NULL,
+                         status SMALLINT NOT NULL
+                     );",
+                )?,
+                // status until and revert_to
+                2 => tx.batch_execute(
+                    "ALTER TABLE status_action
+                         ADD COLUMN IF NOT EXISTS until BIGINT,
+                         ADD COLUMN IF NOT EXISTS revert_to SMALLINT;
+                     ALTER TABLE scheduled_status
+                         ADD COLUMN IF NOT EXISTS until BIGINT,
+                         ADD COLUMN IF NOT EXISTS revert_to SMALLINT;",
+                )?,
+                // status reverts
+                3 => tx.batch_execute(
+                    "ALTER TABLE status_action
+                         ADD COLUMN IF NOT EXISTS reverts BIGINT REFERENCES action (id);",
+                )?,
+                // keyholders and handovers
+                4 => tx.batch_execute(
+                    "ALTER TABLE status_action ADD COLUMN IF NOT EXISTS keyholder TEXT;
+                     ALTER TABLE scheduled_status ADD COLUMN IF NOT EXISTS keyholder TEXT;
+                     CREATE TABLE IF NOT EXISTS handover_action (
+                         id BIGINT PRIMARY KEY REFERENCES action (id),
+                         \"user\" TEXT NOT NULL,
+                         \"from\" TEXT,
+                         \"to\" TEXT NOT NULL
+                     );",
+                )?,
+                // zones
+                5 => tx.batch_execute(
+                    "ALTER TABLE status_action ADD COLUMN IF NOT EXISTS zone TEXT;",
+                )?,
+                // public messages
+                6 => tx.batch_execute(
+                    "ALTER TABLE status_action ADD COLUMN IF NOT EXISTS public_message TEXT;
+                     ALTER TABLE scheduled_status ADD COLUMN IF NOT EXISTS public_message TEXT;",
+                )?,
+                // announcement series
+                7 => tx.batch_execute(
+                    "ALTER TABLE announcement_action
+                         ADD COLUMN IF NOT EXISTS series BIGINT,
+                         ADD COLUMN IF NOT EXISTS recurrence_id BIGINT;
+                     CREATE TABLE IF NOT EXISTS announcement_series (
+                         id BIGSERIAL PRIMARY KEY,
+                         time BIGINT NOT NULL,
+                         \"user\" TEXT NOT NULL,
+                         note TEXT NOT NULL,
+                         rrule TEXT NOT NULL,
+                         timezone TEXT NOT NULL,
+                         \"from\" BIGINT NOT NULL,
+                         duration BIGINT NOT NULL,
+                         public BOOLEAN NOT NULL,
+                         url TEXT,
+                         materialized_until BIGINT NOT NULL,
+                         deleted BOOLEAN NOT NULL
+                     );",
+                )?,
+                // sent reminders
+                8 => tx.batch_execute(
+                    "CREATE TABLE IF NOT EXISTS sent_reminder (
+                         aid BIGINT NOT NULL,
+                         reminder TEXT NOT NULL,
+                         \"from\" BIGINT NOT NULL,
+                         time BIGINT NOT NULL,
+                         PRIMARY KEY (aid, reminder, \"from\")
+                     );",
+                )?,
+                // RSVPs
+                9 => tx.batch_execute(
+                    "CREATE TABLE IF NOT EXISTS rsvp_action (
+                         id BIGINT PRIMARY KEY REFERENCES action (id),
+                         aid BIGINT NOT NULL,
+                         \"user\" TEXT NOT NULL,
+                         answer SMALLINT NOT NULL
+                     );
+                     CREATE INDEX IF NOT EXISTS rsvp_action_aid ON rsvp_action (aid);",
+                )?,
+                // announcement categories and tags
+                10 => tx.batch_execute(
+                    "ALTER TABLE announcement_action
+                         ADD COLUMN IF NOT EXISTS category TEXT,
+                         ADD COLUMN IF NOT EXISTS tags TEXT[] NOT NULL DEFAULT '{}';
+                     ALTER TABLE announcement_series
+                         ADD COLUMN IF NOT EXISTS category TEXT,
+                         ADD COLUMN IF NOT EXISTS tags TEXT[] NOT NULL DEFAULT '{}';",
+                )?,
+                // announcement descriptions
+                11 => tx.batch_execute(
+                    "ALTER TABLE announcement_action ADD COLUMN IF NOT EXISTS description TEXT;
+                     ALTER TABLE announcement_series ADD COLUMN IF NOT EXISTS description TEXT;",
+                )?,
+                // resources
+                12 => tx.batch_execute(
+                    "ALTER TABLE announcement_action
+                         ADD COLUMN IF NOT EXISTS resources TEXT[] NOT NULL DEFAULT '{}';",
+                )?,
+                _ => panic!("there is no schema version {version}"),
+            }
+            tx.batch_execute(
+                "CREATE TABLE IF NOT EXISTS schema_version (version INTEGER NOT NULL);
+                 DELETE FROM schema_version;",
+            )?;
+            tx.execute(
+                "INSERT INTO schema_version (version) VALUES ($1)",
+                &[&(version as i32)],
+            )?;
+            tx.commit()?;
+            Ok(())
+        })
+    }
+
+    fn lock_writes(&mut self) -> Result<(), Error> {
+        // a session lock, so the inserts can still use transactions of their own
+        if self.write_locks == 0 {
+            blocking(|| {
+                self.client
+                    .execute("SELECT pg_advisory_lock($1)", &[&WRITE_LOCK])
+            })?;
+        }
+        self.write_locks += 1;
+        Ok(())
+    }
+
+    fn unlock_writes(&mut self) -> Result<(), Error> {
+        self.write_locks -= 1;
+        if self.write_locks == 0 {
+            blocking(|| {
+                self.client
+                    .execute("SELECT pg_advisory_unlock($1)", &[&WRITE_LOCK])
+            })?;
+        }
+        Ok(())
+    }
+
+    /*
+     * Status
+     */
+
+    fn insert_status(
+        &mut self,
+        action: &mut StatusAction,
+        changed: bool,
+        public_changed: bool,
+    ) -> Result<u64, Error> {
+        blocking(|| {
+            let mut tx = self.client.transaction()?;
+            let action_id: i64 = tx
+                .query_one(
+                    "INSERT INTO action (time, type, note) VALUES ($1, 0, $2) RETURNING id",
+                    &[&action.action.time, &action.action.note],
+                )?
+                .get(0);
+            tx.execute(
+                "INSERT INTO status_action \
+                 (id, \"user\", status, changed, public_changed, until, revert_to, reverts, \
+                 keyholder, zone, public_message) \
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
+                &[
+                    &action_id,
+                    &action.user.as_str(),
+                    &status_to_int(action.status),
+                    &changed,
+                    &public_changed,
+                    &action.until,
+                    &action.revert_to.map(status_to_int),
+                    &action.reverts.map(|id| id as i64),
+                    &action.keyholder.as_ref().map(|k| k.as_str()),
+                    &action.zone,
+                    &action.public_message,
+                ],
+            )?;
+            tx.commit()?;
+            action.action.id = Some(action_id as u64);
+            Ok(action_id as u64)
+        })
+    }
+
+    fn get_status(&mut self, id: u64) -> Result<StatusAction, Error> {
+        blocking(|| {
+            self.client
+                .query_opt(
+                    format!("{STATUS_SELECT} WHERE action.id = $1").as_str(),
+                    &[&(id as i64)],
+                )?
+                .map(|row| row_to_status_action(&row))
+                .ok_or(Error::NotFound)
+        })
+    }
+
+    fn get_last_zone_status(&mut self, zone: Option<&str>) -> Result<StatusAction, Error> {
+        blocking(|| {
+            self.client
+                .query_opt(
+                    format!(
+                        "{STATUS_SELECT} WHERE {SAME_ZONE} \
+                         ORDER BY action.id DESC LIMIT 1"
+                    )
+                    .as_str(),
+                    &[&zone],
+                )?
+                .map(|row| row_to_status_action(&row))
+                .ok_or(Error::NotFound)
+        })
+    }
+
+    fn get_last_changed_zone_status(&mut self, zone: Option<&str>) -> Result<StatusAction, Error> {
+        blocking(|| {
+            self.client
+                .query_opt(
+                    format!(
+                        "{STATUS_SELECT} WHERE {SAME_ZONE} AND status_action.changed AND \
+                         {NOT_REVERTED} ORDER BY action.id DESC LIMIT 1"
+                    )
+                    .as_str(),
+                    &[&zone],
+                )?
+                .map(|row| row_to_status_action(&row))
+                .ok_or(Error::NotFound)
+        })
+    }
+
+    fn get_last_changed_public_zone_status(
+        &mut self,
+        zone: Option<&str>,
+    ) -> Result<StatusAction, Error> {
+        blocking(|| {
+            self.client
+                .query_opt(
+                    format!(
+                        "{STATUS_SELECT} WHERE {SAME_ZONE} AND status_action.public_changed AND \
+                         {NOT_REVERTED} ORDER BY action.id DESC LIMIT 1"
+                    )
+                    .as_str(),
+                    &[&zone],
+                )?
+                .map(|row| row_to_status_action(&row))
+                .ok_or(Error::NotFound)
+        })
+    }
+
+    fn get_status_changes(&mut self, since: i64, count: u64) -> Result<Vec<StatusAction>, Error> {
+        blocking(|| {
+            Ok(self
+                .client
+                .query(
+                    format!(
+                        "{STATUS_SELECT} WHERE status_action.zone IS NULL AND \
+                         status_action.changed AND action.time >= $1 AND {NOT_REVERTED} \
+                         ORDER BY action.id DESC LIMIT $2"
+                    )
+                    .as_str(),
+                    &[&since, &(count as i64)],
+                )?
+                .iter()
+                .map(row_to_status_action)
+                .collect())
+        })
+    }
+
+    fn get_public_status_changes(
+        &mut self,
+        since: i64,
+        count: u64,
+    ) -> Result<Vec<StatusAction>, Error> {
+        blocking(|| {
+            Ok(self
+                .client
+                .query(
+                    format!(
+                        "{STATUS_SELECT} WHERE status_action.zone IS NULL AND \
+                         status_action.public_changed AND action.time >= $1 AND {NOT_REVERTED} \
+                         ORDER BY action.id DESC LIMIT $2"
+                    )
+                    .as_str(),
+                    &[&since, &(count as i64)],
+                )?
+                .iter()
+                .map(row_to_status_action)
+                .collect())
+        })
+    }
+
+    fn get_status_change_before(&mut self, time: i64) -> Result<StatusAction, Error> {
+        blocking(|| {
+            self.client
+                .query_opt(
+                    format!(
+                        "{STATUS_SELECT} WHERE status_action.zone IS NULL AND \
+                         status_action.changed AND action.time < $1 AND {NOT_REVERTED} \
+                         ORDER BY action.id DESC LIMIT 1"
+                    )
+                    .as_str(),
+                    &[&time],
+                )?
+                .map(|row| row_to_status_action(&row))
+                .ok_or(Error::NotFound)
+        })
+    }
+
+    fn get_public_status_change_before(&mut self, time: i64) -> Result<StatusAction, Error> {
+        blocking(|| {
+            self.client
+                .query_opt(
+                    format!(
+                        "{STATUS_SELECT} WHERE status_action.zone IS NULL AND \
+                         status_action.public_changed AND action.time < $1 AND {NOT_REVERTED} \
+                         ORDER BY action.id DESC LIMIT 1"
+                    )
+                    .as_str(),
+                    &[&time],
+                )?
+                .map(|row| row_to_status_action(&row))
+                .ok_or(Error::NotFound)
+        })
+    }
+
+    fn get_last_status_before(&mut self, id: u64) -> Result<StatusAction, Error> {
+        blocking(|| {
+            self.client
+                .query_opt(
+                    format!(
+                        "{STATUS_SELECT} WHERE action.id < $1 AND status_action.zone IS NULL \
+                         AND {NOT_REVERTED} \
+                         ORDER BY action.id DESC LIMIT 1"
+                    )
+                    .as_str(),
+                    &[&(id as i64)],
+                )?
+                .map(|row| row_to_status_action(&row))
+                .ok_or(Error::NotFound)
+        })
+    }
+
+    fn insert_scheduled_status(&mut self, scheduled: &mut ScheduledStatus) -> Result<u64, Error> {
+        blocking(|| {
+            let id: i64 = self
+                .client
+                .query_one(
+                    "INSERT INTO scheduled_status \
+                     (time, at, note, \"user\", status, until, revert_to, keyholder, \
+                     public_message) \
+                     VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING id",
+                    &[
+                        &scheduled.time,
+                        &scheduled.at,
+                        &scheduled.note,
+                        &scheduled.user.as_str(),
+                        &status_to_int(scheduled.status),
+                        &scheduled.until,
+                        &scheduled.revert_to.map(status_to_int),
+                        &scheduled.keyholder.as_ref().map(|k| k.as_str()),
+                        &scheduled.public_message,
+                    ],
+                )?
+                .get(0);
+            scheduled.id = Some(id as u64);
+            Ok(id as u64)
+        })
+    }
+
+    fn get_scheduled_statuses(&mut self) -> Result<Vec<ScheduledStatus>, Error> {
+        blocking(|| {
+            Ok(self
+                .client
+                .query(
+                    "SELECT id, time, at, note, \"user\", status, until, revert_to, keyholder, \
+                     public_message FROM scheduled_status \
+                     ORDER BY at, id",
+                    &[],
+                )?
+                .iter()
+                .map(row_to_scheduled_status)
+                .collect())
+        })
+    }
+
+    fn delete_scheduled_status(&mut self, id: u64) -> Result<bool, Error> {
+        blocking(|| {
+            let deleted = self.client.execute(
+                "DELETE FROM scheduled_status WHERE id = $1",
+                &[&(id as i64)],
+            )?;
+            Ok(deleted > 0)
+        })
+    }
+
+    /*
+     * Handovers
+     */
+
+    fn insert_handover(&mut self, action: &mut HandoverAction) -> Result<u64, Error> {
+        blocking(|| {
+            let mut tx = self.client.transaction()?;
+            let action_id: i64 = tx
+                .query_one(
+                    "INSERT INTO action (time, type, note) VALUES ($1, 3, $2) RETURNING id",
+                    &[&action.action.time, &action.action.note],
+                )?
+                .get(0);
+            tx.execute(
+                "INSERT INTO handover_action (id, \"user\", \"from\", \"to\") \
+                 VALUES ($1, $2, $3, $4)",
+                &[
+                    &action_id,
+                    &action.user.as_str(),
+                    &action.from.as_ref().map(|f| f.as_str()),
+                    &action.to.as_str(),
+                ],
+            )?;
+            tx.commit()?;
+            action.action.id = Some(action_id as u64);
+            Ok(action_id as u64)
+        })
+    }
+
+    fn get_handover(&mut self, id: u64) -> Result<HandoverAction, Error> {
+        blocking(|| {
+            self.client
+                .query_opt(
+                    format!("{HANDOVER_SELECT} WHERE action.id = $1").as_str(),
+                    &[&(id as i64)],
+                )?
+                .map(|row| row_to_handover_action(&row))
+                .ok_or(Error::NotFound)
+        })
+    }
+
+    fn get_last_handover(&mut self) -> Result<Option<HandoverAction>, Error> {
+        blocking(|| {
+            Ok(self
+                .client
+                .query_opt(
+                    format!("{HANDOVER_SELECT} ORDER BY action.id DESC LIMIT 1").as_str(),
+                    &[],
+                )?
+                .map(|row| row_to_handover_action(&row)))
+        })
+    }
+
+    /*
+     * Announcements
+     */
+
+    fn insert_announcement(&mut self, action: &mut AnnouncementAction) -> Result<u64, Error> {
+        blocking(|| {
+            let mut tx = self.client.transaction()?;
+            let action_id: i64 = tx
+                .query_one(
+                    "INSERT INTO action (time, type, note) VALUES ($1, 1, $2) RETURNING id",
+                    &[&action.action.time, &action.action.note],
+                )?
+                .get(0);
+            let aid = action.aid.map(|aid| aid as i64).unwrap_or(action_id);
+            tx.execute(
+                "INSERT INTO announcement_action \
+                 (id, method, aid, \"user\", \"from\", \"to\", public, url, series, \
+                 recurrence_id, category, tags, description, resources) VALUES \
+                 ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)",
+                &[
+                    &action_id,
+                    &method_to_int(action.method),
+                    &aid,
+                    &action.user.as_str(),
+                    &action.from,
+                    &action.to,
+                    &action.public,
+                    &action.url.as_ref().map(|url| url.as_str()),
+                    &action.series.map(|id| id as i64),
+                    &action.recurrence_id,
+                    &action.category,
+                    &action.tags,
+                    &action.description,
+                    &action.resources,
+                ],
+            )?;
+            tx.commit()?;
+            action.action.id = Some(action_id as u64);
+            action.aid = Some(aid as u64);
+            Ok(action_id as u64)
+        })
+    }
+
+    fn get_announcement(&mut self, id: u64) -> Result<AnnouncementAction, Error> {
+        blocking(|| {
+            self.client
+                .query_opt(
+                    format!("{ANNOUNCEMENT_SELECT} WHERE action.id = $1").as_str(),
+                    &[&(id as i64)],
+                )?
+                .map(|row| row_to_announcement_action(&row))
+                .ok_or(Error::NotFound)
+        })
+    }
+
+    fn get_last_announcement(&mut self, aid: u64) -> Result<Option<AnnouncementAction>, Error> {
+        blocking(|| {
+            Ok(self
+                .client
+                .query_opt(
+                    format!(
+                        "{ANNOUNCEMENT_SELECT} WHERE announcement_action.aid = $1 \
+                         ORDER BY action.id DESC LIMIT 1"
+                    )
+                    .as_str(),
+                    &[&(aid as i64)],
+                )?
+                .map(|row| row_to_announcement_action(&row)))
+        })
+    }
+
+    fn get_announcements(
+        &mut self,
+        window: &AnnouncementWindow,
+        filter: &AnnouncementFilter,
+    ) -> Result<Vec<AnnouncementAction>, Error> {
+        let mut query_str = format!(
+            "{ANNOUNCEMENT_SELECT} WHERE action.id IN ( \
+             SELECT max(id) FROM announcement_action GROUP BY aid \
+             ) AND \
+             announcement_action.method != 2"
+        );
+        let mut params = Vec::<Box<dyn ToSql + Sync>>::new();
+
+        if let Some(since) = window.since {
+            params.push(Box::new(since));
+            query_str.push_str(&format!(" AND ${} <= \"to\"", params.len()));
+        }
+        if let Some(until) = window.until {
+            params.push(Box::new(until));
+            query_str.push_str(&format!(" AND \"from\" < ${}", params.len()));
+        }
+        if let Some(ended_before) = window.ended_before {
+            params.push(Box::new(ended_before));
+            query_str.push_str(&format!(" AND \"to\" < ${}", params.len()));
+        }
+        if window.public {
+            query_str.push_str(" AND announcement_action.public");
+        }
+        if let Some(category) = &filter.category {
+            params.push(Box::new(category.clone()));
+            query_str.push_str(&format!(
+                " AND announcement_action.category = ${}",
+                params.len()
+            ));
+        }
+        if let Some(tag) = &filter.tag {
+            params.push(Box::new(tag.clone()));
+            query_str.push_str(&format!(
+                " AND ${} = ANY(announcement_action.tags)",
+                params.len()
+            ));
+        }
+
+        query_str.push_str(if window.ended_before.is_some() {
+            " ORDER BY \"to\" DESC, action.id DESC"
+        } else {
+            " ORDER BY \"from\", action.id"
+        });
+        // LIMIT NULL means no limit
+        params.push(Box::new(window.limit.map(|limit| limit as i64)));
+        params.push(Box::new(window.offset as i64));
+        query_str.push_str(&format!(
+            " LIMIT ${} OFFSET ${}",
+            params.len() - 1,
+            params.len()
+        ));
+
+        blocking(|| {
+            let params: Vec<&(dyn ToSql + Sync)> = params.iter().map(|p| p.as_ref()).collect();
+            Ok(self
+                .client
+                .query(query_str.as_str(), &params)?
+                .iter()
+                .map(row_to_announcement_action)
+                .collect())
+        })
+    }
+
+    fn get_series_announcements(&mut self, series: u64) -> Result<Vec<AnnouncementAction>, Error> {
+        blocking(|| {
+            Ok(self
+                .client
+                .query(
+                    format!(
+                        "{ANNOUNCEMENT_SELECT} WHERE action.id IN ( \
+                         SELECT max(id) FROM announcement_action GROUP BY aid \
+                         ) AND \
+                         announcement_action.series = $1 \
+                         ORDER BY announcement_action.recurrence_id, action.id"
+                    )
+                    .as_str(),
+                    &[&(series as i64)],
+                )?
+                .iter()
+                .map(row_to_announcement_action)
+                .collect())
+        })
+    }
+
+    fn insert_announcement_series(
+        &mut self,
+        series: &mut AnnouncementSeries,
+    ) -> Result<u64, Error> {
+        blocking(|| {
+            let id: i64 = self
+                .client
+                .query_one(
+                    "INSERT INTO announcement_series \
+                     (time, \"user\", note, rrule, timezone, \"from\", duration, public, url, \
+                     materialized_until, deleted, category, tags, description) \
+                     VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14) \
+                     RETURNING id",
+                    &[
+                        &series.time,
+                        &series.user.as_str(),
+                        &series.note,
+                        &series.rrule,
+                        &series.timezone,
+                        &series.from,
+                        &series.duration,
+                        &series.public,
+                        &series.url.as_ref().map(|url| url.as_str()),
+                        &series.materialized_until,
+                        &series.deleted,
+                        &series.category,
+                        &series.tags,
+                        &series.description,
+                    ],
+                )?
+                .get(0);
+            series.id = Some(id as u64);
+            Ok(id as u64)
+        })
+    }
+
+    fn update_announcement_series(&mut self, series: &AnnouncementSeries) -> Result<(), Error> {
+        blocking(|| {
+            let updated = self.client.execute(
+                "UPDATE announcement_series SET \
+                 time = $1, \"user\" = $2, note = $3, rrule = $4, timezone = $5, \"from\" = $6, \
+                 duration = $7, public = $8, url = $9, materialized_until = $10, deleted = $11, \
+                 category = $12, tags = $13, description = $14 WHERE id = $15",
+                &[
+                    &series.time,
+                    &series.user.as_str(),
+                    &series.note,
+                    &series.rrule,
+                    &series.timezone,
+                    &series.from,
+                    &series.duration,
+                    &series.public,
+                    &series.url.as_ref().map(|url| url.as_str()),
+                    &series.materialized_until,
+                    &series.deleted,
+                    &series.category,
+                    &series.tags,
+                    &series.description,
+                    &(series.id.unwrap() as i64),
+                ],
+            )?;
+            match updated {
+                0 => Err(Error::NotFound),
+                _ => Ok(()),
+            }
+        })
+    }
+
+    fn get_announcement_series(&mut self, id: u64) -> Result<AnnouncementSeries, Error> {
+        blocking(|| {
+            self.client
+                .query_opt(
+                    format!("{ANNOUNCEMENT_SERIES_SELECT} WHERE id = $1").as_str(),
+                    &[&(id as i64)],
+                )?
+                .map(|row| row_to_announcement_series(&row))
+                .ok_or(Error::NotFound)
+        })
+    }
+
+    fn get_active_announcement_series(&mut self) -> Result<Vec<AnnouncementSeries>, Error> {
+        blocking(|| {
+            Ok(self
+                .client
+                .query(
+                    format!("{ANNOUNCEMENT_SERIES_SELECT} WHERE NOT deleted ORDER BY id").as_str(),
+                    &[],
+                )?
+                .iter()
+                .map(row_to_announcement_series)
+                .collect())
+        })
+    }
+
+    /*
+     * RSVPs
+     */
+
+    fn insert_rsvp(&mut self, action: &mut RsvpAction) -> Result<u64, Error> {
+        blocking(|| {
+            let mut tx = self.client.transaction()?;
+            let action_id: i64 = tx
+                .query_one(
+                    "INSERT INTO action (time, type, note) VALUES ($1, 4, $2) RETURNING id",
+                    &[&action.action.time, &action.action.note],
+                )?
+                .get(0);
+            tx.execute(
+                "INSERT INTO rsvp_action (id, aid, \"user\", answer) VALUES ($1, $2, $3, $4)",
+                &[
+                    &action_id,
+                    &(action.aid as i64),
+                    &action.user.as_str(),
+                    &answer_to_int(action.answer),
+                ],
+            )?;
+            tx.commit()?;
+            action.action.id = Some(action_id as u64);
+            Ok(action_id as u64)
+        })
+    }
+
+    fn get_rsvp(&mut self, id: u64) -> Result<RsvpAction, Error> {
+        blocking(|| {
+            self.client
+                .query_opt(
+                    format!("{RSVP_SELECT} WHERE action.id = $1").as_str(),
+                    &[&(id as i64)],
+                )?
+                .map(|row| row_to_rsvp_action(&row))
+                .ok_or(Error::NotFound)
+        })
+    }
+
+    fn get_rsvps(&mut self, aid: u64) -> Result<Vec<RsvpAction>, Error> {
+        blocking(|| {
+            Ok(self
+                .client
+                .query(
+                    format!(
+                        "{RSVP_SELECT} WHERE action.id IN \
+                         (SELECT max(id) FROM rsvp_action WHERE aid = $1 GROUP BY \"user\") \
+                         ORDER BY rsvp_action.\"user\""
+                    )
+                    .as_str(),
+                    &[&(aid as i64)],
+                )?
+                .iter()
+                .map(row_to_rsvp_action)
+                .collect())
+        })
+    }
+
+    /*
+     * Reminders
+     */
+
+    fn insert_sent_reminder(
+        &mut self,
+        aid: u64,
+        reminder: &str,
+        from: i64,
+        time: i64,
+    ) -> Result<(), Error> {
+        blocking(|| {
+            self.client.execute(
+                "INSERT INTO sent_reminder (aid, reminder, \"from\", time) \
+                 VALUES ($1, $2, $3, $4) ON CONFLICT DO NOTHING",
+                &[&(aid as i64), &reminder, &from, &time],
+            )?;
+            Ok(())
+        })
+    }
+
+    fn reminder_sent(&mut self, aid: u64, reminder: &str, from: i64) -> Result<bool, Error> {
+        blocking(|| {
+            let row = self.client.query_one(
+                "SELECT EXISTS (SELECT 1 FROM sent_reminder \
+                 WHERE aid = $1 AND reminder = $2 AND \"from\" = $3)",
+                &[&(aid as i64), &reminder, &from],
+            )?;
+            Ok(row.get(0))
+        })
+    }
+
+    /*
+     * Presence
+     */
+
+    fn insert_presence(&mut self, action: &mut PresenceAction) -> Result<u64, Error> {
+        blocking(|| {
+            let mut tx = self.client.transaction()?;
+            let action_id: i64 = tx
+                .query_one(
+                    "INSERT INTO action (time, type, note) VALUES ($1, 2, $2) RETURNING id",
+                    &[&action.action.time, &action.action.note],
+                )?
+                .get(0);
+            for user in action.users.iter() {
+                match user.status {
+                    PresentUserStatus::Joined | PresentUserStatus::Present => {
+                        tx.execute(
+                            "INSERT INTO presence_action (id, \"user\", since) \
+                             VALUES ($1, $2, $3)",
+                            &[&action_id, &user.name.as_str(), &user.since],
+                        )?;
+                    }
+                    PresentUserStatus::Left => {}
+                }
+            }
+            tx.execute(
+                "INSERT INTO presence_anon_action (id, anonymous_users) VALUES ($1, $2)",
+                &[&action_id, &action.anonymous_users],
+            )?;
+            tx.commit()?;
+            action.action.id = Some(action_id as u64);
+            Ok(action_id as u64)
+        })
+    }
+
+    fn get_presence(&mut self, id: u64) -> Result<PresenceAction, Error> {
+        blocking(|| {
+            let action = self
+                .client
+                .query_opt(
+                    "SELECT id, time, note FROM action WHERE id = $1 AND type = 2",
+                    &[&(id as i64)],
+                )?
+                .map(|row| row_to_base_action(&row))
+                .ok_or(Error::NotFound)?;
+            self.presence_by_base_action(action)
+        })
+    }
+
+    fn get_last_presence(&mut self) -> Result<PresenceAction, Error> {
+        blocking(|| {
+            let action = self
+                .client
+                .query_opt(
+                    "SELECT id, time, note FROM action WHERE type = 2 ORDER BY id DESC LIMIT 1",
+                    &[],
+                )?
+                .map(|row| row_to_base_action(&row))
+                .ok_or(Error::NotFound)?;
+            self.presence_by_base_action(action)
+        })
+    }
+
+    fn query(
+        &mut self,
+        type_: QueryActionType,
+        id: RangeExpr<IdExpr>,
+        time: RangeExpr<i64>,
+        count: u64,
+        take: Take,
+        filter: &AnnouncementFilter,
+    ) -> Result<Vec<TypedAction>, Error> {
+        let mut query_str = String::from("SELECT id, type FROM action WHERE");
+        let mut params = Vec::<Box<dyn ToSql + Sync>>::new();
+
+        match id {
+            RangeExpr::Single(IdExpr::Int(i)) => {
+                params.push(Box::new(i as i64));
+                query_str.push_str(&format!(" id = ${}", params.len()));
+            }
+            RangeExpr::Single(IdExpr::Last) => {
+                query_str.push_str(" TRUE");
+            }
+            RangeExpr::Range(IdExpr::Int(i1), IdExpr::Int(i2)) => {
+                params.push(Box::new(i1 as i64));
+                params.push(Box::new(i2 as i64));
+                query_str.push_str(&format!(
+                    " id >= ${} AND id <= ${}",
+                    params.len() - 1,
+                    params.len()
+                ));
+            }
+            RangeExpr::Range(IdExpr::Int(i1), IdExpr::Last) => {
+                params.push(Box::new(i1 as i64));
+                query_str.push_str(&format!(" id >= ${}", params.len()));
+            }
+            RangeExpr::Range(_, _) => {
+                panic!("this case should be unreachable");
+            }
+        }
+
+        let (time1, time2) = match time {
+            RangeExpr::Single(t) => (t, t),
+            RangeExpr::Range(t1, t2) => (t1, t2),
+        };
+        params.push(Box::new(time1));
+        params.push(Box::new(time2));
+        query_str.push_str(&format!(
+            " AND time >= ${} AND time <= ${}",
+            params.len() - 1,
+            params.len()
+        ));
+
+        if type_ != QueryActionType::All {
+            let type_int: i16 = match type_ {
+                QueryActionType::Status => 0,
+                QueryActionType::Announcement => 1,
+                QueryActionType::Presence => 2,
+                QueryActionType::Handover => 3,
+                QueryActionType::Rsvp => 4,
+                _ => panic!(), // impossible
+            };
+            params.push(Box::new(type_int));
+            query_str.push_str(&format!(" AND type = ${}", params.len()));
+        }
+
+        // only announcements have a category and tags
+        if let Some(category) = &filter.category {
+            params.push(Box::new(category.clone()));
+            query_str.push_str(&format!(
+                " AND id IN (SELECT id FROM announcement_action WHERE category = ${})",
+                params.len()
+            ));
+        }
+        if let Some(tag) = &filter.tag {
+            params.push(Box::new(tag.clone()));
+            query_str.push_str(&format!(
+                " AND id IN (SELECT id FROM announcement_action WHERE ${} = ANY(tags))",
+                params.len()
+            ));
+        }
+
+        query_str.push_str(" ORDER BY id ");
+        query_str.push_str(match take {
+            Take::First => "ASC",
+            Take::Last => "DESC",
+        });
+        params.push(Box::new(count as i64));
+        query_str.push_str(&format!(" LIMIT ${}", params.len()));
+
+        let ids: Vec<(u64, i16)> = blocking(|| {
+            let params: Vec<&(dyn ToSql + Sync)> = params.iter().map(|p| p.as_ref()).collect();
+            self.client.query(query_str.as_str(), &params)
+        })?
+        .iter()
+        .map(|row| (row.get::<_, i64>(0) as u64, row.get(1)))
+        .collect();
+        let mut actions = ids
+            .into_iter()
+            .map(|(id, type_)| match type_ {
+                0 => Ok(TypedAction::Status(self.get_status(id)?)),
+                1 => Ok(TypedAction::Announcement(self.get_announcement(id)?)),
+                2 => Ok(TypedAction::Presence(self.get_presence(id)?)),
+                3 => Ok(TypedAction::Handover(self.get_handover(id)?)),
+                4 => Ok(TypedAction::Rsvp(self.get_rsvp(id)?)),
+                t => panic!("unknown action type in db: {}", t),
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        if take == Take::Last {
+            actions.reverse();
+        }
+        Ok(actions)
+    }
+}
+
+/// These tests need a PostgreSQL server. Set `CLUBSTATUSD_TEST_POSTGRES` to its connection
+/// parameters, eg. `host=/var/run/postgresql user=postgres`. Every test creates its own schema.
+#[cfg(test)]
+mod test {
+    use std::ops::{Deref, DerefMut};
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::db::{DbStored, ensure_initialized};
+    use uuid::Uuid;
+
+    /// Storage in a schema of its own, which is dropped again with it.
+    struct TestStorage {
+        storage: PostgresStorage,
+        schema: String,
+    }
+
+    impl Deref for TestStorage {
+        type Target = PostgresStorage;
+
+        fn deref(&self) -> &PostgresStorage {
+            &self.storage
+        }
+    }
+
+    impl DerefMut for TestStorage {
+        fn deref_mut(&mut self) -> &mut PostgresStorage {
+            &mut self.storage
+        }
+    }
+
+    impl Drop for TestStorage {
+        fn drop(&mut self) {
+            // a failed test may have left the connection in an aborted transaction
+            let _ = self.storage.client.batch_execute("ROLLBACK;");
+            if let Err(err) = self
+                .storage
+                .client
+                .batch_execute(format!("DROP SCHEMA {} CASCADE;", self.schema).as_str())
+            {
+                eprintln!("Error dropping test schema {}: {err}", self.schema);
+            }
+        }
+    }
+
+    fn test_storage() -> TestStorage {
+        test_storage_in(&format!("clubstatusd_test_{}", Uuid::new_v4().simple()))
+    }
+
+    /// Creates the schema, a fresh one for every test.
+    fn test_storage_in(schema: &str) -> TestStorage {
+        let mut storage = connect_to_schema(schema);
+        storage
+            .client
+            .batch_execute(format!("CREATE SCHEMA {schema};").as_str())
+            .unwrap();
+        TestStorage {
+            storage,
+            schema: String::from(schema),
+        }
+    }
+
+    fn connect_to_schema(schema: &str) -> PostgresStorage {
+        let params = std::env::var("CLUBSTATUSD_TEST_POSTGRES")
+            .expect("CLUBSTATUSD_TEST_POSTGRES has to be set to run the postgres tests");
+        let mut storage = PostgresStorage::connect(params.as_str()).unwrap();
+        storage
+            .client
+            .batch_execute(format!("SET search_path TO {schema};").as_str())
+            .unwrap();
+        storage
+    }
+
+    crate::db::conformance::conformance_tests!(test_storage);
+
+    /// Databases created before the schema version was recorded, with the initial actions.
+    const UNVERSIONED_SCHEMA: &str = "
+        CREATE TABLE action (id BIGSERIAL PRIMARY KEY, time BIGINT NOT NULL,
+            type SMALLINT NOT NULL, note TEXT NOT NULL);
+        CREATE TABLE status_action (id BIGINT PRIMARY KEY REFERENCES action (id),
+            \"user\" TEXT NOT NULL, status SMALLINT NOT NULL, changed BOOLEAN NOT NULL,
+            public_changed BOOLEAN NOT NULL);
+        CREATE TABLE announcement_action (id BIGINT PRIMARY KEY REFERENCES action (id),
+            method SMALLINT NOT NULL, aid BIGINT NOT NULL, \"user\" TEXT NOT NULL,
+            \"from\" BIGINT NOT NULL, \"to\" BIGINT NOT NULL, public BOOLEAN NOT NULL, url TEXT);
+        CREATE INDEX announcement_action_aid ON announcement_action (aid);
+        CREATE TABLE presence_action (id BIGINT NOT NULL REFERENCES action (id),
+            \"user\" TEXT NOT NULL, since BIGINT NOT NULL);
+        CREATE TABLE presence_anon_action (id BIGINT PRIMARY KEY REFERENCES action (id),
+            anonymous_users REAL NOT NULL);
+        INSERT INTO action (time, type, note) VALUES (0, 0, 'initial state'),
+            (0, 2, 'initial state');
+        INSERT INTO status_action VALUES (1, 'Hans Acker', 0, TRUE, TRUE);
+        INSERT INTO presence_anon_action VALUES (2, 0.0);";
+
+    fn columns(storage: &mut PostgresStorage, table: &str) -> Vec<String> {
+        storage
+            .client
+            .query(
+                "SELECT column_name::TEXT FROM information_schema.columns \
+                 WHERE table_schema = current_schema() AND table_name = $1 \
+                 ORDER BY ordinal_position",
+                &[&table],
+            )
+            .unwrap()
+            .iter()
+            .map(|row| row.get(0))
+            .collect()
+    }
+
+    fn unversioned_storage() -> TestStorage {
+        let mut storage = test_storage();
+        storage.client.batch_execute(UNVERSIONED_SCHEMA).unwrap();
+        storage
+    }
+
+    fn tables(storage: &mut PostgresStorage) -> Vec<String> {
+        storage
+            .client
+            .query(
+                "SELECT table_name::TEXT FROM information_schema.tables \
+                 WHERE table_schema = current_schema() ORDER BY table_name",
+                &[],
+            )
+            .unwrap()
+            .iter()
+            .map(|row| row.get(0))
+            .collect()
+    }
+
+    /// The migrations have to arrive at the schema of a fresh database, with the columns in the
+    /// same order.
+    #[test]
+    fn upgrade_unversioned() {
+        let mut storage = unversioned_storage();
+        assert_eq!(storage.schema_version().unwrap(), 0);
+        ensure_initialized(&mut *storage).unwrap();
+        assert_eq!(storage.schema_version().unwrap(), SCHEMA_VERSION);
+        assert_eq!(storage.get_last_presence().unwrap().action.id, Some(2));
+
+        let mut fresh = test_storage();
+        ensure_initialized(&mut *fresh).unwrap();
+        assert_eq!(tables(&mut storage), tables(&mut fresh));
+        for table in tables(&mut fresh) {
+            assert_eq!(
+                columns(&mut storage, &table),
+                columns(&mut fresh, &table),
+                "{table}"
+            );
+        }
+    }
+
+    /// Upgraded databases have to behave like fresh ones.
+    mod upgraded {
+        use super::*;
+
+        crate::db::conformance::conformance_tests!(
+            unversioned_storage;
+            migrations_rerun,
+            status_changed_flags,
+            public_status_changes,
+            status_get_by_id,
+            status_revert,
+            scheduled_status,
+            keyholder_handover,
+            zone_status,
+            announcement_lifecycle,
+            announcement_current,
+            announcement_window,
+            announcement_series,
+            announcement_categories,
+            announcement_resources,
+            sent_reminders,
+            rsvp,
+            presence_roundtrip,
+            query_filters,
+        );
+    }
+
+    /// A second instance using the same database has to wait until the first one has stored its
+    /// action.
+    #[test]
+    fn write_lock_between_instances() {
+        let schema = format!("clubstatusd_test_{}", Uuid::new_v4().simple());
+        let mut first = test_storage_in(&schema);
+        ensure_initialized(&mut *first).unwrap();
+        let mut second = connect_to_schema(&schema);
+
+        first.lock_writes().unwrap();
+        let waiting = thread::spawn(move || {
+            second.lock_writes().unwrap();
+            let last = second.get_last_status().unwrap();
+            second.unlock_writes().unwrap();
+            last
+        });
+        thread::sleep(Duration::from_millis(200));
+        let mut public = StatusAction::new(
+            String::new(),
+            1,
+            UserName::new("Hans Acker".into()),
+            Status::Public,
+        );
+        public.store(&mut *first, None).unwrap();
+        first.unlock_writes().unwrap();
+        assert_eq!(waiting.join().unwrap().status, Status::Public);
+    }
+}
@@ -0,0 +1,410 @@
+//! Sends reminder emails before announcements, eg. to the members list two days before a plenum.
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use chrono::TimeZone;
+use lettre::message::Mailbox;
+use lettre::message::header::ContentType;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use serde::Deserialize;
+
+use crate::clock::SharedClock;
+use crate::db::{DbCon, Storage};
+use clubstatus_types::AnnouncementAction;
+
+/// How often announcements are checked for due reminders, in seconds.
+const INTERVAL: u64 = 60;
+
+/// Seconds to wait for the SMTP server.
+const TIMEOUT: u64 = 30;
+
+/// Sends a reminder `before` seconds before announcements matching the filter start.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ReminderRule {
+    /// Identifies the rule when tracking which reminders have been sent, so changing it makes
+    /// every reminder due again.
+    pub name: String,
+    pub before: i64,
+    /// Only announcements whose note starts with this.
+    #[serde(default)]
+    pub note_prefix: Option<String>,
+    /// Only announcements with this category.
+    #[serde(default)]
+    pub category: Option<String>,
+    pub to: Vec<String>,
+    /// Templates, see `render`.
+    pub subject: String,
+    pub body: String,
+}
+
+impl ReminderRule {
+    fn matches(&self, announcement: &AnnouncementAction) -> bool {
+        let prefix = match &self.note_prefix {
+            Some(prefix) => announcement.action.note.starts_with(prefix.as_str()),
+            None => true,
+        };
+        let category = match &self.category {
+            Some(category) => announcement.category.as_ref() == Some(category),
+            None => true,
+        };
+        prefix && category
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SmtpSecurity {
+    /// Plain text, only for servers on localhost.
+    None,
+    StartTls,
+    Tls,
+}
+
+pub struct SmtpConfig {
+    pub server: String,
+    /// Defaults to the usual port for `security`.
+    pub port: Option<u16>,
+    pub security: SmtpSecurity,
+    pub credentials: Option<(String, String)>,
+}
+
+pub struct Reminders {
+    mailer: SmtpTransport,
+    from: Mailbox,
+    /// Times in the templates are given in this timezone.
+    timezone: chrono_tz::Tz,
+    rules: Vec<(ReminderRule, Vec<Mailbox>)>,
+}
+
+impl Reminders {
+    pub fn new(
+        smtp: SmtpConfig,
+        from: &str,
+        timezone: &str,
+        rules: Vec<ReminderRule>,
+    ) -> Result<Self, String> {
+        let mut builder = match smtp.security {
+            SmtpSecurity::None => SmtpTransport::builder_dangerous(&smtp.server),
+            SmtpSecurity::StartTls => {
+                SmtpTransport::starttls_relay(&smtp.server).map_err(|e| format!("server: {e}"))?
+            }
+            SmtpSecurity::Tls => {
+                SmtpTransport::relay(&smtp.server).map_err(|e| format!("server: {e}"))?
+            }
+        };
+        if let Some(port) = smtp.port {
+            builder = builder.port(port);
+        }
+        if let Some((user, password)) = smtp.credentials {
+            builder = builder.credentials(Credentials::new(user, password));
+        }
+        let mailer = builder.timeout(Some(Duration::from_secs(TIMEOUT))).build();
+
+        let from = from.parse().map_err(|e| format!("from: {e}"))?;
+        let timezone = timezone
+            .parse()
+            .map_err(|_| format!("timezone: unknown timezone '{timezone}'"))?;
+        let mut checked: Vec<(ReminderRule, Vec<Mailbox>)> = Vec::new();
+        for rule in rules {
+            if checked.iter().any(|(r, _)| r.name == rule.name) {
+                return Err(format!("rules: the name \"{}\" is used twice", rule.name));
+            }
+            let to = rule
+                .to
+                .iter()
+                .map(|to| to.parse())
+                .collect::<Result<Vec<Mailbox>, _>>()
+                .map_err(|e| format!("rules.{}.to: {e}", rule.name))?;
+            checked.push((rule, to));
+        }
+        Ok(Reminders {
+            mailer,
+            from,
+            timezone,
+            rules: checked,
+        })
+    }
+
+    /// Announcements that have not started yet with a reminder due, as (rule index,
+    /// announcement).
+    fn due(&self, storage: &mut dyn Storage, now: i64) -> Vec<(usize, AnnouncementAction)> {
+        let mut due = Vec::new();
+        for announcement in storage.get_current_announcements(now).unwrap() {
+            if announcement.from <= now {
+                continue;
+            }
+            let aid = announcement.aid.unwrap();
+            for (i, (rule, _)) in self.rules.iter().enumerate() {
+                if announcement.from - rule.before <= now
+                    && rule.matches(&announcement)
+                    && !storage
+                        .reminder_sent(aid, &rule.name, announcement.from)
+                        .unwrap()
+                {
+                    due.push((i, announcement.clone()));
+                }
+            }
+        }
+        due
+    }
+
+    fn send(&self, rule: usize, announcement: &AnnouncementAction) -> Result<(), String> {
+        let (rule, to) = &self.rules[rule];
+        let mut message = Message::builder().from(self.from.clone());
+        for to in to {
+            message = message.to(to.clone());
+        }
+        let message = message
+            .subject(render(&rule.subject, announcement, self.timezone))
+            .header(ContentType::TEXT_PLAIN)
+            .body(render(&rule.body, announcement, self.timezone))
+            .map_err(|e| e.to_string())?;
+        self.mailer.send(&message).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// Sends the due reminders, returning how many have been sent. Reminders that could not be
+    /// sent are tried again on the next check, until the announcement starts. The database is not
+    /// locked while talking to the SMTP server.
+    pub fn check(&self, shared_con: &Mutex<DbCon>, now: i64) -> usize {
+        let due = self.due(&mut **shared_con.lock().unwrap(), now);
+        let mut sent = 0;
+        for (rule, announcement) in due {
+            let name = &self.rules[rule].0.name;
+            let aid = announcement.aid.unwrap();
+            if let Err(err) = self.send(rule, &announcement) {
+                eprintln!("Error sending reminder {name} for announcement {aid}: {err}");
+                continue;
+            }
+            println!("Sent reminder {name} for announcement {aid}");
+            shared_con
+                .lock()
+                .unwrap()
+                .insert_sent_reminder(aid, name, announcement.from, now)
+                .unwrap();
+            sent += 1;
+        }
+        sent
+    }
+}
+
+/// Fills in `{note}`, `{user}`, `{from}`, `{to}` (as "2023-11-16 20:00" in `timezone`) and `{url}`
+/// (empty if not set).
+fn render(template: &str, announcement: &AnnouncementAction, timezone: chrono_tz::Tz) -> String {
+    let time = |t: i64| {
+        timezone
+            .timestamp_opt(t, 0)
+            .unwrap()
+            .format("%Y-%m-%d %H:%M")
+            .to_string()
+    };
+    template
+        .replace("{note}", &announcement.action.note)
+        .replace("{user}", announcement.user.as_str())
+        .replace("{from}", &time(announcement.from))
+        .replace("{to}", &time(announcement.to))
+        .replace(
+            "{url}",
+            announcement.url.as_ref().map(|u| u.as_str()).unwrap_or(""),
+        )
+}
+
+pub fn start(shared_con: Arc<Mutex<DbCon>>, clock: SharedClock, reminders: Reminders) {
+    thread::Builder::new()
+        .name(String::from("reminders"))
+        .spawn(move || {
+            loop {
+                reminders.check(&shared_con, clock.now());
+                thread::sleep(Duration::new(INTERVAL, 0));
+            }
+        })
+        .unwrap();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::sqlite::SqliteStorage;
+    use crate::db::{DbStored, ensure_initialized};
+    use clubstatus_types::{AnnouncementMethod, BaseAction, UserName};
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc::{self, Receiver};
+
+    /// 2023-11-16T20:00:00+01:00
+    const PLENUM: i64 = 1700161200;
+
+    /// A local SMTP server accepting every message, which it passes on as received (headers and
+    /// body).
+    fn smtp_sink() -> (u16, Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut writer = stream.unwrap();
+                let mut reader = BufReader::new(writer.try_clone().unwrap());
+                writer.write_all(b"220 sink ESMTP\r\n").unwrap();
+                let mut data: Option<String> = None;
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 0 {
+                    let reply: &[u8] = match &mut data {
+                        Some(_) if line == ".\r\n" => {
+                            let _ = tx.send(data.take().unwrap());
+                            b"250 queued\r\n"
+                        }
+                        Some(message) => {
+                            message.push_str(&line);
+                            b""
+                        }
+                        None if line.starts_with("DATA") => {
+                            data = Some(String::new());
+                            b"354 go ahead\r\n"
+                        }
+                        None if line.starts_with("QUIT") => {
+                            writer.write_all(b"221 bye\r\n").unwrap();
+                            break;
+                        }
+                        None => b"250 ok\r\n",
+                    };
+                    writer.write_all(reply).unwrap();
+                    line.clear();
+                }
+            }
+        });
+        (port, rx)
+    }
+
+    fn reminders(port: u16) -> Reminders {
+        Reminders::new(
+            SmtpConfig {
+                server: String::from("127.0.0.1"),
+                port: Some(port),
+                security: SmtpSecurity::None,
+                credentials: None,
+            },
+            "clubstatusd <status@example.org>",
+            "Europe/Berlin",
+            vec![ReminderRule {
+                name: String::from("plenum"),
+                before: 2 * 86400,
+                note_prefix: Some(String::from("Plenum")),
+                category: None,
+                to: vec![String::from("members@example.org")],
+                subject: String::from("Reminder: {note} on {from}"),
+                body: String::from("{note} from {from} to {to}\nSee {url}"),
+            }],
+        )
+        .unwrap()
+    }
+
+    fn storage_with_announcements() -> Mutex<DbCon> {
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+        ensure_initialized(&mut storage).unwrap();
+        for (note, from) in [("Plenum KW46", PLENUM), ("Lötworkshop", PLENUM + 3600)] {
+            AnnouncementAction {
+                action: BaseAction::new_with_time(String::from(note), PLENUM - 7 * 86400),
+                method: AnnouncementMethod::New,
+                aid: None,
+                user: UserName::new("plenumsbot".into()),
+                from,
+                to: from + 3600,
+                public: true,
+                url: Some("https://example.org/plenum".parse().unwrap()),
+                series: None,
+                recurrence_id: None,
+                category: None,
+                tags: Vec::new(),
+                description: None,
+                resources: Vec::new(),
+            }
+            .store(&mut storage, None)
+            .unwrap();
+        }
+        Mutex::new(Box::new(storage))
+    }
+
+    #[test]
+    fn sends_matching_reminders_once() {
+        let (port, received) = smtp_sink();
+        let reminders = reminders(port);
+        let con = storage_with_announcements();
+
+        assert_eq!(reminders.check(&con, PLENUM - 3 * 86400), 0);
+        assert_eq!(reminders.check(&con, PLENUM - 2 * 86400), 1);
+        let message = received.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(message.contains("To: members@example.org"));
+        assert!(message.contains("Subject: Reminder: Plenum KW46 on 2023-11-16 20:00"));
+        assert!(message.contains("Plenum KW46 from 2023-11-16 20:00 to 2023-11-16 21:00"));
+        assert!(message.contains("See https://example.org/plenum"));
+
+        // not again, even after a restart
+        assert_eq!(reminders.check(&con, PLENUM - 86400), 0);
+        assert_eq!(self::reminders(port).check(&con, PLENUM - 86400), 0);
+        assert!(received.try_recv().is_err());
+
+        // but again when the plenum is moved
+        let mut moved = con
+            .lock()
+            .unwrap()
+            .get_last_announcement(3)
+            .unwrap()
+            .unwrap();
+        moved.action = BaseAction::new_with_time(moved.action.note, PLENUM - 86400);
+        moved.method = AnnouncementMethod::Mod;
+        moved.from += 1800;
+        moved.store(&mut **con.lock().unwrap(), None).unwrap();
+        assert_eq!(reminders.check(&con, PLENUM - 86400), 1);
+        assert!(received.recv_timeout(Duration::from_secs(5)).is_ok());
+    }
+
+    #[test]
+    fn retries_until_sent() {
+        // nothing listens on this port anymore
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let con = storage_with_announcements();
+        assert_eq!(reminders(port).check(&con, PLENUM - 86400), 0);
+        assert!(
+            !con.lock()
+                .unwrap()
+                .reminder_sent(3, "plenum", PLENUM)
+                .unwrap()
+        );
+
+        let (port, received) = smtp_sink();
+        assert_eq!(reminders(port).check(&con, PLENUM - 3600), 1);
+        assert!(received.recv_timeout(Duration::from_secs(5)).is_ok());
+        // too late once it started
+        let con = storage_with_announcements();
+        assert_eq!(reminders(port).check(&con, PLENUM), 0);
+    }
+
+    #[test]
+    fn rule_matches_category() {
+        let con = storage_with_announcements();
+        let mut workshop = con
+            .lock()
+            .unwrap()
+            .get_last_announcement(4)
+            .unwrap()
+            .unwrap();
+        workshop.action = BaseAction::new_with_time(workshop.action.note, PLENUM - 86400);
+        workshop.method = AnnouncementMethod::Mod;
+        workshop.category = Some(String::from("workshop"));
+        workshop.store(&mut **con.lock().unwrap(), None).unwrap();
+
+        let mut reminders = reminders(0);
+        let rule = &mut reminders.rules[0].0;
+        rule.note_prefix = None;
+        rule.category = Some(String::from("workshop"));
+        let due = reminders.due(&mut **con.lock().unwrap(), PLENUM - 86400);
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].1.action.note, "Lötworkshop");
+    }
+}
@@ -0,0 +1,193 @@
+//! Status changes requested for a later time, either explicitly with `at`, or by setting `until`
+//! on a status.
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::api::mqtt::MqttSendQueue;
+use crate::clock::SharedClock;
+use crate::db::{DbCon, DbStored, Error, SYSTEM_USER, Storage};
+use clubstatus_types::{ScheduledStatus, StatusAction, UserName};
+
+/// How often the scheduler checks for due status changes, in seconds.
+const INTERVAL: u64 = 5;
+
+/// Stores a status change to be applied once `scheduled.at` has passed.
+pub fn schedule(storage: &mut dyn Storage, scheduled: &mut ScheduledStatus) -> Result<u64, Error> {
+    let id = storage.insert_scheduled_status(scheduled)?;
+    println!("Scheduled status change: {:?}", scheduled);
+    Ok(id)
+}
+
+/// Applies all scheduled status changes with `at <= now`, returning the ids of the created status
+/// actions.
+///
+/// The status actions get `now` as their time, so actions stay ordered by time even if the server
+/// was down when a change was due.
+pub fn apply_due(
+    storage: &mut dyn Storage,
+    mqtt: Option<&MqttSendQueue>,
+    now: i64,
+) -> Result<Vec<u64>, Error> {
+    let mut applied = Vec::new();
+    for scheduled in storage.get_scheduled_statuses()? {
+        if scheduled.at > now {
+            break;
+        }
+        // delete first, so a failure while storing can't apply the change twice
+        let Some(scheduled_id) = scheduled.id else {
+            continue;
+        };
+        if !storage.delete_scheduled_status(scheduled_id)? {
+            continue;
+        }
+        if let Some(id) = scheduled.to_action(now).store(storage, mqtt) {
+            applied.push(id);
+        }
+    }
+    Ok(applied)
+}
+
+/// Reverts the status to `revert_to` once the `until` of the last status action has passed,
+/// returning the id of the created status action.
+///
+/// Storing a newer status action (eg. the same status with a later `until`) prevents this.
+pub fn revert_expired(
+    storage: &mut dyn Storage,
+    mqtt: Option<&MqttSendQueue>,
+    now: i64,
+) -> Result<Option<u64>, Error> {
+    let last = storage.get_last_status()?;
+    let (Some(until), Some(revert_to)) = (last.until, last.revert_to) else {
+        return Ok(None);
+    };
+    if until > now {
+        return Ok(None);
+    }
+    Ok(StatusAction::new(
+        String::from("until passed"),
+        now,
+        UserName::new(SYSTEM_USER.into()),
+        revert_to,
+    )
+    .store(storage, mqtt))
+}
+
+/// Applies due status changes, then reverts an expired status. Errors are logged, the next tick
+/// tries again.
+pub fn tick(storage: &mut dyn Storage, mqtt: Option<&MqttSendQueue>, now: i64) {
+    if let Err(err) = apply_due(storage, mqtt, now) {
+        eprintln!("Error applying scheduled status changes: {err}");
+    }
+    if let Err(err) = revert_expired(storage, mqtt, now) {
+        eprintln!("Error reverting expired status: {err}");
+    }
+}
+
+/// Starts a thread applying scheduled status changes when they are due. Changes that became due
+/// while the server was down are applied right away.
+pub fn start_scheduler(
+    shared_con: Arc<Mutex<DbCon>>,
+    mqtt: Option<&MqttSendQueue>,
+    clock: SharedClock,
+) {
+    let mqtt = mqtt.cloned();
+    thread::Builder::new()
+        .name(String::from("status_scheduler"))
+        .spawn(move || {
+            loop {
+                {
+                    let mut con = shared_con.lock().unwrap();
+                    tick(&mut **con, mqtt.as_ref(), clock.now());
+                }
+                thread::sleep(Duration::new(INTERVAL, 0));
+            }
+        })
+        .unwrap();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::ensure_initialized;
+    use crate::db::sqlite::SqliteStorage;
+    use clubstatus_types::{Status, UserName};
+
+    fn scheduled(at: i64, status: Status) -> ScheduledStatus {
+        ScheduledStatus {
+            id: None,
+            time: 0,
+            at,
+            note: format!("at {at}"),
+            user: UserName::new("Hans Acker".into()),
+            status,
+            until: None,
+            revert_to: None,
+            keyholder: None,
+            public_message: None,
+        }
+    }
+
+    #[test]
+    fn applies_due_changes_in_order() {
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+        ensure_initialized(&mut storage).unwrap();
+        schedule(&mut storage, &mut scheduled(200, Status::Closed)).unwrap();
+        schedule(&mut storage, &mut scheduled(100, Status::Public)).unwrap();
+        schedule(&mut storage, &mut scheduled(300, Status::Private)).unwrap();
+
+        assert!(apply_due(&mut storage, None, 99).unwrap().is_empty());
+        assert_eq!(apply_due(&mut storage, None, 100).unwrap(), vec![3]);
+        let last = storage.get_last_status().unwrap();
+        assert_eq!((last.status, last.action.time), (Status::Public, 100));
+        assert_eq!(last.action.note, "at 100");
+
+        // overdue changes are applied with the current time
+        assert_eq!(apply_due(&mut storage, None, 1000).unwrap(), vec![4, 5]);
+        let last = storage.get_last_status().unwrap();
+        assert_eq!((last.status, last.action.time), (Status::Private, 1000));
+        assert!(storage.get_scheduled_statuses().unwrap().is_empty());
+        assert!(apply_due(&mut storage, None, 2000).unwrap().is_empty());
+    }
+
+    #[test]
+    fn reverts_after_until() {
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+        ensure_initialized(&mut storage).unwrap();
+        let status = |until, revert_to| StatusAction {
+            until,
+            revert_to,
+            ..StatusAction::new(
+                String::new(),
+                100,
+                UserName::new("Hans Acker".into()),
+                Status::Public,
+            )
+        };
+        assert_eq!(revert_expired(&mut storage, None, 100).unwrap(), None);
+        status(Some(200), Some(Status::Private))
+            .store(&mut storage, None)
+            .unwrap();
+        assert_eq!(revert_expired(&mut storage, None, 199).unwrap(), None);
+
+        // renewing the status postpones the reversion
+        status(Some(300), Some(Status::Private))
+            .store(&mut storage, None)
+            .unwrap();
+        assert_eq!(revert_expired(&mut storage, None, 200).unwrap(), None);
+        assert_eq!(revert_expired(&mut storage, None, 300).unwrap(), Some(5));
+        let last = storage.get_last_status().unwrap();
+        assert_eq!((last.status, last.action.time), (Status::Private, 300));
+        assert_eq!(last.user.as_str(), SYSTEM_USER);
+        assert_eq!(last.until, None);
+        assert_eq!(revert_expired(&mut storage, None, 400).unwrap(), None);
+
+        // a newer status without until cancels the reversion
+        status(Some(500), Some(Status::Closed))
+            .store(&mut storage, None)
+            .unwrap();
+        status(None, None).store(&mut storage, None).unwrap();
+        assert_eq!(revert_expired(&mut storage, None, 600).unwrap(), None);
+    }
+}
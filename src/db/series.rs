@@ -0,0 +1,409 @@
+//! Recurring announcements. The occurrences of a series are stored as ordinary announcements
+//! `AHEAD` seconds before they start, so they can be modified or deleted one by one.
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, TimeZone};
+use rrule::{Frequency, RRule, RRuleSet, Tz, Unvalidated};
+
+use crate::api::mqtt::MqttSendQueue;
+use crate::clock::SharedClock;
+use crate::db::{DbCon, DbStored, Error, Storage};
+use clubstatus_types::{
+    AnnouncementAction, AnnouncementMethod, AnnouncementSeries, BaseAction, UserName,
+};
+
+/// Occurrences are stored this many seconds before they start.
+pub const AHEAD: i64 = 28 * 86400;
+
+/// How often the series are checked for occurrences to store, in seconds.
+const INTERVAL: u64 = 60;
+
+/// At most this many occurrences of a series are stored at once, the rest follows on the next
+/// check.
+const LIMIT: u16 = 100;
+
+/// Parses `rrule`, evaluated in `timezone` with the first occurrence at `from`.
+pub fn parse(rrule: &str, timezone: &str, from: i64) -> Result<RRuleSet, String> {
+    let tz: chrono_tz::Tz = timezone
+        .parse()
+        .map_err(|_| format!("unknown timezone '{timezone}'"))?;
+    let rule: RRule<Unvalidated> = rrule.parse().map_err(|e| format!("invalid rrule: {e}"))?;
+    if matches!(
+        rule.get_freq(),
+        Frequency::Hourly | Frequency::Minutely | Frequency::Secondly
+    ) {
+        return Err(String::from(
+            "invalid rrule: FREQ has to be DAILY or longer",
+        ));
+    }
+    rule.build(at(Tz::Tz(tz), from)?)
+        .map_err(|e| format!("invalid rrule: {e}"))
+}
+
+fn at(tz: Tz, time: i64) -> Result<DateTime<Tz>, String> {
+    tz.timestamp_opt(time, 0)
+        .single()
+        .ok_or_else(|| format!("time {time} is out of range"))
+}
+
+/// Start times of the occurrences with `after < start <= before`, at most `LIMIT`.
+fn occurrences(series: &AnnouncementSeries, after: i64, before: i64) -> Result<Vec<i64>, Error> {
+    let rule = parse(&series.rrule, &series.timezone, series.from).map_err(Error::InvalidSeries)?;
+    let tz = rule.get_dt_start().timezone();
+    let (after, before) = (
+        at(tz, after + 1).map_err(Error::InvalidSeries)?,
+        at(tz, before).map_err(Error::InvalidSeries)?,
+    );
+    Ok(rule
+        .after(after)
+        .before(before)
+        .all(LIMIT)
+        .dates
+        .iter()
+        .map(|start| start.timestamp())
+        .collect())
+}
+
+fn occurrence(series: &AnnouncementSeries, start: i64, now: i64) -> AnnouncementAction {
+    AnnouncementAction {
+        action: BaseAction::new_with_time(series.note.clone(), now),
+        method: AnnouncementMethod::New,
+        aid: None,
+        user: series.user.clone(),
+        from: start,
+        to: start + series.duration,
+        public: series.public,
+        url: series.url.clone(),
+        series: series.id,
+        recurrence_id: Some(start),
+        category: series.category.clone(),
+        tags: series.tags.clone(),
+        description: series.description.clone(),
+        // series can't book resources
+        resources: Vec::new(),
+    }
+}
+
+/// Stores the occurrences starting until `now + AHEAD` that have not been stored yet, skipping
+/// the ones that have ended already. Returns the ids of the stored announcements.
+pub fn materialize(
+    storage: &mut dyn Storage,
+    series: &mut AnnouncementSeries,
+    mqtt: Option<&MqttSendQueue>,
+    now: i64,
+) -> Result<Vec<u64>, Error> {
+    let until = now + AHEAD;
+    let starts = occurrences(series, series.materialized_until, until)?;
+    let mut stored = Vec::new();
+    for &start in &starts {
+        if start + series.duration < now {
+            continue;
+        }
+        if let Some(id) = occurrence(series, start, now).store(storage, mqtt) {
+            stored.push(id);
+        }
+    }
+    series.materialized_until = match starts.last() {
+        Some(&last) if starts.len() == LIMIT as usize => last,
+        _ => until,
+    };
+    storage.update_announcement_series(series)?;
+    Ok(stored)
+}
+
+/// Stores a new series together with its first occurrences, returning the id of the series.
+/// Returns `Error::InvalidSeries` for an invalid rrule or timezone.
+pub fn create(
+    storage: &mut dyn Storage,
+    series: &mut AnnouncementSeries,
+    mqtt: Option<&MqttSendQueue>,
+    now: i64,
+) -> Result<u64, Error> {
+    parse(&series.rrule, &series.timezone, series.from).map_err(Error::InvalidSeries)?;
+    // the first occurrence starts at `from`
+    series.materialized_until = series.from - 1;
+    let id = storage.insert_announcement_series(series)?;
+    println!("Stored new announcement series: {:?}", series);
+    materialize(storage, series, mqtt, now)?;
+    Ok(id)
+}
+
+/// Deletes the occurrences that have not started yet.
+fn delete_upcoming(
+    storage: &mut dyn Storage,
+    series: u64,
+    user: &UserName,
+    mqtt: Option<&MqttSendQueue>,
+    now: i64,
+) -> Result<(), Error> {
+    for occurrence in storage.get_series_announcements(series)? {
+        if occurrence.method == AnnouncementMethod::Del || occurrence.from <= now {
+            continue;
+        }
+        AnnouncementAction {
+            // the other fields are taken from the occurrence when stored
+            aid: occurrence.aid,
+            method: AnnouncementMethod::Del,
+            user: user.clone(),
+            action: BaseAction::new_with_time(String::new(), now),
+            ..occurrence
+        }
+        .store(storage, mqtt);
+    }
+    Ok(())
+}
+
+/// Replaces the series with the same id. Occurrences that have not started yet are deleted and
+/// stored again according to the new series, which also undoes changes to single occurrences.
+/// Returns `Error::NotFound` for unknown or deleted series, and `Error::InvalidSeries` for an
+/// invalid rrule or timezone.
+pub fn modify(
+    storage: &mut dyn Storage,
+    series: &mut AnnouncementSeries,
+    mqtt: Option<&MqttSendQueue>,
+    now: i64,
+) -> Result<u64, Error> {
+    let id = series.id.ok_or(Error::NotFound)?;
+    parse(&series.rrule, &series.timezone, series.from).map_err(Error::InvalidSeries)?;
+    if storage.get_announcement_series(id)?.deleted {
+        return Err(Error::NotFound);
+    }
+    delete_upcoming(storage, id, &series.user, mqtt, now)?;
+    // occurrences that have started already are kept
+    series.materialized_until = now.max(series.from - 1);
+    storage.update_announcement_series(series)?;
+    println!("Modified announcement series: {:?}", series);
+    materialize(storage, series, mqtt, now)?;
+    Ok(id)
+}
+
+/// Deletes the series and its occurrences that have not started yet. Returns `Error::NotFound`
+/// for unknown or already deleted series.
+pub fn delete(
+    storage: &mut dyn Storage,
+    id: u64,
+    user: UserName,
+    mqtt: Option<&MqttSendQueue>,
+    now: i64,
+) -> Result<u64, Error> {
+    let mut series = storage.get_announcement_series(id)?;
+    if series.deleted {
+        return Err(Error::NotFound);
+    }
+    delete_upcoming(storage, id, &user, mqtt, now)?;
+    series.time = now;
+    series.user = user;
+    series.deleted = true;
+    storage.update_announcement_series(&series)?;
+    println!("Deleted announcement series {id}");
+    Ok(id)
+}
+
+/// Stores the upcoming occurrences of all series. Errors are logged, a series that fails is
+/// skipped and tried again on the next tick.
+pub fn tick(storage: &mut dyn Storage, mqtt: Option<&MqttSendQueue>, now: i64) {
+    let active = match storage.get_active_announcement_series() {
+        Ok(active) => active,
+        Err(err) => {
+            eprintln!("Error loading announcement series: {err}");
+            return;
+        }
+    };
+    for mut series in active {
+        if let Err(err) = materialize(storage, &mut series, mqtt, now) {
+            let id = series.id.unwrap_or_default();
+            eprintln!("Error storing occurrences of announcement series {id}: {err}");
+        }
+    }
+}
+
+/// Starts a thread storing the occurrences of all series before they start.
+pub fn start_materializer(
+    shared_con: Arc<Mutex<DbCon>>,
+    mqtt: Option<&MqttSendQueue>,
+    clock: SharedClock,
+) {
+    let mqtt = mqtt.cloned();
+    thread::Builder::new()
+        .name(String::from("announcement_series"))
+        .spawn(move || {
+            loop {
+                {
+                    let mut con = shared_con.lock().unwrap();
+                    tick(&mut **con, mqtt.as_ref(), clock.now());
+                }
+                thread::sleep(Duration::new(INTERVAL, 0));
+            }
+        })
+        .unwrap();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::ensure_initialized;
+    use crate::db::sqlite::SqliteStorage;
+    use chrono_tz::Europe::Berlin;
+
+    fn berlin(month: u32, day: u32, hour: u32) -> i64 {
+        Berlin
+            .with_ymd_and_hms(2024, month, day, hour, 0, 0)
+            .unwrap()
+            .timestamp()
+    }
+
+    /// Every other Thursday at 20:00 in Berlin, for an hour.
+    fn plenum(from: i64) -> AnnouncementSeries {
+        AnnouncementSeries {
+            id: None,
+            time: 0,
+            user: UserName::new("plenumsbot".into()),
+            note: String::from("Plenum"),
+            rrule: String::from("FREQ=WEEKLY;INTERVAL=2;BYDAY=TH"),
+            timezone: String::from("Europe/Berlin"),
+            from,
+            duration: 3600,
+            public: false,
+            url: None,
+            category: Some(String::from("plenum")),
+            tags: Vec::new(),
+            description: None,
+            materialized_until: 0,
+            deleted: false,
+        }
+    }
+
+    fn upcoming(storage: &mut dyn Storage, series: u64) -> Vec<(i64, String)> {
+        storage
+            .get_series_announcements(series)
+            .unwrap()
+            .into_iter()
+            .filter(|a| a.method != AnnouncementMethod::Del)
+            .map(|a| (a.from, a.action.note))
+            .collect()
+    }
+
+    #[test]
+    fn parse_checks_rule_and_timezone() {
+        assert!(parse("FREQ=WEEKLY;BYDAY=TH", "Europe/Berlin", 0).is_ok());
+        assert_eq!(
+            parse("FREQ=WEEKLY", "Mars/Olympus_Mons", 0).unwrap_err(),
+            "unknown timezone 'Mars/Olympus_Mons'"
+        );
+        assert!(parse("FREQ=FORTNIGHTLY", "UTC", 0).is_err());
+        assert_eq!(
+            parse("FREQ=HOURLY", "UTC", 0).unwrap_err(),
+            "invalid rrule: FREQ has to be DAILY or longer"
+        );
+    }
+
+    #[test]
+    fn invalid_series_are_refused_and_skipped() {
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+        ensure_initialized(&mut storage).unwrap();
+        let now = berlin(3, 20, 12);
+        let mut invalid = AnnouncementSeries {
+            timezone: String::from("Mars/Olympus_Mons"),
+            ..plenum(berlin(3, 21, 20))
+        };
+        assert!(matches!(
+            create(&mut storage, &mut invalid, None, now),
+            Err(Error::InvalidSeries(_))
+        ));
+        assert!(storage.get_active_announcement_series().unwrap().is_empty());
+
+        // stored without the check, eg. with a timezone since removed from the tz database
+        storage.insert_announcement_series(&mut invalid).unwrap();
+        let id = create(&mut storage, &mut plenum(berlin(3, 21, 20)), None, now).unwrap();
+        tick(&mut storage, None, berlin(4, 18, 20) - AHEAD);
+        assert_eq!(upcoming(&mut storage, id).len(), 3);
+        assert!(upcoming(&mut storage, invalid.id.unwrap()).is_empty());
+    }
+
+    #[test]
+    fn materializes_ahead_across_dst() {
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+        ensure_initialized(&mut storage).unwrap();
+        let now = berlin(3, 20, 12);
+        let mut series = plenum(berlin(3, 21, 20));
+        let id = create(&mut storage, &mut series, None, now).unwrap();
+        // 20:00 local time, before and after the switch to summer time on March 31st
+        let starts: Vec<i64> = upcoming(&mut storage, id).iter().map(|o| o.0).collect();
+        assert_eq!(starts, vec![berlin(3, 21, 20), berlin(4, 4, 20)]);
+        assert_eq!(starts[1] - starts[0], 14 * 86400 - 3600);
+
+        // nothing new until the next occurrence is less than AHEAD away
+        tick(&mut storage, None, now + 86400);
+        assert_eq!(upcoming(&mut storage, id).len(), 2);
+        tick(&mut storage, None, berlin(4, 18, 20) - AHEAD);
+        assert_eq!(upcoming(&mut storage, id).len(), 3);
+
+        // a deleted occurrence is not stored again
+        let first = storage.get_series_announcements(id).unwrap()[0].clone();
+        assert_eq!(first.category.as_deref(), Some("plenum"));
+        AnnouncementAction {
+            action: BaseAction::new_with_time(String::new(), now),
+            method: AnnouncementMethod::Del,
+            ..first
+        }
+        .store(&mut storage, None)
+        .unwrap();
+        tick(&mut storage, None, berlin(4, 18, 20) - AHEAD);
+        assert_eq!(upcoming(&mut storage, id).len(), 2);
+    }
+
+    #[test]
+    fn modify_and_delete_series() {
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+        ensure_initialized(&mut storage).unwrap();
+        let now = berlin(3, 20, 12);
+        let mut series = plenum(berlin(3, 21, 20));
+        let id = create(&mut storage, &mut series, None, now).unwrap();
+
+        // after the first plenum, it moves to 19:00
+        let now = berlin(3, 22, 12);
+        let mut modified = AnnouncementSeries {
+            id: Some(id),
+            note: String::from("Plenum (early)"),
+            ..plenum(berlin(4, 4, 19))
+        };
+        modify(&mut storage, &mut modified, None, now).unwrap();
+        assert_eq!(
+            upcoming(&mut storage, id),
+            vec![
+                (berlin(3, 21, 20), String::from("Plenum")),
+                (berlin(4, 4, 19), String::from("Plenum (early)")),
+                (berlin(4, 18, 19), String::from("Plenum (early)")),
+            ]
+        );
+
+        delete(
+            &mut storage,
+            id,
+            UserName::new("Hans Acker".into()),
+            None,
+            now,
+        )
+        .unwrap();
+        assert_eq!(upcoming(&mut storage, id).len(), 1);
+        assert!(storage.get_announcement_series(id).unwrap().deleted);
+        assert!(storage.get_active_announcement_series().unwrap().is_empty());
+        assert!(matches!(
+            delete(
+                &mut storage,
+                id,
+                UserName::new("Hans Acker".into()),
+                None,
+                now
+            ),
+            Err(Error::NotFound)
+        ));
+        assert!(matches!(
+            modify(&mut storage, &mut modified, None, now),
+            Err(Error::NotFound)
+        ));
+    }
+}
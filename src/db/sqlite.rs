@@ -0,0 +1,1380 @@
+use rusqlite::types::ToSql;
+use rusqlite::{Connection, Row, params};
+
+use crate::api::{AnnouncementFilter, IdExpr, RangeExpr, Take};
+use crate::db::{AnnouncementWindow, Error, SCHEMA_VERSION, Storage};
+use crate::model::QueryActionType;
+use clubstatus_types::{
+    AnnouncementAction, AnnouncementSeries, BaseAction, HandoverAction, PresenceAction,
+    PresentNamedUser, PresentUserStatus, RsvpAction, ScheduledStatus, StatusAction, TypedAction,
+    UserName,
+};
+
+/// The default storage backend.
+pub struct SqliteStorage {
+    con: Connection,
+    /// How many `lock_writes` have not been unlocked yet.
+    write_locks: u32,
+}
+
+impl SqliteStorage {
+    pub fn open(path: &str) -> Result<Self, Error> {
+        Ok(SqliteStorage {
+            con: Connection::open(path)?,
+            write_locks: 0,
+        })
+    }
+
+    /// A database that only lives as long as this struct, for tests.
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<Self, Error> {
+        Ok(SqliteStorage {
+            con: Connection::open_in_memory()?,
+            write_locks: 0,
+        })
+    }
+
+    fn presence_by_base_action(&self, action: BaseAction) -> Result<PresenceAction, Error> {
+        let id = action.id.unwrap() as i64;
+        let mut stmt = self
+            .con
+            .prepare("SELECT user, since FROM presence_action WHERE id = ?")?;
+        let mut users = stmt
+            .query_map([&id], |row| {
+                Ok(PresentNamedUser {
+                    name: UserName::new(row.get(0)?),
+                    since: row.get(1)?,
+                    status: PresentUserStatus::Present,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        users.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let anonymous_users = self
+            .con
+            .query_row(
+                "SELECT anonymous_users FROM presence_anon_action WHERE id = ?",
+                [&id],
+                |row| row.get(0),
+            )
+            .unwrap_or(0.0) as f32;
+
+        Ok(PresenceAction {
+            action,
+            users,
+            anonymous_users,
+        })
+    }
+}
+
+fn row_to_base_action(row: &Row) -> Result<BaseAction, rusqlite::Error> {
+    Ok(BaseAction {
+        id: Some(row.get::<_, i64>(0)? as u64),
+        time: row.get(1)?,
+        note: row.get(3)?,
+    })
+}
+
+fn row_to_status_action(row: &Row) -> Result<StatusAction, rusqlite::Error> {
+    Ok(StatusAction {
+        action: row_to_base_action(row)?,
+        user: row.get(5)?,
+        status: row.get(6)?,
+        until: row.get(9)?,
+        revert_to: row.get(10)?,
+        reverts: row.get::<_, Option<i64>>(11)?.map(|id| id as u64),
+        keyholder: row.get(12)?,
+        zone: row.get(13)?,
+        public_message: row.get(14)?,
+    })
+}
+
+fn row_to_scheduled_status(row: &Row) -> Result<ScheduledStatus, rusqlite::Error> {
+    Ok(ScheduledStatus {
+        id: Some(row.get::<_, i64>(0)? as u64),
+        time: row.get(1)?,
+        at: row.get(2)?,
+        note: row.get(3)?,
+        user: row.get(4)?,
+        status: row.get(5)?,
+        until: row.get(6)?,
+        revert_to: row.get(7)?,
+        keyholder: row.get(8)?,
+        public_message: row.get(9)?,
+    })
+}
+
+fn row_to_handover_action(row: &Row) -> Result<HandoverAction, rusqlite::Error> {
+    Ok(HandoverAction {
+        action: row_to_base_action(row)?,
+        user: row.get(5)?,
+        from: row.get(6)?,
+        to: row.get(7)?,
+    })
+}
+
+fn row_to_rsvp_action(row: &Row) -> Result<RsvpAction, rusqlite::Error> {
+    Ok(RsvpAction {
+        action: row_to_base_action(row)?,
+        aid: row.get::<_, i64>(5)? as u64,
+        user: row.get(6)?,
+        answer: row.get(7)?,
+    })
+}
+
+fn row_to_announcement_action(row: &Row) -> Result<AnnouncementAction, rusqlite::Error> {
+    Ok(AnnouncementAction {
+        action: row_to_base_action(row)?,
+        method: row.get(5)?,
+        aid: Some(row.get::<_, i64>(6)? as u64),
+        user: row.get(7)?,
+        from: row.get(8)?,
+        to: row.get(9)?,
+        public: match row.get(10)? {
+            0 => false,
+            1 => true,
+            unexpected => panic!("unexpected value for public: {unexpected}"),
+        },
+        url: row.get(11)?,
+        series: row.get::<_, Option<i64>>(12)?.map(|id| id as u64),
+        recurrence_id: row.get(13)?,
+        category: row.get(14)?,
+        tags: split_list(row.get(15)?),
+        description: row.get(16)?,
+        resources: split_list(row.get(17)?),
+    })
+}
+
+fn row_to_announcement_series(row: &Row) -> Result<AnnouncementSeries, rusqlite::Error> {
+    Ok(AnnouncementSeries {
+        id: Some(row.get::<_, i64>(0)? as u64),
+        time: row.get(1)?,
+        user: row.get(2)?,
+        note: row.get(3)?,
+        rrule: row.get(4)?,
+        timezone: row.get(5)?,
+        from: row.get(6)?,
+        duration: row.get(7)?,
+        public: row.get(8)?,
+        url: row.get(9)?,
+        materialized_until: row.get(10)?,
+        deleted: row.get(11)?,
+        category: row.get(12)?,
+        tags: split_list(row.get(13)?),
+        description: row.get(14)?,
+    })
+}
+
+const ANNOUNCEMENT_SERIES_COLUMNS: &str = "id, time, user, note, rrule, timezone, \"from\", \
+     duration, public, url, materialized_until, deleted, category, tags, description";
+
+/// Tags and resources are stored separated by commas, which they can't contain.
+fn join_list(tags: &[String]) -> String {
+    tags.join(",")
+}
+
+fn split_list(tags: String) -> Vec<String> {
+    tags.split(',')
+        .filter(|tag| !tag.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Adds a column in a migration, unless it exists already (eg. added by hand as described in
+/// older changelogs).
+fn add_column(con: &Connection, table: &str, column: &str, definition: &str) -> Result<(), Error> {
+    let exists: bool = con.query_row(
+        "SELECT count(*) > 0 FROM pragma_table_info(?) WHERE name = ?",
+        params![table, column],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        con.execute(
+            &format!("ALTER TABLE {table} ADD COLUMN {column} {definition}"),
+            params![],
+        )?;
+    }
+    Ok(())
+}
+
+impl Storage for SqliteStorage {
+    fn is_initialized(&mut self) -> Result<bool, Error> {
+        let count: i64 = self.con.query_row(
+            "SELECT count(*) FROM sqlite_master WHERE type = 'table' AND name = 'action'",
+            params![],
+            |row| row.get(0),
+        )?;
+        Ok(count > 0)
+    }
+
+    fn create_tables(&mut self) -> Result<(), Error> {
+        // a savepoint, which also works inside the transaction of `lock_writes`
+        let tx = self.con.savepoint()?;
+        /*
+         * types:
+         *   0: status
+         *   1: announcement
+         *   2: presence
+         *   3: handover
+         *   4: rsvp
+         */
+        tx.execute(
+            "CREATE TABLE action (
+                     id INTEGER PRIMARY KEY AUTOINCREMENT,
+                     time INTEGER NOT NULL,
+                     type INTEGER NOT NULL,
+                     note TEXT NOT NULL
+                 )",
+            params![],
+        )?;
+
+        /*
+         * status:
+         *   0: closed
+         *   1: private
+         *   2: public
+         * changed: boolean
+         * revert_to: status or NULL
+         * reverts: id of the reverted status action or NULL
+         * keyholder: NULL while closed
+         * zone: NULL for the status of the whole space
+         * public_message: shown publicly, unlike the note
+         */
+        tx.execute(
+            "CREATE TABLE status_action (
+                 id INTEGER PRIMARY KEY,
+                 user TEXT NOT NULL,
+                 status INTEGER NOT NULL,
+                 changed INTEGER NOT NULL,
+                 public_changed INTEGER NOT NULL,
+                 until INTEGER,
+                 revert_to INTEGER,
+                 reverts INTEGER,
+                 keyholder TEXT,
+                 zone TEXT,
+                 public_message TEXT
+             )",
+            params![],
+        )?;
+
+        /*
+         * method:
+         *   0: new
+         *   1: mod
+         *   2: del
+         * public: boolean
+         * series: id of the announcement_series this is an occurrence of, or NULL
+         * category: one of the configured categories, or NULL
+         * tags, resources: separated by commas
+         */
+        tx.execute(
+            "CREATE TABLE announcement_action (
+                 id INTEGER PRIMARY KEY,
+                 method INTEGER,
+                 aid INTEGER,
+                 user TEXT NOT NULL,
+                 'from' INTEGER,
+                 'to' INTEGER,
+                 public INTEGER,
+                 url TEXT,
+                 series INTEGER,
+                 recurrence_id INTEGER,
+                 category TEXT,
+                 tags TEXT NOT NULL,
+                 description TEXT,
+                 resources TEXT NOT NULL
+             )",
+            params![],
+        )?;
+
+        /*
+         * public, deleted: boolean
+         */
+        tx.execute(
+            "CREATE TABLE announcement_series (
+                 id INTEGER PRIMARY KEY AUTOINCREMENT,
+                 time INTEGER NOT NULL,
+                 user TEXT NOT NULL,
+                 note TEXT NOT NULL,
+                 rrule TEXT NOT NULL,
+                 timezone TEXT NOT NULL,
+                 'from' INTEGER NOT NULL,
+                 duration INTEGER NOT NULL,
+                 public INTEGER NOT NULL,
+                 url TEXT,
+                 materialized_until INTEGER NOT NULL,
+                 deleted INTEGER NOT NULL,
+                 category TEXT,
+                 tags TEXT NOT NULL,
+                 description TEXT
+             )",
+            params![],
+        )?;
+
+        tx.execute(
+            "CREATE TABLE sent_reminder (
+                 aid INTEGER NOT NULL,
+                 reminder TEXT NOT NULL,
+                 'from' INTEGER NOT NULL,
+                 time INTEGER NOT NULL,
+                 PRIMARY KEY (aid, reminder, 'from')
+             )",
+            params![],
+        )?;
+
+        tx.execute(
+            "CREATE TABLE scheduled_status (
+                 id INTEGER PRIMARY KEY AUTOINCREMENT,
+                 time INTEGER NOT NULL,
+                 at INTEGER NOT NULL,
+                 note TEXT NOT NULL,
+                 user TEXT NOT NULL,
+                 status INTEGER NOT NULL,
+                 until INTEGER,
+                 revert_to INTEGER,
+                 keyholder TEXT,
+                 public_message TEXT
+             )",
+            params![],
+        )?;
+
+        tx.execute(
+            "CREATE TABLE handover_action (
+                 id INTEGER PRIMARY KEY,
+                 user TEXT NOT NULL,
+                 'from' TEXT,
+                 'to' TEXT NOT NULL
+             )",
+            params![],
+        )?;
+
+        /*
+         * answer:
+         *   0: no
+         *   1: maybe
+         *   2: yes
+         */
+        tx.execute(
+            "CREATE TABLE rsvp_action (
+                 id INTEGER PRIMARY KEY,
+                 aid INTEGER NOT NULL,
+                 user TEXT NOT NULL,
+                 answer INTEGER NOT NULL
+             )",
+            params![],
+        )?;
+
+        tx.execute(
+            "CREATE TABLE presence_action (
+                 id INTEGER,
+                 user TEXT NOT NULL,
+                 since INTEGER
+             )",
+            params![],
+        )?;
+
+        tx.execute(
+            "CREATE TABLE presence_anon_action (
+                 id INTEGER,
+                 anonymous_users FLOAT
+             )",
+            params![],
+        )?;
+        tx.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn schema_version(&mut self) -> Result<u32, Error> {
+        Ok(self
+            .con
+            .pragma_query_value(None, "user_version", |row| row.get(0))?)
+    }
+
+    fn migrate(&mut self, version: u32) -> Result<(), Error> {
+        let tx = self.con.savepoint()?;
+        match version {
+            // scheduled status changes
+            1 => {
+                tx.execute(
+                    "CREATE TABLE IF NOT EXISTS scheduled_status (
+                         id INTEGER PRIMARY KEY AUTOINCREMENT,
+                         time INTEGER NOT NULL,
+                         at INTEGER NOT NULL,
+                         note TEXT NOT NULL,
+                         user TEXT NOT NULL,
+                         status INTEGER NOT NULL
+                     )",
+                    params![],
+                )?;
+            }
+            // status until and revert_to
+            2 => {
+                add_column(&tx, "status_action", "until", "INTEGER")?;
+                add_column(&tx, "status_action", "revert_to", "INTEGER")?;
+                add_column(&tx, "scheduled_status", "until", "INTEGER")?;
+                add_column(&tx, "scheduled_status", "revert_to", "INTEGER")?;
+            }
+            // status reverts
+            3 => {
+                add_column(&tx, "status_action", "reverts", "INTEGER")?;
+            }
+            // keyholders and handovers
+            4 => {
+                add_column(&tx, "status_action", "keyholder", "TEXT")?;
+                add_column(&tx, "scheduled_status", "keyholder", "TEXT")?;
+                tx.execute(
+                    "CREATE TABLE IF NOT EXISTS handover_action (
+                         id INTEGER PRIMARY KEY,
+                         user TEXT NOT NULL,
+                         'from' TEXT,
+                         'to' TEXT NOT NULL
+                     )",
+                    params![],
+                )?;
+            }
+            // zones
+            5 => {
+                add_column(&tx, "status_action", "zone", "TEXT")?;
+            }
+            // public messages
+            6 => {
+                add_column(&tx, "status_action", "public_message", "TEXT")?;
+                add_column(&tx, "scheduled_status", "public_message", "TEXT")?;
+            }
+            // announcement series
+            7 => {
+                add_column(&tx, "announcement_action", "series", "INTEGER")?;
+                add_column(&tx, "announcement_action", "recurrence_id", "INTEGER")?;
+                tx.execute(
+                    "CREATE TABLE IF NOT EXISTS announcement_series (
+                         id INTEGER PRIMARY KEY AUTOINCREMENT,
+                         time INTEGER NOT NULL,
+                         user TEXT NOT NULL,
+                         note TEXT NOT NULL,
+                         rrule TEXT NOT NULL,
+                         timezone TEXT NOT NULL,
+                         'from' INTEGER NOT NULL,
+                         duration INTEGER NOT NULL,
+                         public INTEGER NOT NULL,
+                         url TEXT,
+                         materialized_until INTEGER NOT NULL,
+                         deleted INTEGER NOT NULL
+                     )",
+                    params![],
+                )?;
+            }
+            // sent reminders
+            8 => {
+                tx.execute(
+                    "CREATE TABLE IF NOT EXISTS sent_reminder (
+                         aid INTEGER NOT NULL,
+                         reminder TEXT NOT NULL,
+                         'from' INTEGER NOT NULL,
+                         time INTEGER NOT NULL,
+                         PRIMARY KEY (aid, reminder, 'from')
+                     )",
+                    params![],
+                )?;
+            }
+            // RSVPs
+            9 => {
+                tx.execute(
+                    "CREATE TABLE IF NOT EXISTS rsvp_action (
+                         id INTEGER PRIMARY KEY,
+                         aid INTEGER NOT NULL,
+                         user TEXT NOT NULL,
+                         answer INTEGER NOT NULL
+                     )",
+                    params![],
+                )?;
+            }
+            // announcement categories and tags
+            10 => {
+                for table in ["announcement_action", "announcement_series"] {
+                    add_column(&tx, table, "category", "TEXT")?;
+                    add_column(&tx, table, "tags", "TEXT NOT NULL DEFAULT ''")?;
+                }
+            }
+            // announcement descriptions
+            11 => {
+                add_column(&tx, "announcement_action", "description", "TEXT")?;
+                add_column(&tx, "announcement_series", "description", "TEXT")?;
+            }
+            // resources
+            12 => {
+                add_column(
+                    &tx,
+                    "announcement_action",
+                    "resources",
+                    "TEXT NOT NULL DEFAULT ''",
+                )?;
+            }
+            _ => panic!("there is no schema version {version}"),
+        }
+        tx.pragma_update(None, "user_version", version)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn lock_writes(&mut self) -> Result<(), Error> {
+        // other connections can still read, but not start writing until COMMIT. The inserts use
+        // savepoints, which work inside this transaction.
+        if self.write_locks == 0 {
+            self.con.execute_batch("BEGIN IMMEDIATE")?;
+        }
+        self.write_locks += 1;
+        Ok(())
+    }
+
+    fn unlock_writes(&mut self) -> Result<(), Error> {
+        self.write_locks -= 1;
+        if self.write_locks == 0 {
+            self.con.execute_batch("COMMIT")?;
+        }
+        Ok(())
+    }
+
+    /*
+     * Status
+     */
+
+    fn insert_status(
+        &mut self,
+        action: &mut StatusAction,
+        changed: bool,
+        public_changed: bool,
+    ) -> Result<u64, Error> {
+        let tx = self.con.savepoint()?;
+        tx.execute(
+            "INSERT INTO action (time, type, note) VALUES (?, ?, ?)",
+            params![&action.action.time, &0, &action.action.note],
+        )?;
+        let action_id = tx.last_insert_rowid() as u64;
+        tx.execute(
+            "INSERT INTO status_action \
+             (id, user, status, changed, public_changed, until, revert_to, reverts, keyholder, \
+             zone, public_message) \
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            params![
+                &(action_id as i64),
+                &action.user,
+                &action.status,
+                &(changed as i64),
+                &(public_changed as i64),
+                &action.until,
+                &action.revert_to,
+                &action.reverts.map(|id| id as i64),
+                &action.keyholder,
+                &action.zone,
+                &action.public_message,
+            ],
+        )?;
+        tx.commit()?;
+        action.action.id = Some(action_id);
+        Ok(action_id)
+    }
+
+    fn get_status(&mut self, id: u64) -> Result<StatusAction, Error> {
+        Ok(self.con.query_row(
+            "SELECT * FROM action JOIN status_action WHERE \
+             action.id = ? AND status_action.id = ?",
+            params![&(id as i64), &(id as i64)],
+            row_to_status_action,
+        )?)
+    }
+
+    fn get_last_zone_status(&mut self, zone: Option<&str>) -> Result<StatusAction, Error> {
+        Ok(self.con.query_row(
+            "SELECT * FROM action JOIN status_action WHERE action.type = 0 AND \
+             action.id = status_action.id AND status_action.zone IS ? \
+             ORDER BY action.id DESC LIMIT 1",
+            params![&zone],
+            row_to_status_action,
+        )?)
+    }
+
+    fn get_last_changed_zone_status(&mut self, zone: Option<&str>) -> Result<StatusAction, Error> {
+        Ok(self.con.query_row(
+            "SELECT * FROM action JOIN status_action WHERE action.type = 0 AND \
+             action.id = status_action.id AND status_action.zone IS ? AND \
+             status_action.changed = 1 AND \
+             action.id NOT IN (SELECT reverts FROM status_action WHERE reverts IS NOT NULL) \
+             ORDER BY action.id DESC LIMIT 1",
+            params![&zone],
+            row_to_status_action,
+        )?)
+    }
+
+    fn get_last_changed_public_zone_status(
+        &mut self,
+        zone: Option<&str>,
+    ) -> Result<StatusAction, Error> {
+        Ok(self.con.query_row(
+            "SELECT * FROM action JOIN status_action WHERE action.type = 0 AND \
+             action.id = status_action.id AND status_action.zone IS ? AND \
+             status_action.public_changed = 1 AND \
+             action.id NOT IN (SELECT reverts FROM status_action WHERE reverts IS NOT NULL) \
+             ORDER BY action.id DESC LIMIT 1",
+            params![&zone],
+            row_to_status_action,
+        )?)
+    }
+
+    fn get_status_changes(&mut self, since: i64, count: u64) -> Result<Vec<StatusAction>, Error> {
+        let mut stmt = self.con.prepare(
+            "SELECT * FROM action JOIN status_action WHERE action.type = 0 AND \
+             action.id = status_action.id AND status_action.zone IS NULL AND \
+             status_action.changed = 1 AND action.time >= ? AND \
+             action.id NOT IN (SELECT reverts FROM status_action WHERE reverts IS NOT NULL) \
+             ORDER BY action.id DESC LIMIT ?",
+        )?;
+        let changes = stmt
+            .query_map(params![&since, &(count as i64)], row_to_status_action)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(changes)
+    }
+
+    fn get_public_status_changes(
+        &mut self,
+        since: i64,
+        count: u64,
+    ) -> Result<Vec<StatusAction>, Error> {
+        let mut stmt = self.con.prepare(
+            "SELECT * FROM action JOIN status_action WHERE action.type = 0 AND \
+             action.id = status_action.id AND status_action.zone IS NULL AND \
+             status_action.public_changed = 1 AND action.time >= ? AND \
+             action.id NOT IN (SELECT reverts FROM status_action WHERE reverts IS NOT NULL) \
+             ORDER BY action.id DESC LIMIT ?",
+        )?;
+        let changes = stmt
+            .query_map(params![&since, &(count as i64)], row_to_status_action)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(changes)
+    }
+
+    fn get_status_change_before(&mut self, time: i64) -> Result<StatusAction, Error> {
+        Ok(self.con.query_row(
+            "SELECT * FROM action JOIN status_action WHERE action.type = 0 AND \
+             action.id = status_action.id AND status_action.zone IS NULL AND \
+             status_action.changed = 1 AND action.time < ? AND \
+             action.id NOT IN (SELECT reverts FROM status_action WHERE reverts IS NOT NULL) \
+             ORDER BY action.id DESC LIMIT 1",
+            params![&time],
+            row_to_status_action,
+        )?)
+    }
+
+    fn get_public_status_change_before(&mut self, time: i64) -> Result<StatusAction, Error> {
+        Ok(self.con.query_row(
+            "SELECT * FROM action JOIN status_action WHERE action.type = 0 AND \
+             action.id = status_action.id AND status_action.zone IS NULL AND \
+             status_action.public_changed = 1 AND action.time < ? AND \
+             action.id NOT IN (SELECT reverts FROM status_action WHERE reverts IS NOT NULL) \
+             ORDER BY action.id DESC LIMIT 1",
+            params![&time],
+            row_to_status_action,
+        )?)
+    }
+
+    fn get_last_status_before(&mut self, id: u64) -> Result<StatusAction, Error> {
+        Ok(self.con.query_row(
+            "SELECT * FROM action JOIN status_action WHERE action.type = 0 AND \
+             action.id = status_action.id AND action.id < ? AND status_action.zone IS NULL AND \
+             action.id NOT IN (SELECT reverts FROM status_action WHERE reverts IS NOT NULL) \
+             ORDER BY action.id DESC LIMIT 1",
+            params![&(id as i64)],
+            row_to_status_action,
+        )?)
+    }
+
+    fn insert_scheduled_status(&mut self, scheduled: &mut ScheduledStatus) -> Result<u64, Error> {
+        self.con.execute(
+            "INSERT INTO scheduled_status \
+             (time, at, note, user, status, until, revert_to, keyholder, public_message) \
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            params![
+                &scheduled.time,
+                &scheduled.at,
+                &scheduled.note,
+                &scheduled.user,
+                &scheduled.status,
+                &scheduled.until,
+                &scheduled.revert_to,
+                &scheduled.keyholder,
+                &scheduled.public_message,
+            ],
+        )?;
+        let id = self.con.last_insert_rowid() as u64;
+        scheduled.id = Some(id);
+        Ok(id)
+    }
+
+    fn get_scheduled_statuses(&mut self) -> Result<Vec<ScheduledStatus>, Error> {
+        let mut stmt = self.con.prepare(
+            "SELECT id, time, at, note, user, status, until, revert_to, keyholder, \
+             public_message FROM scheduled_status ORDER BY at, id",
+        )?;
+        let scheduled = stmt
+            .query_map([], row_to_scheduled_status)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(scheduled)
+    }
+
+    fn delete_scheduled_status(&mut self, id: u64) -> Result<bool, Error> {
+        let deleted = self.con.execute(
+            "DELETE FROM scheduled_status WHERE id = ?",
+            params![&(id as i64)],
+        )?;
+        Ok(deleted > 0)
+    }
+
+    /*
+     * Handovers
+     */
+
+    fn insert_handover(&mut self, action: &mut HandoverAction) -> Result<u64, Error> {
+        let tx = self.con.savepoint()?;
+        tx.execute(
+            "INSERT INTO action (time, type, note) VALUES (?, ?, ?)",
+            params![&action.action.time, &3, &action.action.note],
+        )?;
+        let action_id = tx.last_insert_rowid() as u64;
+        tx.execute(
+            "INSERT INTO handover_action (id, user, \"from\", \"to\") VALUES (?, ?, ?, ?)",
+            params![&(action_id as i64), &action.user, &action.from, &action.to],
+        )?;
+        tx.commit()?;
+        action.action.id = Some(action_id);
+        Ok(action_id)
+    }
+
+    fn get_handover(&mut self, id: u64) -> Result<HandoverAction, Error> {
+        Ok(self.con.query_row(
+            "SELECT * FROM action JOIN handover_action WHERE action.type = 3 AND \
+             action.id = ? AND handover_action.id = ?",
+            params![&(id as i64), &(id as i64)],
+            row_to_handover_action,
+        )?)
+    }
+
+    fn get_last_handover(&mut self) -> Result<Option<HandoverAction>, Error> {
+        let res = self.con.query_row(
+            "SELECT * FROM action JOIN handover_action WHERE action.type = 3 AND \
+             action.id = handover_action.id \
+             ORDER BY action.id DESC LIMIT 1",
+            params![],
+            row_to_handover_action,
+        );
+        match res {
+            Ok(handover_action) => Ok(Some(handover_action)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /*
+     * Announcements
+     */
+
+    fn insert_announcement(&mut self, action: &mut AnnouncementAction) -> Result<u64, Error> {
+        let tx = self.con.savepoint()?;
+        tx.execute(
+            "INSERT INTO action (time, type, note) VALUES (?, ?, ?)",
+            params![&action.action.time, &1, &action.action.note],
+        )?;
+        let action_id = tx.last_insert_rowid() as u64;
+        let aid = action.aid.unwrap_or(action_id);
+        tx.execute(
+            "INSERT INTO announcement_action \
+             (id, method, aid, user, \"from\", \"to\", public, url, series, recurrence_id, \
+             category, tags, description, resources) \
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            params![
+                &(action_id as i64),
+                &action.method,
+                &(aid as i64),
+                &action.user,
+                &action.from,
+                &action.to,
+                &(action.public as i64),
+                &action.url,
+                &action.series.map(|id| id as i64),
+                &action.recurrence_id,
+                &action.category,
+                &join_list(&action.tags),
+                &action.description,
+                &join_list(&action.resources),
+            ],
+        )?;
+        tx.commit()?;
+        action.action.id = Some(action_id);
+        action.aid = Some(aid);
+        Ok(action_id)
+    }
+
+    fn get_announcement(&mut self, id: u64) -> Result<AnnouncementAction, Error> {
+        Ok(self.con.query_row(
+            "SELECT * FROM action JOIN announcement_action WHERE action.type = 1 AND \
+             action.id = ? AND announcement_action.id = ?",
+            params![&(id as i64), &(id as i64)],
+            row_to_announcement_action,
+        )?)
+    }
+
+    fn get_last_announcement(&mut self, aid: u64) -> Result<Option<AnnouncementAction>, Error> {
+        let res = self.con.query_row(
+            "SELECT * FROM action JOIN announcement_action WHERE action.type = 1 AND \
+             action.id = announcement_action.id AND announcement_action.aid = ? \
+             ORDER BY action.id DESC LIMIT 1",
+            params![&(aid as i64)],
+            row_to_announcement_action,
+        );
+        match res {
+            Ok(announcement_action) => Ok(Some(announcement_action)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn get_announcements(
+        &mut self,
+        window: &AnnouncementWindow,
+        filter: &AnnouncementFilter,
+    ) -> Result<Vec<AnnouncementAction>, Error> {
+        let mut query_str = String::from(
+            "SELECT * FROM action JOIN announcement_action WHERE \
+             action.id IN ( \
+             SELECT max(id) FROM announcement_action GROUP BY aid \
+             ) AND \
+             action.id = announcement_action.id AND \
+             announcement_action.method != 2",
+        );
+        let limit = window.limit.map_or(-1, |limit| limit as i64);
+        let offset = window.offset as i64;
+        let mut params = Vec::<&dyn ToSql>::new();
+
+        if let Some(since) = &window.since {
+            query_str.push_str(" AND ? <= \"to\"");
+            params.push(since);
+        }
+        if let Some(until) = &window.until {
+            query_str.push_str(" AND \"from\" < ?");
+            params.push(until);
+        }
+        if let Some(ended_before) = &window.ended_before {
+            query_str.push_str(" AND \"to\" < ?");
+            params.push(ended_before);
+        }
+        if window.public {
+            query_str.push_str(" AND announcement_action.public = 1");
+        }
+        if let Some(category) = &filter.category {
+            query_str.push_str(" AND announcement_action.category = ?");
+            params.push(category);
+        }
+        if let Some(tag) = &filter.tag {
+            query_str.push_str(
+                " AND instr(',' || announcement_action.tags || ',', ',' || ? || ',') > 0",
+            );
+            params.push(tag);
+        }
+
+        query_str.push_str(if window.ended_before.is_some() {
+            " ORDER BY \"to\" DESC, action.id DESC"
+        } else {
+            " ORDER BY \"from\", action.id"
+        });
+        // a negative limit means no limit
+        query_str.push_str(" LIMIT ? OFFSET ?");
+        params.push(&limit);
+        params.push(&offset);
+
+        let mut stmt = self.con.prepare(&query_str)?;
+        let actions = stmt
+            .query_map(&*params, row_to_announcement_action)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(actions)
+    }
+
+    fn get_series_announcements(&mut self, series: u64) -> Result<Vec<AnnouncementAction>, Error> {
+        let mut stmt = self.con.prepare(
+            "SELECT * FROM action JOIN announcement_action WHERE \
+             action.id IN ( \
+             SELECT max(id) FROM announcement_action GROUP BY aid \
+             ) AND \
+             action.id = announcement_action.id AND \
+             announcement_action.series = ? \
+             ORDER BY recurrence_id, action.id",
+        )?;
+        let actions = stmt
+            .query_map([&(series as i64)], row_to_announcement_action)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(actions)
+    }
+
+    fn insert_announcement_series(
+        &mut self,
+        series: &mut AnnouncementSeries,
+    ) -> Result<u64, Error> {
+        self.con.execute(
+            "INSERT INTO announcement_series \
+             (time, user, note, rrule, timezone, \"from\", duration, public, url, \
+             materialized_until, deleted, category, tags, description) \
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            params![
+                &series.time,
+                &series.user,
+                &series.note,
+                &series.rrule,
+                &series.timezone,
+                &series.from,
+                &series.duration,
+                &series.public,
+                &series.url,
+                &series.materialized_until,
+                &series.deleted,
+                &series.category,
+                &join_list(&series.tags),
+                &series.description,
+            ],
+        )?;
+        let id = self.con.last_insert_rowid() as u64;
+        series.id = Some(id);
+        Ok(id)
+    }
+
+    fn update_announcement_series(&mut self, series: &AnnouncementSeries) -> Result<(), Error> {
+        let updated = self.con.execute(
+            "UPDATE announcement_series SET \
+             time = ?, user = ?, note = ?, rrule = ?, timezone = ?, \"from\" = ?, duration = ?, \
+             public = ?, url = ?, materialized_until = ?, deleted = ?, category = ?, tags = ?, \
+             description = ? WHERE id = ?",
+            params![
+                &series.time,
+                &series.user,
+                &series.note,
+                &series.rrule,
+                &series.timezone,
+                &series.from,
+                &series.duration,
+                &series.public,
+                &series.url,
+                &series.materialized_until,
+                &series.deleted,
+                &series.category,
+                &join_list(&series.tags),
+                &series.description,
+                &(series.id.unwrap() as i64),
+            ],
+        )?;
+        match updated {
+            0 => Err(Error::NotFound),
+            _ => Ok(()),
+        }
+    }
+
+    fn get_announcement_series(&mut self, id: u64) -> Result<AnnouncementSeries, Error> {
+        Ok(self.con.query_row(
+            &format!("SELECT {ANNOUNCEMENT_SERIES_COLUMNS} FROM announcement_series WHERE id = ?"),
+            params![&(id as i64)],
+            row_to_announcement_series,
+        )?)
+    }
+
+    fn get_active_announcement_series(&mut self) -> Result<Vec<AnnouncementSeries>, Error> {
+        let mut stmt = self.con.prepare(&format!(
+            "SELECT {ANNOUNCEMENT_SERIES_COLUMNS} FROM announcement_series \
+             WHERE deleted = 0 ORDER BY id"
+        ))?;
+        let series = stmt
+            .query_map([], row_to_announcement_series)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(series)
+    }
+
+    /*
+     * RSVPs
+     */
+
+    fn insert_rsvp(&mut self, action: &mut RsvpAction) -> Result<u64, Error> {
+        let tx = self.con.savepoint()?;
+        tx.execute(
+            "INSERT INTO action (time, type, note) VALUES (?, ?, ?)",
+            params![&action.action.time, &4, &action.action.note],
+        )?;
+        let action_id = tx.last_insert_rowid() as u64;
+        tx.execute(
+            "INSERT INTO rsvp_action (id, aid, user, answer) VALUES (?, ?, ?, ?)",
+            params![
+                &(action_id as i64),
+                &(action.aid as i64),
+                &action.user,
+                &action.answer
+            ],
+        )?;
+        tx.commit()?;
+        action.action.id = Some(action_id);
+        Ok(action_id)
+    }
+
+    fn get_rsvp(&mut self, id: u64) -> Result<RsvpAction, Error> {
+        Ok(self.con.query_row(
+            "SELECT * FROM action JOIN rsvp_action WHERE action.type = 4 AND \
+             action.id = ? AND rsvp_action.id = ?",
+            params![&(id as i64), &(id as i64)],
+            row_to_rsvp_action,
+        )?)
+    }
+
+    fn get_rsvps(&mut self, aid: u64) -> Result<Vec<RsvpAction>, Error> {
+        let mut stmt = self.con.prepare(
+            "SELECT * FROM action JOIN rsvp_action ON action.id = rsvp_action.id \
+             WHERE rsvp_action.id IN \
+             (SELECT max(id) FROM rsvp_action WHERE aid = ? GROUP BY user) \
+             ORDER BY rsvp_action.user",
+        )?;
+        let rsvps = stmt
+            .query_map(params![&(aid as i64)], row_to_rsvp_action)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rsvps)
+    }
+
+    /*
+     * Reminders
+     */
+
+    fn insert_sent_reminder(
+        &mut self,
+        aid: u64,
+        reminder: &str,
+        from: i64,
+        time: i64,
+    ) -> Result<(), Error> {
+        self.con.execute(
+            "INSERT OR IGNORE INTO sent_reminder (aid, reminder, 'from', time) \
+             VALUES (?, ?, ?, ?)",
+            params![&(aid as i64), reminder, &from, &time],
+        )?;
+        Ok(())
+    }
+
+    fn reminder_sent(&mut self, aid: u64, reminder: &str, from: i64) -> Result<bool, Error> {
+        let count: i64 = self.con.query_row(
+            "SELECT count(*) FROM sent_reminder WHERE aid = ? AND reminder = ? AND \"from\" = ?",
+            params![&(aid as i64), reminder, &from],
+            |row| row.get(0),
+        )?;
+        Ok(count > 0)
+    }
+
+    /*
+     * Presence
+     */
+
+    fn insert_presence(&mut self, action: &mut PresenceAction) -> Result<u64, Error> {
+        let tx = self.con.savepoint()?;
+        tx.execute(
+            "INSERT INTO action (time, type, note) VALUES (?, ?, ?)",
+            params![&action.action.time, &2, &action.action.note],
+        )?;
+        let action_id = tx.last_insert_rowid() as u64;
+        for user in action.users.iter() {
+            match user.status {
+                PresentUserStatus::Joined | PresentUserStatus::Present => {
+                    tx.execute(
+                        "INSERT INTO presence_action (id, user, since) VALUES (?, ?, ?)",
+                        params![&(action_id as i64), &user.name.as_str(), &user.since],
+                    )?;
+                }
+                PresentUserStatus::Left => {}
+            }
+        }
+        tx.execute(
+            "INSERT INTO presence_anon_action (id, anonymous_users) VALUES (?, ?)",
+            params![&(action_id as i64), &(action.anonymous_users as f64)],
+        )?;
+        tx.commit()?;
+        action.action.id = Some(action_id);
+        Ok(action_id)
+    }
+
+    fn get_presence(&mut self, id: u64) -> Result<PresenceAction, Error> {
+        let action = self.con.query_row(
+            "SELECT * FROM action WHERE id = ? AND type = 2",
+            params![&(id as i64)],
+            row_to_base_action,
+        )?;
+        self.presence_by_base_action(action)
+    }
+
+    fn get_last_presence(&mut self) -> Result<PresenceAction, Error> {
+        let action = self.con.query_row(
+            "SELECT * FROM action WHERE type = 2 ORDER BY id DESC LIMIT 1",
+            params![],
+            row_to_base_action,
+        )?;
+        self.presence_by_base_action(action)
+    }
+
+    fn query(
+        &mut self,
+        type_: QueryActionType,
+        id: RangeExpr<IdExpr>,
+        time: RangeExpr<i64>,
+        count: u64,
+        take: Take,
+        filter: &AnnouncementFilter,
+    ) -> Result<Vec<TypedAction>, Error> {
+        let mut query_str = String::from("SELECT id, type FROM action WHERE");
+
+        // for livetime reasons we need to define these variables before params:
+        let id1;
+        let id2;
+        let time1;
+        let time2;
+        let count = count as i64;
+        let type_int: u8;
+
+        let mut params = Vec::<&dyn ToSql>::new();
+
+        match id {
+            RangeExpr::Single(IdExpr::Int(i)) => {
+                id1 = i as i64;
+                query_str.push_str(" id=?");
+                params.push(&id1);
+            }
+            RangeExpr::Single(IdExpr::Last) => {
+                query_str.push_str(" 1");
+            }
+            RangeExpr::Range(IdExpr::Int(i1), IdExpr::Int(i2)) => {
+                id1 = i1 as i64;
+                id2 = i2 as i64;
+                query_str.push_str(" id >= ? AND id <= ?");
+                params.push(&id1);
+                params.push(&id2);
+            }
+            RangeExpr::Range(IdExpr::Int(i1), IdExpr::Last) => {
+                id1 = i1 as i64;
+                query_str.push_str(" id >= ?");
+                params.push(&id1);
+            }
+            RangeExpr::Range(_, _) => {
+                panic!("this case should be unreachable");
+            }
+        }
+
+        match time {
+            RangeExpr::Single(t) => {
+                time1 = t;
+                time2 = t;
+            }
+            RangeExpr::Range(t1, t2) => {
+                time1 = t1;
+                time2 = t2;
+            }
+        };
+        query_str.push_str(" AND \"time\" >= ? AND \"time\" <= ?");
+        params.push(&time1);
+        params.push(&time2);
+
+        if type_ != QueryActionType::All {
+            type_int = match type_ {
+                QueryActionType::Status => 0,
+                QueryActionType::Announcement => 1,
+                QueryActionType::Presence => 2,
+                QueryActionType::Handover => 3,
+                QueryActionType::Rsvp => 4,
+                _ => panic!(), // impossible
+            };
+            query_str.push_str(" AND type=?");
+            params.push(&type_int);
+        }
+
+        // only announcements have a category and tags
+        if let Some(category) = &filter.category {
+            query_str.push_str(" AND id IN (SELECT id FROM announcement_action WHERE category=?)");
+            params.push(category);
+        }
+        if let Some(tag) = &filter.tag {
+            query_str.push_str(
+                " AND id IN (SELECT id FROM announcement_action \
+                 WHERE instr(',' || tags || ',', ',' || ? || ',') > 0)",
+            );
+            params.push(tag);
+        }
+
+        query_str.push_str(" ORDER BY id ");
+        query_str.push_str(match take {
+            Take::First => "ASC",
+            Take::Last => "DESC",
+        });
+        query_str.push_str(" LIMIT ?");
+        params.push(&count);
+
+        let ids = {
+            let mut stmt = self.con.prepare(&query_str[..])?;
+            stmt.query_map(&*params, |row| {
+                Ok((row.get::<_, i64>(0)? as u64, row.get::<_, i64>(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?
+        };
+        // FIXME: this looks very inefficient, we are doing a query _per returned action_!
+        let mut actions = ids
+            .into_iter()
+            .map(|(id, type_)| match type_ {
+                0 => Ok(TypedAction::Status(self.get_status(id)?)),
+                1 => Ok(TypedAction::Announcement(self.get_announcement(id)?)),
+                2 => Ok(TypedAction::Presence(self.get_presence(id)?)),
+                3 => Ok(TypedAction::Handover(self.get_handover(id)?)),
+                4 => Ok(TypedAction::Rsvp(self.get_rsvp(id)?)),
+                t => panic!("unknown action type in db: {}", t),
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        if take == Take::Last {
+            actions.reverse();
+        }
+        Ok(actions)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::db::{DbStored, ensure_initialized};
+    use clubstatus_types::Status;
+
+    crate::db::conformance::conformance_tests!(|| Box::new(
+        SqliteStorage::open_in_memory().unwrap()
+    ));
+
+    /// Databases created before the schema version was recorded, with the initial actions.
+    const BASELINE_SCHEMA: &str = "
+        CREATE TABLE action (id INTEGER PRIMARY KEY AUTOINCREMENT, time INTEGER NOT NULL,
+            type INTEGER NOT NULL, note TEXT NOT NULL);
+        CREATE TABLE status_action (id INTEGER PRIMARY KEY, user TEXT NOT NULL,
+            status INTEGER NOT NULL, changed INTEGER NOT NULL, public_changed INTEGER NOT NULL);
+        CREATE TABLE announcement_action (id INTEGER PRIMARY KEY, method INTEGER, aid INTEGER,
+            user TEXT NOT NULL, 'from' INTEGER, 'to' INTEGER, public INTEGER, url TEXT);
+        CREATE TABLE presence_action (id INTEGER, user TEXT NOT NULL, since INTEGER);
+        CREATE TABLE presence_anon_action (id INTEGER, anonymous_users FLOAT);
+        INSERT INTO action VALUES (1, 0, 0, 'initial state'), (2, 0, 2, 'initial state');
+        INSERT INTO status_action VALUES (1, 'Hans Acker', 0, 1, 1);
+        INSERT INTO presence_anon_action VALUES (2, 0.0);";
+
+    fn columns(storage: &SqliteStorage, table: &str) -> Vec<String> {
+        let mut stmt = storage
+            .con
+            .prepare("SELECT name FROM pragma_table_info(?)")
+            .unwrap();
+        stmt.query_map([table], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    fn baseline_storage() -> SqliteStorage {
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        storage.con.execute_batch(BASELINE_SCHEMA).unwrap();
+        storage
+    }
+
+    fn tables(storage: &SqliteStorage) -> Vec<String> {
+        let mut stmt = storage
+            .con
+            .prepare(
+                "SELECT name FROM sqlite_master \
+                 WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name",
+            )
+            .unwrap();
+        stmt.query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    /// The migrations have to arrive at the schema of a fresh database, with the columns in the
+    /// same order.
+    #[test]
+    fn upgrade_baseline() {
+        let mut storage = baseline_storage();
+        assert_eq!(storage.schema_version().unwrap(), 0);
+        ensure_initialized(&mut storage).unwrap();
+        assert_eq!(storage.schema_version().unwrap(), SCHEMA_VERSION);
+        assert_eq!(storage.get_last_presence().unwrap().action.id, Some(2));
+
+        let mut fresh = SqliteStorage::open_in_memory().unwrap();
+        ensure_initialized(&mut fresh).unwrap();
+        assert_eq!(tables(&storage), tables(&fresh));
+        for table in tables(&fresh) {
+            assert_eq!(
+                columns(&storage, &table),
+                columns(&fresh, &table),
+                "{table}"
+            );
+        }
+    }
+
+    /// Upgraded databases have to behave like fresh ones.
+    mod upgraded {
+        use super::*;
+
+        crate::db::conformance::conformance_tests!(
+            || Box::new(baseline_storage());
+            migrations_rerun,
+            status_changed_flags,
+            public_status_changes,
+            status_get_by_id,
+            status_revert,
+            scheduled_status,
+            keyholder_handover,
+            zone_status,
+            announcement_lifecycle,
+            announcement_current,
+            announcement_window,
+            announcement_series,
+            announcement_categories,
+            announcement_resources,
+            sent_reminders,
+            rsvp,
+            presence_roundtrip,
+            query_filters,
+        );
+    }
+
+    /// A second instance using the same database file has to wait until the first one has
+    /// stored its action.
+    #[test]
+    fn write_lock_between_instances() {
+        let path = std::env::temp_dir().join(format!(
+            "clubstatusd-write-lock-{}.sqlite",
+            std::process::id()
+        ));
+        let path = path.to_str().unwrap();
+        let mut first = SqliteStorage::open(path).unwrap();
+        ensure_initialized(&mut first).unwrap();
+        let mut second = SqliteStorage::open(path).unwrap();
+
+        first.lock_writes().unwrap();
+        let waiting = thread::spawn(move || {
+            second.lock_writes().unwrap();
+            let last = second.get_last_status().unwrap();
+            second.unlock_writes().unwrap();
+            last
+        });
+        thread::sleep(Duration::from_millis(200));
+        let mut public = StatusAction::new(
+            String::new(),
+            1,
+            UserName::new("Hans Acker".into()),
+            Status::Public,
+        );
+        public.store(&mut first, None).unwrap();
+        first.unlock_writes().unwrap();
+        assert_eq!(waiting.join().unwrap().status, Status::Public);
+        std::fs::remove_file(path).unwrap();
+    }
+}
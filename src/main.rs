@@ -2,6 +2,7 @@
 extern crate rocket;
 
 mod api;
+mod clock;
 mod db;
 mod model;
 mod util;
@@ -45,16 +46,24 @@ async fn rocket() -> _ {
         }
     };
 
-    let db_path_str = conf
-        .get_string("database_path")
-        .unwrap_or_else(|_| String::from("/var/local/clubstatusd/db.sqlite"));
-    let con = match db::connect(db_path_str.as_str()) {
+    let con = match conf.get_string("database_url") {
+        Ok(url) => connect_database_url(url.as_str()),
+        Err(_) => {
+            let db_path_str = conf
+                .get_string("database_path")
+                .unwrap_or_else(|_| String::from("/var/local/clubstatusd/db.sqlite"));
+            db::connect(db_path_str.as_str()).map_err(|err| {
+                format!(
+                    "Could not open database (path: {}), error message:\n{:?}",
+                    db_path_str, err
+                )
+            })
+        }
+    };
+    let con = match con {
         Ok(con) => con,
-        Err(err) => {
-            eprintln!(
-                "Could not open database (path: {}), error message:\n{:?}",
-                db_path_str, err
-            );
+        Err(msg) => {
+            eprintln!("{}", msg);
             std::process::exit(1);
         }
     };
@@ -80,30 +89,317 @@ async fn rocket() -> _ {
 
     let shared_con = Arc::new(Mutex::new(con));
 
+    let zones: Vec<String> = conf.get("zones").unwrap_or_default();
+    // zone names are used in MQTT topics
+    if let Some(zone) = zones
+        .iter()
+        .find(|z| z.is_empty() || z.contains(['/', '+', '#']))
+    {
+        eprintln!("Invalid zone name \"{zone}\", it can't be empty or contain '/', '+' or '#'.");
+        std::process::exit(1);
+    }
+
+    let announcement_categories: Vec<String> =
+        conf.get("announcement_categories").unwrap_or_default();
+    if announcement_categories.iter().any(String::is_empty) {
+        eprintln!("Invalid announcement category \"\", categories can't be empty.");
+        std::process::exit(1);
+    }
+
+    let announcement_limit = conf.get_int("announcement_limit").unwrap_or(30);
+    if announcement_limit < 0 {
+        eprintln!("Invalid announcement_limit {announcement_limit}, it can't be negative.");
+        std::process::exit(1);
+    }
+
+    let status_history_range = conf.get_int("status_history_range").unwrap_or(90 * 86400);
+    if status_history_range < 0 {
+        eprintln!("Invalid status_history_range {status_history_range}, it can't be negative.");
+        std::process::exit(1);
+    }
+
+    let resources: Vec<api::Resource> = conf.get("resources").unwrap_or_else(|err| match err {
+        ConfigError::NotFound(_) => Vec::new(),
+        err => {
+            eprintln!("Error reading resources: {err}");
+            std::process::exit(1);
+        }
+    });
+    // resource names are used in URLs
+    if let Some(resource) = resources
+        .iter()
+        .find(|r| r.name.is_empty() || r.name.contains(['/', ',']))
+    {
+        eprintln!(
+            "Invalid resource name \"{}\", it can't be empty or contain '/' or ','.",
+            resource.name
+        );
+        std::process::exit(1);
+    }
+
+    let clock: clock::SharedClock = Arc::new(clock::SystemClock);
+
+    let door = conf.get_table("door").ok().map(|_| {
+        let rules: Vec<db::door::DoorRule> = conf.get("door.rules").unwrap_or_else(|err| {
+            eprintln!("Error reading door.rules: {err}");
+            std::process::exit(1);
+        });
+        if rules.iter().any(|rule| rule.conditions.is_empty()) {
+            eprintln!("Every rule in door.rules needs at least one condition");
+            std::process::exit(1);
+        }
+        let config = db::door::DoorConfig {
+            user: clubstatus_types::UserName::new(
+                conf.get_string("door.user")
+                    .unwrap_or_else(|_| String::from("door")),
+            ),
+            debounce: conf.get_int("door.debounce").unwrap_or(5),
+            manual_override: conf.get_int("door.manual_override").unwrap_or(1800),
+            rules,
+        };
+        Arc::new(Mutex::new(db::door::Door::new(config)))
+    });
+
     let mqtt_server = conf.get_string("mqtt.server").ok();
     let port = conf.get_int("mqtt.port").unwrap_or(1883) as u16;
     let mqtt_topic_prefix = conf
         .get_string("mqtt.topic_prefix")
         .unwrap_or_else(|_| String::from(""));
-    let mqtt_handler =
-        api::mqtt::start_handler(mqtt_server, port, mqtt_topic_prefix, shared_con.clone());
+    if door.is_some() && mqtt_server.is_none() {
+        eprintln!("door is configured, but mqtt.server is not set, the door sensor is ignored.");
+    }
+    let mqtt_handler = api::mqtt::start_handler(
+        mqtt_server,
+        port,
+        mqtt_topic_prefix,
+        shared_con.clone(),
+        zones.clone(),
+        door.clone(),
+        clock.clone(),
+    );
+    if let Some(mqtt) = &mqtt_handler {
+        let reminders: Vec<i64> = conf.get("mqtt.announcement_reminders").unwrap_or_default();
+        db::announcement_events::start(
+            shared_con.clone(),
+            mqtt,
+            clock.clone(),
+            db::announcement_events::AnnouncementEvents::new(reminders),
+        );
+    }
+    if let Some(door) = door {
+        db::door::start(
+            shared_con.clone(),
+            mqtt_handler.as_ref(),
+            clock.clone(),
+            door,
+        );
+    }
+
+    let spaceapi_file = conf.get_string("spaceapi_file").ok().map(Utf8PathBuf::from);
+    let spaceapi_static = match (conf.get::<serde_json::Value>("spaceapi"), &spaceapi_file) {
+        (Ok(_), Some(_)) => {
+            eprintln!("Only one of spaceapi and spaceapi_file can be set");
+            std::process::exit(1);
+        }
+        (Ok(serde_json::Value::String(s)), None) => Some(api::spaceapi_schema::parse(s.as_str())),
+        (Ok(value), None) => Some(api::spaceapi_schema::from_value(value)),
+        (Err(ConfigError::NotFound(_)), Some(path)) => Some(api::spaceapi_file::load(path)),
+        (Err(ConfigError::NotFound(_)), None) => None,
+        (Err(err), _) => Some(Err(err.to_string())),
+    }
+    .map(|s| {
+        s.unwrap_or_else(|err| {
+            eprintln!("Error reading spaceapi: {err}");
+            std::process::exit(1);
+        })
+    });
+
+    let keyholder_check = match conf.get_string("keyholder.close") {
+        Ok(s) if s == "refuse" => api::KeyholderCheck::Refuse,
+        Ok(s) if s == "warn" => api::KeyholderCheck::Warn,
+        Err(ConfigError::NotFound(_)) => api::KeyholderCheck::default(),
+        Ok(s) => {
+            eprintln!("keyholder.close has to be either \"warn\" or \"refuse\", not \"{s}\".");
+            std::process::exit(1);
+        }
+        Err(e) => {
+            dbg!(e);
+            panic!();
+        }
+    };
+
+    let spaceapi_presence = api::SpaceapiPresence {
+        count: conf.get_bool("spaceapi_presence.count").unwrap_or(false),
+        public_names: conf
+            .get::<Vec<String>>("spaceapi_presence.public_names")
+            .unwrap_or_default()
+            .into_iter()
+            .map(clubstatus_types::UserName::new)
+            .collect(),
+    };
+    if !spaceapi_presence.count && !spaceapi_presence.public_names.is_empty() {
+        eprintln!("Warning: spaceapi_presence.public_names are only shown with count = true.");
+    }
+
+    let spaceapi_events = conf.get_table("spaceapi_events").ok().map(|_| {
+        let window = conf.get_int("spaceapi_events.window").unwrap_or(7 * 86400);
+        if window < 0 {
+            eprintln!("Invalid spaceapi_events.window {window}, it can't be negative.");
+            std::process::exit(1);
+        }
+        let count = conf.get_int("spaceapi_events.count").unwrap_or(10);
+        if count < 0 {
+            eprintln!("Invalid spaceapi_events.count {count}, it can't be negative.");
+            std::process::exit(1);
+        }
+        api::SpaceapiEvents {
+            window,
+            count: count as u64,
+        }
+    });
+
+    if let Ok(grace_period) = conf.get_int("auto_close.grace_period") {
+        let auto_close = db::auto_close::AutoClose::new(
+            grace_period,
+            conf.get_bool("auto_close.dry_run").unwrap_or(false),
+        );
+        db::auto_close::start(
+            shared_con.clone(),
+            mqtt_handler.as_ref(),
+            clock.clone(),
+            auto_close,
+        );
+    }
+
+    if conf.get_table("reminders").is_ok() {
+        let security = match conf.get_string("reminders.security") {
+            Ok(s) if s == "none" => db::reminders::SmtpSecurity::None,
+            Ok(s) if s == "starttls" => db::reminders::SmtpSecurity::StartTls,
+            Ok(s) if s == "tls" => db::reminders::SmtpSecurity::Tls,
+            Err(ConfigError::NotFound(_)) => db::reminders::SmtpSecurity::StartTls,
+            Ok(s) => {
+                eprintln!(
+                    "reminders.security has to be \"none\", \"starttls\" or \"tls\", not \"{s}\"."
+                );
+                std::process::exit(1);
+            }
+            Err(e) => {
+                dbg!(e);
+                panic!();
+            }
+        };
+        let required = |key: &str| {
+            conf.get_string(key).unwrap_or_else(|err| {
+                eprintln!("Error reading {key}: {err}");
+                std::process::exit(1);
+            })
+        };
+        let smtp = db::reminders::SmtpConfig {
+            server: required("reminders.server"),
+            port: conf.get_int("reminders.port").ok().map(|p| p as u16),
+            security,
+            credentials: conf
+                .get_string("reminders.user")
+                .ok()
+                .map(|user| (user, required("reminders.password"))),
+        };
+        let rules = conf.get("reminders.rules").unwrap_or_else(|err| {
+            eprintln!("Error reading reminders.rules: {err}");
+            std::process::exit(1);
+        });
+        let reminders = db::reminders::Reminders::new(
+            smtp,
+            &required("reminders.from"),
+            &conf
+                .get_string("reminders.timezone")
+                .unwrap_or_else(|_| String::from("UTC")),
+            rules,
+        )
+        .unwrap_or_else(|err| {
+            eprintln!("Error reading reminders.{err}");
+            std::process::exit(1);
+        });
+        db::reminders::start(shared_con.clone(), clock.clone(), reminders);
+    }
 
-    let spaceapi_static = conf
-        .get_string("spaceapi")
-        .ok()
-        .map(|s| serde_json::from_str(s.as_str()).expect("Cannot parse spaceapi json."));
+    let alerts = conf.get_table("alerts").ok().map(|_| {
+        let default = db::alerts::AlertRules::default();
+        let rules = db::alerts::AlertRules {
+            open_empty_after: conf
+                .get_int("alerts.open_empty_after")
+                .unwrap_or(default.open_empty_after),
+            closed_occupied_people: conf
+                .get_float("alerts.closed_occupied_people")
+                .map(|p| p as f32)
+                .unwrap_or(default.closed_occupied_people),
+            closed_occupied_after: conf
+                .get_int("alerts.closed_occupied_after")
+                .unwrap_or(default.closed_occupied_after),
+        };
+        let alerts = Arc::new(Mutex::new(db::alerts::Alerts::new(rules)));
+        db::alerts::start(
+            shared_con.clone(),
+            mqtt_handler.as_ref(),
+            clock.clone(),
+            alerts.clone(),
+        );
+        alerts
+    });
 
     let listen_addr = conf
         .get_string("listen")
         .unwrap_or_else(|_| String::from("localhost:8000"));
-    api::run(
+    let settings = api::Settings {
+        spaceapi_static,
+        spaceapi_file: spaceapi_file.map(api::spaceapi_file::SpaceapiFile::new),
+        keyholder_check,
+        zones,
+        announcement_categories,
+        // 0 means no limit
+        announcement_limit: Some(announcement_limit as u64).filter(|&limit| limit > 0),
+        // 0 means the whole history
+        status_history_range: Some(status_history_range).filter(|&range| range > 0),
+        resources,
+        spaceapi_presence,
+        spaceapi_events,
+    };
+    let spaceapi_check = {
+        let mut con = shared_con.lock().unwrap();
+        api::check_spaceapi(&mut **con, &settings, clock.now())
+    };
+    if let Err(errors) = spaceapi_check {
+        eprintln!("The SpaceAPI output does not match the SpaceAPI schema, check spaceapi:");
+        for error in errors {
+            eprintln!("  {error}");
+        }
+        std::process::exit(1);
+    }
+    let rocket = api::run(
         shared_con,
         listen_addr.as_str(),
         password,
         cookie_salt,
         mqtt_handler,
-        spaceapi_static,
-    )
+        settings,
+        clock,
+    );
+    match alerts {
+        Some(alerts) => api::mount_alerts(rocket, alerts),
+        None => rocket,
+    }
+}
+
+#[cfg(feature = "postgres")]
+fn connect_database_url(url: &str) -> Result<db::DbCon, String> {
+    db::connect_postgres(url)
+        .map_err(|err| format!("Could not connect to database, error message:\n{:?}", err))
+}
+
+#[cfg(not(feature = "postgres"))]
+fn connect_database_url(_url: &str) -> Result<db::DbCon, String> {
+    Err(String::from(
+        "database_url is set, but clubstatusd was built without the postgres feature.",
+    ))
 }
 
 fn hex_str_to_salt(s: &str) -> Salt {
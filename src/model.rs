@@ -1,13 +1,12 @@
 use rocket::request::FromParam;
 
-use crate::db::DbStored;
-use clubstatus_types::*;
-
 #[derive(Clone, Debug, PartialEq)]
 pub enum QueryActionType {
     Status,
     Announcement,
     Presence,
+    Handover,
+    Rsvp,
     All,
 }
 impl<'a> FromParam<'a> for QueryActionType {
@@ -17,27 +16,12 @@ impl<'a> FromParam<'a> for QueryActionType {
             "status" => Ok(QueryActionType::Status),
             "announcement" => Ok(QueryActionType::Announcement),
             "presence" => Ok(QueryActionType::Presence),
+            "handover" => Ok(QueryActionType::Handover),
+            "rsvp" => Ok(QueryActionType::Rsvp),
             "all" => Ok(QueryActionType::All),
-            _ => Err("action type must one of  status | announcement | presence | all"),
+            _ => Err(
+                "action type must one of  status | announcement | presence | handover | rsvp | all",
+            ),
         }
     }
 }
-
-pub trait Action: DbStored {
-    fn get_base_action(&self) -> &BaseAction;
-}
-impl Action for StatusAction {
-    fn get_base_action(&self) -> &BaseAction {
-        &self.action
-    }
-}
-impl Action for AnnouncementAction {
-    fn get_base_action(&self) -> &BaseAction {
-        &self.action
-    }
-}
-impl Action for PresenceAction {
-    fn get_base_action(&self) -> &BaseAction {
-        &self.action
-    }
-}